@@ -1,20 +1,108 @@
-use crossterm::event::KeyEvent;
+use std::time::Duration;
+
+use crossterm::event::{self, KeyEvent};
+use model::{ChatMessage, UserAction};
 use ratatui::Frame;
 
-use crate::client::TaskSpawner;
+use crate::client::{RawTask, TaskSpawner};
+use crate::{ui, State};
 
-use super::AppState;
+use super::{App, AppState};
 
+/// `App` state once the user has logged in.
 pub struct Connected {
     pub username: String,
+    pub state: State<'static>,
+}
+
+impl Connected {
+    pub fn new(username: String) -> Self {
+        Connected {
+            state: State::new(username.clone()),
+            username,
+        }
+    }
 }
 
 impl AppState for Connected {
     fn render(&self, f: &mut Frame) {
-        todo!()
+        ui::ui(f, &self.state);
     }
 
     fn input(&mut self, key: KeyEvent, spawner: &mut TaskSpawner) -> bool {
-        todo!()
+        match key.code {
+            event::KeyCode::Esc => {
+                return true;
+            }
+            event::KeyCode::F(1) => {
+                self.state.show_debug = !self.state.show_debug;
+            }
+            // with nothing typed, the arrow keys and enter pick a dialogue choice
+            event::KeyCode::Up | event::KeyCode::Down | event::KeyCode::Enter
+                if self.state.dialogue.is_some() && self.state.textarea.is_empty() =>
+            {
+                let dialogue = self.state.dialogue.as_mut().unwrap();
+                let count = dialogue.view.choices.len();
+                match key.code {
+                    event::KeyCode::Up => {
+                        dialogue.selected = (dialogue.selected + count - 1) % count
+                    }
+                    event::KeyCode::Down => dialogue.selected = (dialogue.selected + 1) % count,
+                    _ => spawner.spawn_task(RawTask {
+                        req: UserAction::Command((dialogue.selected + 1).to_string()),
+                    }),
+                }
+            }
+            event::KeyCode::Enter => {
+                let line = self.state.textarea.lines()[0].trim().to_string();
+                if line.is_empty() {
+                    return false;
+                }
+
+                // `/command args` is a game command, anything else is said in the current room
+                let req = match line.strip_prefix('/') {
+                    Some(command) => UserAction::Command(command.to_string()),
+                    None => match &self.state.current_tab {
+                        Some(room_name) => UserAction::Chat(ChatMessage::Public {
+                            room_name: room_name.clone(),
+                            from: self.username.clone(),
+                            msg: line,
+                        }),
+                        None => return false,
+                    },
+                };
+                spawner.spawn_task(RawTask { req });
+
+                self.state
+                    .textarea
+                    .move_cursor(tui_textarea::CursorMove::End);
+                self.state.textarea.delete_line_by_head();
+            }
+            _ => {
+                self.state.textarea.input(key);
+            }
+        }
+
+        false
+    }
+}
+
+impl App<Connected> {
+    pub fn run(&mut self) {
+        loop {
+            if let Ok(true) = event::poll(Duration::from_millis(50)) {
+                if let event::Event::Key(key) = event::read().unwrap() {
+                    if self.state.input(key, &mut self.spawner) {
+                        break;
+                    }
+                }
+            }
+
+            while let Ok(res) = self.rx.try_recv() {
+                self.state.state.handle_response(res);
+            }
+
+            self.terminal.draw(|f| self.state.render(f)).unwrap();
+        }
     }
 }
@@ -0,0 +1,186 @@
+use std::time::Duration;
+
+use crossterm::event::{self, KeyEvent};
+use model::{CharacterOption, Response, ServerResponse, UserAction};
+use ratatui::prelude::*;
+use ratatui::widgets::{Block, Borders, List, ListItem, ListState, Paragraph, Wrap};
+
+use crate::client::{RawTask, TaskSpawner};
+
+use super::{connected::Connected, App, AppState};
+
+/// `App` state when a new user is choosing a race and class.
+pub struct Creating {
+    races: Vec<CharacterOption>,
+    classes: Vec<CharacterOption>,
+    race: ListState,
+    class: ListState,
+    choosing_class: bool,
+    error: Option<String>,
+}
+
+impl Creating {
+    pub fn new(races: Vec<CharacterOption>, classes: Vec<CharacterOption>) -> Self {
+        Creating {
+            races,
+            classes,
+            race: ListState::default().with_selected(Some(0)),
+            class: ListState::default().with_selected(Some(0)),
+            choosing_class: false,
+            error: None,
+        }
+    }
+
+    fn active(&mut self) -> (&mut ListState, usize) {
+        if self.choosing_class {
+            (&mut self.class, self.classes.len())
+        } else {
+            (&mut self.race, self.races.len())
+        }
+    }
+
+    fn highlighted(&self) -> Option<&CharacterOption> {
+        if self.choosing_class {
+            self.classes.get(self.class.selected()?)
+        } else {
+            self.races.get(self.race.selected()?)
+        }
+    }
+}
+
+fn option_list<'a>(title: &'a str, options: &'a [CharacterOption], active: bool) -> List<'a> {
+    let border_style = if active {
+        Style::new().yellow()
+    } else {
+        Style::new()
+    };
+
+    List::new(
+        options
+            .iter()
+            .map(|option| ListItem::new(option.name.as_str()))
+            .collect::<Vec<_>>(),
+    )
+    .block(
+        Block::default()
+            .title(title)
+            .borders(Borders::ALL)
+            .border_style(border_style),
+    )
+    .highlight_symbol("> ")
+    .highlight_style(Style::new().bold())
+}
+
+impl AppState for Creating {
+    fn render(&self, f: &mut Frame) {
+        let chunks = Layout::default()
+            .constraints([Constraint::Max(1), Constraint::Min(5), Constraint::Max(5)])
+            .margin(2)
+            .split(f.size());
+        let lists = Layout::default()
+            .direction(Direction::Horizontal)
+            .constraints([Constraint::Percentage(50), Constraint::Percentage(50)])
+            .split(chunks[1]);
+
+        let header = match &self.error {
+            Some(reason) => Line::styled(reason.as_str(), Style::new().red()),
+            None => Line::from("Create your character: Up/Down to choose, Enter to confirm"),
+        };
+        f.render_widget(Paragraph::new(header), chunks[0]);
+
+        f.render_stateful_widget(
+            option_list("Race", &self.races, !self.choosing_class),
+            lists[0],
+            &mut self.race.clone(),
+        );
+        f.render_stateful_widget(
+            option_list("Class", &self.classes, self.choosing_class),
+            lists[1],
+            &mut self.class.clone(),
+        );
+
+        let description = self.highlighted().map_or("", |o| o.description.as_str());
+        f.render_widget(
+            Paragraph::new(description)
+                .wrap(Wrap { trim: true })
+                .block(Block::default().borders(Borders::ALL)),
+            chunks[2],
+        );
+    }
+
+    fn input(&mut self, key: KeyEvent, spawner: &mut TaskSpawner) -> bool {
+        match key.code {
+            event::KeyCode::Up => {
+                let (list, len) = self.active();
+                let selected = list.selected().unwrap_or(0);
+                list.select(Some((selected + len - 1) % len.max(1)));
+            }
+            event::KeyCode::Down => {
+                let (list, len) = self.active();
+                let selected = list.selected().unwrap_or(0);
+                list.select(Some((selected + 1) % len.max(1)));
+            }
+            event::KeyCode::Esc | event::KeyCode::Left if self.choosing_class => {
+                self.choosing_class = false;
+            }
+            event::KeyCode::Esc => {
+                return true;
+            }
+            event::KeyCode::Enter | event::KeyCode::Right if !self.choosing_class => {
+                self.choosing_class = true;
+            }
+            event::KeyCode::Enter => {
+                let race = self.race.selected().and_then(|i| self.races.get(i));
+                let class = self.class.selected().and_then(|i| self.classes.get(i));
+                if let (Some(race), Some(class)) = (race, class) {
+                    spawner.spawn_task(RawTask {
+                        req: UserAction::CreateCharacter {
+                            race: race.id.clone(),
+                            class: class.id.clone(),
+                        },
+                    });
+                }
+            }
+            _ => {}
+        }
+
+        false
+    }
+}
+
+impl From<App<Creating>> for App<Connected> {
+    fn from(mut value: App<Creating>) -> Self {
+        let mut got_username = None;
+        loop {
+            if let Ok(true) = event::poll(Duration::from_millis(100)) {
+                if let event::Event::Key(key) = event::read().unwrap() {
+                    if value.state.input(key, &mut value.spawner) {
+                        // user has quit
+                        break;
+                    }
+                }
+            }
+
+            match value.rx.try_recv() {
+                Ok(Response::Server(ServerResponse::JoinedServer { username })) => {
+                    got_username = Some(username);
+                    break;
+                }
+                Ok(Response::Server(ServerResponse::LoginFailed { reason })) => {
+                    value.state.error = Some(reason);
+                    value.state.choosing_class = false;
+                }
+                _ => {}
+            }
+
+            value.terminal.draw(|f| value.state.render(f)).unwrap();
+        }
+
+        App {
+            state: Connected::new(got_username.unwrap()),
+            spawner: value.spawner,
+            rx: value.rx,
+            terminal: value.terminal,
+        }
+    }
+}
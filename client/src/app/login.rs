@@ -5,7 +5,7 @@ use crate::{
     CrosstermTerminal,
 };
 
-use super::{connected::Connected, App, AppState};
+use super::{connected::Connected, creating::Creating, App, AppState};
 use crossterm::event::{self, KeyEvent};
 use ratatui::widgets::Block;
 use ratatui::{prelude::*, widgets::Borders};
@@ -16,6 +16,7 @@ use tui_textarea::{CursorMove, TextArea};
 /// `App` state when user is logging into server.
 pub struct Login<'a> {
     text_field: TextArea<'a>,
+    error: Option<String>,
 }
 
 impl<'a> App<Login<'a>> {
@@ -24,7 +25,10 @@ impl<'a> App<Login<'a>> {
         let mut text_field = TextArea::default();
         text_field.set_block(Block::default().borders(Borders::ALL));
         App {
-            state: Login { text_field },
+            state: Login {
+                text_field,
+                error: None,
+            },
             spawner,
             rx,
             terminal,
@@ -43,7 +47,13 @@ impl AppState for Login<'_> {
             .horizontal_margin(2)
             .split(f.size());
 
-        f.render_widget(self.text_field.widget(), chunks[1]);
+        let mut text_field = self.text_field.clone();
+        let title = match &self.error {
+            Some(reason) => Line::styled(reason.as_str(), Style::new().red()),
+            None => Line::from("Username"),
+        };
+        text_field.set_block(Block::default().borders(Borders::ALL).title(title));
+        f.render_widget(text_field.widget(), chunks[1]);
     }
 
     fn input(&mut self, key: KeyEvent, spawner: &mut TaskSpawner) -> bool {
@@ -79,23 +89,33 @@ impl<'a> From<App<Login<'a>>> for App<Connected> {
         let mut got_username = None;
         loop {
             // process input
-            match event::poll(Duration::from_millis(100)) {
-                Ok(true) => {
-                    if let event::Event::Key(key) = event::read().unwrap() {
-                        if value.state.input(key, &mut value.spawner) {
-                            // user has quit
-                            break;
-                        }
+            if let Ok(true) = event::poll(Duration::from_millis(100)) {
+                if let event::Event::Key(key) = event::read().unwrap() {
+                    if value.state.input(key, &mut value.spawner) {
+                        // user has quit
+                        break;
                     }
                 }
-                _ => {}
             }
 
-            if let Ok(res) = value.rx.try_recv() {
-                if let Response::Server(ServerResponse::JoinedServer { username }) = res {
+            match value.rx.try_recv() {
+                Ok(Response::Server(ServerResponse::JoinedServer { username })) => {
                     got_username = Some(username);
                     break;
                 }
+                Ok(Response::Server(ServerResponse::LoginFailed { reason })) => {
+                    value.state.error = Some(reason);
+                }
+                Ok(Response::Server(ServerResponse::ChooseCharacter { races, classes })) => {
+                    // new player, pick a race and class before joining
+                    return App::<Connected>::from(App {
+                        state: Creating::new(races, classes),
+                        spawner: value.spawner,
+                        rx: value.rx,
+                        terminal: value.terminal,
+                    });
+                }
+                _ => {}
             }
 
             // render
@@ -103,9 +123,7 @@ impl<'a> From<App<Login<'a>>> for App<Connected> {
         }
 
         App {
-            state: Connected {
-                username: got_username.unwrap(),
-            },
+            state: Connected::new(got_username.unwrap()),
             spawner: value.spawner,
             rx: value.rx,
             terminal: value.terminal,
@@ -1,4 +1,5 @@
 pub mod connected;
+pub mod creating;
 pub mod login;
 
 use crossterm::event::KeyEvent;
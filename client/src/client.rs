@@ -1,10 +1,4 @@
-use std::sync::Arc;
-
-use tokio::{
-    io::{AsyncReadExt, AsyncWriteExt, BufReader},
-    net::{tcp::OwnedWriteHalf, TcpStream},
-    sync::{mpsc, Mutex},
-};
+use tokio::{net::TcpStream, sync::mpsc};
 
 use futures::SinkExt;
 use tokio_stream::StreamExt;
@@ -20,13 +14,6 @@ pub struct TaskSpawner {
     send: mpsc::Sender<RawTask>,
 }
 
-async fn handle_task_raw(socket: Arc<Mutex<OwnedWriteHalf>>, task: RawTask) {
-    let mut socket = socket.lock().await;
-    let req_bytes: Vec<u8> = task.req.into();
-    socket.write_all(&req_bytes).await.unwrap();
-    socket.flush().await.unwrap();
-}
-
 impl TaskSpawner {
     pub fn new() -> (TaskSpawner, mpsc::UnboundedReceiver<model::Response>) {
         let (send, mut recv) = mpsc::channel::<RawTask>(100);
@@ -52,14 +39,11 @@ impl TaskSpawner {
                 loop {
                     tokio::select! {
                         res = transport.next() => {
-                            match res {
-                                Some(Ok(msg)) => {
-                                    let res: Response = bincode::deserialize(&msg[..]).unwrap();
-                                    tx.send(res).unwrap();
-                                },
-                                _ => {}
-}
+                            if let Some(Ok(msg)) = res {
+                                let res: Response = bincode::deserialize(&msg[..]).unwrap();
+                                tx.send(res).unwrap();
                             }
+                        }
                         task = recv.recv() => {
                             if let Some(task) = task {
                                 let req_bytes: Vec<u8> = task.req.into();
@@ -3,10 +3,13 @@ mod client;
 mod ui;
 
 use app::{connected::Connected, App};
-use model::{ChatMessage, UserAction};
+use model::{
+    ChatMessage, CombatEvent, DialogueView, GameUpdate, InventoryView, MapView, PartyView,
+    QuestStatus, QuestView, SkillsView, SpeechKind, TradeView, Vitals,
+};
 use ratatui::prelude::*;
 use ratatui::Terminal;
-use std::collections::HashMap;
+use std::collections::{BTreeMap, HashMap};
 use std::io::Stdout;
 
 use crossterm::{
@@ -15,7 +18,7 @@ use crossterm::{
     terminal::{disable_raw_mode, enable_raw_mode, EnterAlternateScreen, LeaveAlternateScreen},
 };
 
-use client::{RawTask, TaskSpawner};
+use client::TaskSpawner;
 
 use chrono::{DateTime, Local};
 
@@ -29,7 +32,17 @@ pub struct ServerMessage {
 pub enum MessageType {
     Public { msg: String, from: String },
     Private { msg: String, from: String },
+    Party { msg: String, from: String },
+    Speech { kind: SpeechKind, msg: String },
     Server(String),
+    Game(String),
+    Combat(CombatEvent),
+}
+
+/// An open conversation with an NPC and the choice currently highlighted.
+pub struct Dialogue {
+    view: DialogueView,
+    selected: usize,
 }
 
 pub struct UserData {
@@ -43,15 +56,73 @@ pub struct State<'a> {
     show_debug: bool,
     user_data: Option<UserData>,
     current_tab: Option<String>,
+    inventory: Option<InventoryView>,
+    vitals: Option<Vitals>,
+    /// Quests being worked on, by id.
+    quests: BTreeMap<String, QuestView>,
+    dialogue: Option<Dialogue>,
+    trade: Option<TradeView>,
+    party: Option<PartyView>,
+    skills: SkillsView,
+    map: Option<MapView>,
 }
 
 impl State<'_> {
+    pub fn new(username: String) -> Self {
+        let mut textarea = tui_textarea::TextArea::default();
+        textarea
+            .set_block(ratatui::widgets::Block::default().borders(ratatui::widgets::Borders::ALL));
+
+        State {
+            textarea,
+            room_messages: HashMap::new(),
+            debug_messages: vec![],
+            show_debug: false,
+            user_data: Some(UserData { username }),
+            current_tab: None,
+            inventory: None,
+            vitals: None,
+            quests: BTreeMap::new(),
+            dialogue: None,
+            trade: None,
+            party: None,
+            skills: SkillsView::default(),
+            map: None,
+        }
+    }
+
+    /// Adds a message to the log of the currently open room.
+    fn push_message(&mut self, ty: MessageType) {
+        let Some(room_log) = self
+            .current_tab
+            .as_ref()
+            .and_then(|room_name| self.room_messages.get_mut(room_name))
+        else {
+            self.debug_messages
+                .push("Got a message before joining a room".to_string());
+            return;
+        };
+
+        room_log.push(ServerMessage {
+            ty,
+            timestamp: Local::now(),
+        });
+    }
+
     pub fn handle_response(&mut self, res: model::Response) {
         let timestamp = Local::now();
 
         match res {
             model::Response::Chat(chat) => match chat {
-                ChatMessage::Private { from, msg } => todo!(),
+                ChatMessage::Private { from, msg } => {
+                    self.push_message(MessageType::Private { msg, from })
+                }
+                ChatMessage::Party { from, msg } => {
+                    self.push_message(MessageType::Party { msg, from })
+                }
+                ChatMessage::Speech { kind, msg } => {
+                    self.push_message(MessageType::Speech { kind, msg })
+                }
                 ChatMessage::Public {
                     room_name,
                     from,
@@ -69,7 +140,7 @@ impl State<'_> {
                 }
                 ChatMessage::Username(_) => todo!(),
             },
-            model::Response::Game(_) => todo!(),
+            model::Response::Game(update) => self.handle_game_update(update),
             model::Response::Server(res) => self.handle_server_response(res),
         }
     }
@@ -92,7 +163,9 @@ impl State<'_> {
                     None => self.current_tab = Some(room_name.clone()),
                 }
             }
-            model::ServerResponse::OtherUserJoined { name } => todo!(),
+            model::ServerResponse::OtherUserJoined { name } => {
+                self.push_message(MessageType::Server(format!("{name} joined.")))
+            }
             model::ServerResponse::General { room_name, msg } => {
                 let room_buffer = self.room_messages.get_mut(&room_name).unwrap();
                 room_buffer.push(ServerMessage {
@@ -100,6 +173,49 @@ impl State<'_> {
                     timestamp: Local::now(),
                 });
             }
+            model::ServerResponse::LoginFailed { .. }
+            | model::ServerResponse::ChooseCharacter { .. } => {
+                self.debug_messages
+                    .push("Got a login response after logging in".to_string());
+            }
+        }
+    }
+
+    pub fn handle_game_update(&mut self, update: GameUpdate) {
+        match update {
+            GameUpdate::Message(msg) => self.push_message(MessageType::Game(msg)),
+            GameUpdate::Inventory(inventory) => self.inventory = Some(inventory),
+            GameUpdate::Vitals(vitals) => self.vitals = Some(vitals),
+            GameUpdate::CombatRound(events) => {
+                for event in events {
+                    self.push_message(MessageType::Combat(event));
+                }
+            }
+            GameUpdate::QuestUpdated(quest) => match quest.status {
+                QuestStatus::Active | QuestStatus::Complete => {
+                    self.quests.insert(quest.id.clone(), quest);
+                }
+                QuestStatus::TurnedIn | QuestStatus::Abandoned => {
+                    self.quests.remove(&quest.id);
+                }
+            },
+            GameUpdate::Dialogue(view) => {
+                if !view.text.is_empty() {
+                    self.push_message(MessageType::Game(format!(
+                        "{} says, '{}'",
+                        view.npc, view.text
+                    )));
+                }
+                self.dialogue = if view.choices.is_empty() {
+                    None
+                } else {
+                    Some(Dialogue { view, selected: 0 })
+                };
+            }
+            GameUpdate::Trade(trade) => self.trade = trade,
+            GameUpdate::Party(party) => self.party = party,
+            GameUpdate::Skills(skills) => self.skills = skills,
+            GameUpdate::Map(map) => self.map = Some(map),
         }
     }
 }
@@ -111,6 +227,7 @@ fn main() -> Result<(), Box<dyn std::error::Error>> {
 
     // Start login process
     let mut app_connected = App::<Connected>::from(app);
+    app_connected.run();
 
     reset_terminal(&mut app_connected.terminal)
 }
@@ -1,8 +1,55 @@
 use ratatui::prelude::*;
-use ratatui::widgets::{Block, Borders, Paragraph, Tabs};
-use ratatui::{Frame, Terminal};
+use ratatui::widgets::{Block, Borders, LineGauge, Paragraph, Wrap};
+use ratatui::Frame;
 
-use crate::{MessageType, ServerMessage, State, UserData};
+use model::{
+    AttackResult, CombatEvent, InventoryView, ItemView, MapView, PartyView, QuestStatus, QuestView,
+    SkillsView, SpeechKind, TradeOfferView, TradeView, Vitals,
+};
+
+use crate::{Dialogue, MessageType, ServerMessage, State, UserData};
+
+/// Wide enough for a map reaching three rooms either side of the player.
+const MINIMAP_WIDTH: u16 = 31;
+
+fn render_combat_event(event: &CombatEvent) -> Line<'_> {
+    let CombatEvent {
+        attacker,
+        defender,
+        result,
+        defender_hp,
+        defender_max_hp,
+    } = event;
+
+    let (text, style) = match result {
+        AttackResult::Miss => (
+            format!("{attacker} misses {defender}."),
+            Style::new().dark_gray(),
+        ),
+        AttackResult::Hit { damage } => (
+            format!("{attacker} hits {defender} for {damage}."),
+            Style::new().red(),
+        ),
+        AttackResult::Critical { damage } => (
+            format!("{attacker} CRITICALLY hits {defender} for {damage}!"),
+            Style::new().red().bold(),
+        ),
+    };
+
+    let mut chars = text.chars();
+    let text = match chars.next() {
+        Some(first) => first.to_uppercase().chain(chars).collect(),
+        None => text,
+    };
+
+    Line::from(vec![
+        Span::styled(text, style),
+        Span::styled(
+            format!(" [{defender_hp}/{defender_max_hp}]"),
+            Style::new().dark_gray(),
+        ),
+    ])
+}
 
 fn render_message<'a>(message: &'a ServerMessage) -> Vec<Line<'a>> {
     match &message.ty {
@@ -29,6 +76,11 @@ fn render_message<'a>(message: &'a ServerMessage) -> Vec<Line<'a>> {
 
             lines
         }
+        MessageType::Game(contents) => contents
+            .split('\n')
+            .map(|line| Line::styled(line, Style::new().cyan()))
+            .collect(),
+        MessageType::Combat(event) => vec![render_combat_event(event)],
         MessageType::Private { from, msg } => {
             vec![Line::from(vec![
                 Span::styled(
@@ -39,27 +91,84 @@ fn render_message<'a>(message: &'a ServerMessage) -> Vec<Line<'a>> {
                 Span::styled(msg, Style::new().green().bold()),
             ])]
         }
+        MessageType::Party { from, msg } => vec![Line::from(vec![
+            Span::styled(
+                format!("[{}] ", message.timestamp.format("%H:%M")),
+                Style::new().magenta().bold(),
+            ),
+            Span::styled(format!("[group] {from}: "), Style::new().magenta().bold()),
+            Span::styled(msg, Style::new().magenta()),
+        ])],
+        MessageType::Speech { kind, msg } => {
+            let style = match kind {
+                SpeechKind::Say => Style::new().white(),
+                SpeechKind::Emote => Style::new().light_green().italic(),
+                SpeechKind::Whisper => Style::new().dark_gray().italic(),
+                SpeechKind::Shout => Style::new().light_red().bold(),
+                SpeechKind::Yell => Style::new().red(),
+            };
+            vec![Line::from(vec![
+                Span::styled(
+                    format!("[{}] ", message.timestamp.format("%H:%M")),
+                    style.bold(),
+                ),
+                Span::styled(msg, style),
+            ])]
+        }
     }
 }
 
 fn render_message_area(f: &mut Frame, state: &State, area: Rect) {
+    // open conversations and trades take the bottom of the message area
+    let area = match &state.dialogue {
+        Some(dialogue) => {
+            let chunks = Layout::default()
+                .direction(Direction::Vertical)
+                .constraints([
+                    Constraint::Min(3),
+                    Constraint::Length(dialogue.view.choices.len() as u16 + 2),
+                ])
+                .split(area);
+            render_dialogue(f, dialogue, chunks[1]);
+            chunks[0]
+        }
+        None => area,
+    };
+
+    let area = match &state.trade {
+        Some(trade) => {
+            let rows = |offer: &TradeOfferView| offer.items.len() + usize::from(offer.gold > 0);
+            let height = rows(&trade.yours).max(rows(&trade.theirs)).max(1) as u16 + 2;
+            let chunks = Layout::default()
+                .direction(Direction::Vertical)
+                .constraints([Constraint::Min(3), Constraint::Length(height)])
+                .split(area);
+            render_trade(f, trade, chunks[1]);
+            chunks[0]
+        }
+        None => area,
+    };
+
+    // the debug pane takes the right half when it's toggled on, otherwise the minimap
+    // sits beside the messages
+    let side = if state.show_debug {
+        Constraint::Percentage(50)
+    } else if state.map.is_some() {
+        Constraint::Length(MINIMAP_WIDTH)
+    } else {
+        Constraint::Length(0)
+    };
     let chunks = Layout::default()
         .direction(Direction::Horizontal)
-        .constraints([Constraint::Percentage(50), Constraint::Min(1)])
+        .constraints([Constraint::Min(1), side])
         .split(area);
 
     let max_messages = chunks[0].height - 2;
 
-    // render another area for debug messages!
-
     let messages = match state.current_tab {
         None => vec![],
         Some(ref room_name) => match state.room_messages.get(room_name) {
-            Some(messages) => messages
-                .iter()
-                .map(|msg| render_message(msg))
-                .flatten()
-                .collect::<Vec<_>>(),
+            Some(messages) => messages.iter().flat_map(render_message).collect::<Vec<_>>(),
             None => panic!("Unkown room name {room_name}"),
         },
     };
@@ -81,19 +190,325 @@ fn render_message_area(f: &mut Frame, state: &State, area: Rect) {
     )
     .block(Block::default().borders(Borders::ALL));
 
+    f.render_widget(para, chunks[0]);
     if state.show_debug {
-        f.render_widget(para, chunks[0]);
         f.render_widget(debug_messages, chunks[1]);
+    } else if let Some(map) = &state.map {
+        render_minimap(f, map, chunks[1]);
+    }
+}
+
+/// Draws the map centred on the player, who is highlighted.
+fn render_minimap(f: &mut Frame, map: &MapView, area: Rect) {
+    let lines = map
+        .render()
+        .into_iter()
+        .map(|line| match line.find('@') {
+            Some(i) => Line::from(vec![
+                Span::raw(line[..i].to_string()),
+                Span::styled("@", Style::new().yellow().bold()),
+                Span::raw(line[i + 1..].to_string()),
+            ]),
+            None => Line::raw(line),
+        })
+        .collect::<Vec<_>>();
+
+    // scroll so the player's row is in the middle
+    let player_row = map.rooms.iter().map(|room| -room.y).max().unwrap_or(0) * 2 + 1;
+    let offset = (player_row - i32::from(area.height.saturating_sub(2)) / 2).max(0) as u16;
+
+    let para = Paragraph::new(lines)
+        .alignment(Alignment::Center)
+        .scroll((offset, 0))
+        .block(
+            Block::default()
+                .borders(Borders::ALL)
+                .title(map.location.as_str()),
+        );
+    f.render_widget(para, area);
+}
+
+fn render_item<'a>(item: &'a ItemView, depth: usize, lines: &mut Vec<Line<'a>>) {
+    lines.push(Line::from(format!("{}{}", "  ".repeat(depth), item.name)));
+    for inner in &item.contents {
+        render_item(inner, depth + 1, lines);
+    }
+}
+
+fn render_inventory(f: &mut Frame, inventory: &InventoryView, area: Rect) {
+    let mut lines = vec![];
+    for (slot, item) in &inventory.worn {
+        lines.push(Line::from(vec![
+            Span::styled(format!("<{slot}> "), Style::new().yellow()),
+            Span::from(item.name.as_str()),
+        ]));
+    }
+    if !inventory.worn.is_empty() {
+        lines.push(Line::from(""));
+    }
+    for item in &inventory.carried {
+        render_item(item, 0, &mut lines);
+    }
+
+    let title = format!(
+        "Inventory ({}/{}, {} gold)",
+        inventory.weight, inventory.capacity, inventory.gold
+    );
+    let para = Paragraph::new(lines).block(Block::default().title(title).borders(Borders::ALL));
+    f.render_widget(para, area);
+}
+
+fn render_quest_log<'a>(f: &mut Frame, quests: impl Iterator<Item = &'a QuestView>, area: Rect) {
+    let mut lines = vec![];
+    for quest in quests {
+        let style = match quest.status {
+            QuestStatus::Complete => Style::new().green().bold(),
+            _ => Style::new().bold(),
+        };
+        lines.push(Line::styled(quest.name.as_str(), style));
+        for objective in &quest.objectives {
+            let style = if objective.current >= objective.required {
+                Style::new().dark_gray()
+            } else {
+                Style::new()
+            };
+            lines.push(Line::styled(
+                format!(
+                    "  {} {}/{}",
+                    objective.description, objective.current, objective.required
+                ),
+                style,
+            ));
+        }
+    }
+
+    let para = Paragraph::new(lines)
+        .wrap(Wrap { trim: false })
+        .block(Block::default().title("Quests").borders(Borders::ALL));
+    f.render_widget(para, area);
+}
+
+fn render_dialogue(f: &mut Frame, dialogue: &Dialogue, area: Rect) {
+    let lines = dialogue
+        .view
+        .choices
+        .iter()
+        .enumerate()
+        .map(|(i, choice)| {
+            let style = if i == dialogue.selected {
+                Style::new().yellow().bold().reversed()
+            } else {
+                Style::new().yellow()
+            };
+            Line::styled(format!("{}. {choice}", i + 1), style)
+        })
+        .collect::<Vec<_>>();
+
+    let title = format!("Talking to {}", dialogue.view.npc);
+    let para = Paragraph::new(lines)
+        .wrap(Wrap { trim: false })
+        .block(Block::default().title(title).borders(Borders::ALL));
+    f.render_widget(para, area);
+}
+
+fn render_trade_offer(f: &mut Frame, title: String, offer: &TradeOfferView, area: Rect) {
+    let mut lines = vec![];
+    for item in &offer.items {
+        render_item(item, 0, &mut lines);
+    }
+    if offer.gold > 0 {
+        lines.push(Line::styled(
+            format!("{} gold", offer.gold),
+            Style::new().yellow(),
+        ));
+    }
+
+    let title = if offer.confirmed {
+        Line::from(vec![
+            Span::from(title),
+            Span::styled(" (confirmed)", Style::new().green().bold()),
+        ])
     } else {
-        f.render_widget(para, area);
+        Line::from(title)
+    };
+    let para = Paragraph::new(lines).block(Block::default().title(title).borders(Borders::ALL));
+    f.render_widget(para, area);
+}
+
+fn render_trade(f: &mut Frame, trade: &TradeView, area: Rect) {
+    let chunks = Layout::default()
+        .direction(Direction::Horizontal)
+        .constraints([Constraint::Percentage(50), Constraint::Percentage(50)])
+        .split(area);
+
+    render_trade_offer(f, "You offer".to_string(), &trade.yours, chunks[0]);
+    render_trade_offer(
+        f,
+        format!("{} offers", trade.partner),
+        &trade.theirs,
+        chunks[1],
+    );
+}
+
+fn render_party(f: &mut Frame, party: &PartyView, area: Rect) {
+    let block = Block::default().title("Group").borders(Borders::ALL);
+    let inner = block.inner(area);
+    f.render_widget(block, area);
+
+    let rows = Layout::default()
+        .direction(Direction::Vertical)
+        .constraints(vec![Constraint::Length(1); party.members.len() * 2])
+        .split(inner);
+
+    for (i, member) in party.members.iter().enumerate() {
+        let mut name = vec![
+            Span::styled(member.name.as_str(), Style::new().bold()),
+            Span::from(format!(" lvl {}", member.level)),
+        ];
+        if member.name == party.leader {
+            name.push(Span::styled(" (leader)", Style::new().yellow()));
+        } else if !member.following {
+            name.push(Span::styled(" (not following)", Style::new().dark_gray()));
+        }
+        f.render_widget(Paragraph::new(Line::from(name)), rows[i * 2]);
+        f.render_widget(
+            vital_gauge("HP", member.hp, member.max_hp, Color::Red),
+            rows[i * 2 + 1],
+        );
+    }
+}
+
+fn render_skills(f: &mut Frame, skills: &SkillsView, area: Rect) {
+    let mut lines = vec![];
+    for skill in &skills.skills {
+        let status = if skill.cooldown == 0 {
+            Span::styled("ready", Style::new().green())
+        } else {
+            Span::styled(
+                format!("{}/{}", skill.cooldown, skill.max_cooldown),
+                Style::new().yellow(),
+            )
+        };
+        lines.push(Line::from(vec![
+            Span::styled(format!("{} ", skill.name), Style::new().bold()),
+            Span::styled(format!("({}) ", skill.cost), Style::new().dark_gray()),
+            status,
+        ]));
+    }
+    for affect in &skills.affects {
+        let style = if affect.harmful {
+            Style::new().red()
+        } else {
+            Style::new().cyan()
+        };
+        lines.push(Line::styled(
+            format!("* {} ({})", affect.name, affect.remaining),
+            style,
+        ));
+    }
+
+    let para = Paragraph::new(lines).block(Block::default().title("Skills").borders(Borders::ALL));
+    f.render_widget(para, area);
+}
+
+/// The column beside the messages: group, inventory, skills and quest log.
+fn render_side_panel(f: &mut Frame, state: &State, inventory: &InventoryView, area: Rect) {
+    let mut constraints = vec![];
+    if let Some(party) = &state.party {
+        constraints.push(Constraint::Length(party.members.len() as u16 * 2 + 2));
     }
+    constraints.push(Constraint::Min(5));
+    let skill_lines = state.skills.skills.len() + state.skills.affects.len();
+    if skill_lines > 0 {
+        constraints.push(Constraint::Length(skill_lines as u16 + 2));
+    }
+    if !state.quests.is_empty() {
+        constraints.push(Constraint::Percentage(40));
+    }
+
+    let chunks = Layout::default()
+        .direction(Direction::Vertical)
+        .constraints(constraints)
+        .split(area);
+    let mut chunks = chunks.iter();
+
+    if let Some(party) = &state.party {
+        render_party(f, party, *chunks.next().unwrap());
+    }
+    render_inventory(f, inventory, *chunks.next().unwrap());
+    if skill_lines > 0 {
+        render_skills(f, &state.skills, *chunks.next().unwrap());
+    }
+    if let Some(area) = chunks.next() {
+        render_quest_log(f, state.quests.values(), *area);
+    }
+}
+
+fn vital_gauge(label: &str, current: i32, max: i32, color: Color) -> LineGauge<'_> {
+    let ratio = if max > 0 {
+        (current as f64 / max as f64).clamp(0.0, 1.0)
+    } else {
+        0.0
+    };
+
+    LineGauge::default()
+        .label(format!("{label} {current}/{max}"))
+        .gauge_style(Style::new().fg(color))
+        .ratio(ratio)
+}
+
+fn render_vitals(f: &mut Frame, vitals: &Vitals, area: Rect) {
+    let chunks = Layout::default()
+        .direction(Direction::Horizontal)
+        .constraints([
+            Constraint::Max(12),
+            Constraint::Ratio(1, 4),
+            Constraint::Ratio(1, 4),
+            Constraint::Ratio(1, 4),
+            Constraint::Ratio(1, 4),
+        ])
+        .split(area);
+
+    f.render_widget(
+        Paragraph::new(Span::styled(
+            format!("Lvl {}", vitals.level),
+            Style::new().bold(),
+        )),
+        chunks[0],
+    );
+    f.render_widget(
+        vital_gauge("HP", vitals.hp, vitals.max_hp, Color::Red),
+        chunks[1],
+    );
+    f.render_widget(
+        vital_gauge("MP", vitals.mana, vitals.max_mana, Color::Blue),
+        chunks[2],
+    );
+    f.render_widget(
+        vital_gauge("SP", vitals.stamina, vitals.max_stamina, Color::Green),
+        chunks[3],
+    );
+    f.render_widget(
+        vital_gauge(
+            "XP",
+            vitals.experience as i32,
+            vitals.next_level as i32,
+            Color::Yellow,
+        ),
+        chunks[4],
+    );
 }
 
 pub fn ui(f: &mut Frame, state: &State) {
     let chunks = Layout::default()
         .direction(Direction::Vertical)
         .margin(2)
-        .constraints([Constraint::Max(1), Constraint::Min(10), Constraint::Max(3)])
+        .constraints([
+            Constraint::Max(1),
+            Constraint::Max(1),
+            Constraint::Min(10),
+            Constraint::Max(3),
+        ])
         .split(f.size());
 
     let current_room = match &state.current_tab {
@@ -111,6 +526,21 @@ pub fn ui(f: &mut Frame, state: &State) {
     };
 
     f.render_widget(status_line, chunks[0]);
-    render_message_area(f, state, chunks[1]);
-    f.render_widget(state.textarea.widget(), chunks[2]);
+    if let Some(vitals) = &state.vitals {
+        render_vitals(f, vitals, chunks[1]);
+    }
+
+    match &state.inventory {
+        Some(inventory) => {
+            let main_chunks = Layout::default()
+                .direction(Direction::Horizontal)
+                .constraints([Constraint::Min(1), Constraint::Length(32)])
+                .split(chunks[2]);
+            render_message_area(f, state, main_chunks[0]);
+            render_side_panel(f, state, inventory, main_chunks[1]);
+        }
+        None => render_message_area(f, state, chunks[2]),
+    }
+
+    f.render_widget(state.textarea.widget(), chunks[3]);
 }
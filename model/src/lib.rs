@@ -1,14 +1,21 @@
 use serde::{Deserialize, Serialize};
 
-impl Into<Vec<u8>> for UserAction {
-    fn into(self) -> Vec<u8> {
-        bincode::serialize(&self).unwrap()
+impl From<UserAction> for Vec<u8> {
+    fn from(value: UserAction) -> Self {
+        bincode::serialize(&value).unwrap()
     }
 }
 
 #[derive(Serialize, Deserialize, Clone, Debug)]
 pub enum UserAction {
     Chat(ChatMessage),
+    /// A game command line such as `get sword` or `wear helmet`, parsed by the server.
+    Command(String),
+    /// Answers `ServerResponse::ChooseCharacter` with the ids of the chosen options.
+    CreateCharacter {
+        race: String,
+        class: String,
+    },
 }
 
 #[derive(Serialize, Deserialize, Clone, Debug)]
@@ -20,15 +27,39 @@ pub enum Response {
 
 #[derive(Serialize, Deserialize, Clone, Debug)]
 pub enum ServerResponse {
-    JoinedServer { username: String },
-    JoinedRoom { room_name: String },
-    OtherUserJoined { name: String },
-    General { room_name: String, msg: String },
+    JoinedServer {
+        username: String,
+    },
+    JoinedRoom {
+        room_name: String,
+    },
+    OtherUserJoined {
+        name: String,
+    },
+    General {
+        room_name: String,
+        msg: String,
+    },
+    LoginFailed {
+        reason: String,
+    },
+    /// Sent after login when the username has no saved character yet.
+    ChooseCharacter {
+        races: Vec<CharacterOption>,
+        classes: Vec<CharacterOption>,
+    },
 }
 
-impl Into<Vec<u8>> for Response {
-    fn into(self) -> Vec<u8> {
-        bincode::serialize(&self).unwrap()
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct CharacterOption {
+    pub id: String,
+    pub name: String,
+    pub description: String,
+}
+
+impl From<Response> for Vec<u8> {
+    fn from(value: Response) -> Self {
+        bincode::serialize(&value).unwrap()
     }
 }
 
@@ -48,6 +79,17 @@ impl Response {
         })
     }
 
+    pub fn game_msg(msg: &str) -> Response {
+        Response::Game(GameUpdate::Message(msg.to_string()))
+    }
+
+    pub fn speech(kind: SpeechKind, msg: &str) -> Response {
+        Response::Chat(ChatMessage::Speech {
+            kind,
+            msg: msg.to_string(),
+        })
+    }
+
     pub fn private_msg(msg: &str, from: &str) -> Response {
         Response::Chat(ChatMessage::Private {
             from: from.to_string(),
@@ -68,9 +110,305 @@ pub enum ChatMessage {
         msg: String,
     },
     Username(String),
+    /// Said to everyone in the sender's group.
+    Party {
+        from: String,
+        msg: String,
+    },
+    /// Something said or done in character, already worded for whoever gets it.
+    Speech {
+        kind: SpeechKind,
+        msg: String,
+    },
+}
+
+/// How something was said, which decides who hears it.
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Eq)]
+pub enum SpeechKind {
+    /// Heard by everyone in the same location.
+    Say,
+    /// An action seen by everyone in the same location.
+    Emote,
+    /// Heard only by one person, though others nearby see it happen.
+    Whisper,
+    /// Heard across the whole area.
+    Shout,
+    /// Heard in the same location and the ones next to it.
+    Yell,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub enum GameUpdate {
+    /// Narrative text, e.g. the output of `look` or someone picking up an item.
+    Message(String),
+    /// The full contents of the player's inventory, sent whenever it changes.
+    Inventory(InventoryView),
+    /// Every attack made in the player's location during one combat round.
+    CombatRound(Vec<CombatEvent>),
+    Vitals(Vitals),
+    /// A quest in the player's log was started, progressed, finished or dropped.
+    QuestUpdated(QuestView),
+    /// A line of conversation from an NPC. The conversation is over when there are no
+    /// choices left.
+    Dialogue(DialogueView),
+    /// The state of the player's trade, or `None` once it's over.
+    Trade(Option<TradeView>),
+    /// The player's group and its members' vitals, or `None` after leaving it.
+    Party(Option<PartyView>),
+    /// The player's skills and what's affecting them, sent whenever either changes.
+    Skills(SkillsView),
+    /// The locations around the player, sent whenever they move.
+    Map(MapView),
+}
+
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Eq)]
+pub enum MapDirection {
+    North,
+    East,
+    South,
+    West,
+    Up,
+    Down,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct MapExit {
+    pub dir: MapDirection,
+    /// Leads somewhere other than the location drawn next to this one that way.
+    pub odd: bool,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct MapRoom {
+    /// Columns east of the player's location.
+    pub x: i32,
+    /// Rows south of the player's location.
+    pub y: i32,
+    pub name: String,
+    pub exits: Vec<MapExit>,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct MapView {
+    /// Name of the player's location, which is the room at 0, 0.
+    pub location: String,
+    pub rooms: Vec<MapRoom>,
+}
+
+impl MapView {
+    /// Draws the map as text, four columns and two rows to a room, e.g. `[@]-[ ]`.
+    /// The player's location is `@`, and `^`, `v` and `%` mark ways up, down or both.
+    /// Exits that don't lead to the neighbouring room are drawn with `~`.
+    pub fn render(&self) -> Vec<String> {
+        let Some(min_x) = self.rooms.iter().map(|room| room.x).min() else {
+            return vec![];
+        };
+        let max_x = self.rooms.iter().map(|room| room.x).max().unwrap();
+        let min_y = self.rooms.iter().map(|room| room.y).min().unwrap();
+        let max_y = self.rooms.iter().map(|room| room.y).max().unwrap();
+
+        let width = ((max_x - min_x) * 4 + 5) as usize;
+        let height = ((max_y - min_y) * 2 + 3) as usize;
+        let mut grid = vec![vec![' '; width]; height];
+        for room in &self.rooms {
+            let col = ((room.x - min_x) * 4 + 1) as usize;
+            let row = ((room.y - min_y) * 2 + 1) as usize;
+            let has = |dir| room.exits.iter().any(|exit| exit.dir == dir);
+            let centre = match (
+                room.x == 0 && room.y == 0,
+                has(MapDirection::Up),
+                has(MapDirection::Down),
+            ) {
+                (true, _, _) => '@',
+                (false, true, true) => '%',
+                (false, true, false) => '^',
+                (false, false, true) => 'v',
+                (false, false, false) => ' ',
+            };
+            grid[row][col] = '[';
+            grid[row][col + 1] = centre;
+            grid[row][col + 2] = ']';
+
+            for exit in &room.exits {
+                let (r, c, line) = match exit.dir {
+                    MapDirection::North => (row - 1, col + 1, '|'),
+                    MapDirection::South => (row + 1, col + 1, '|'),
+                    MapDirection::East => (row, col + 3, '-'),
+                    MapDirection::West => (row, col - 1, '-'),
+                    MapDirection::Up | MapDirection::Down => continue,
+                };
+                grid[r][c] = if exit.odd { '~' } else { line };
+            }
+        }
+
+        grid.into_iter()
+            .map(|row| row.into_iter().collect())
+            .collect()
+    }
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct SkillView {
+    pub name: String,
+    /// e.g. "8 mana"
+    pub cost: String,
+    /// Game ticks until it can be used again, 0 when ready.
+    pub cooldown: u32,
+    pub max_cooldown: u32,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct AffectView {
+    pub name: String,
+    /// Game ticks until it wears off.
+    pub remaining: u32,
+    pub harmful: bool,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, Default)]
+pub struct SkillsView {
+    pub skills: Vec<SkillView>,
+    pub affects: Vec<AffectView>,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct PartyMemberView {
+    pub name: String,
+    pub level: u32,
+    pub hp: i32,
+    pub max_hp: i32,
+    pub mana: i32,
+    pub max_mana: i32,
+    /// Whether they move when the leader does.
+    pub following: bool,
 }
 
 #[derive(Serialize, Deserialize, Clone, Debug)]
-pub struct GameUpdate {
-    pub msg: String,
+pub struct PartyView {
+    pub leader: String,
+    pub members: Vec<PartyMemberView>,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, Default)]
+pub struct TradeOfferView {
+    pub items: Vec<ItemView>,
+    pub gold: u64,
+    pub confirmed: bool,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct TradeView {
+    pub partner: String,
+    pub yours: TradeOfferView,
+    pub theirs: TradeOfferView,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct DialogueView {
+    pub npc: String,
+    pub text: String,
+    /// Picked by sending the choice's number, starting from 1, as a command.
+    pub choices: Vec<String>,
+}
+
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Eq)]
+pub enum QuestStatus {
+    Active,
+    /// Every objective is done; the quest giver is waiting for the player.
+    Complete,
+    TurnedIn,
+    Abandoned,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct ObjectiveView {
+    pub description: String,
+    pub current: u32,
+    pub required: u32,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct QuestView {
+    pub id: String,
+    pub name: String,
+    pub status: QuestStatus,
+    pub objectives: Vec<ObjectiveView>,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, Default)]
+pub struct Vitals {
+    pub hp: i32,
+    pub max_hp: i32,
+    pub mana: i32,
+    pub max_mana: i32,
+    pub stamina: i32,
+    pub max_stamina: i32,
+    pub level: u32,
+    pub experience: u64,
+    /// Total experience needed to reach the next level.
+    pub next_level: u64,
+}
+
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Eq)]
+pub enum AttackResult {
+    Miss,
+    Hit { damage: i32 },
+    Critical { damage: i32 },
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct CombatEvent {
+    pub attacker: String,
+    pub defender: String,
+    pub result: AttackResult,
+    /// The defender's hit points after the attack.
+    pub defender_hp: i32,
+    pub defender_max_hp: i32,
+}
+
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub enum WearSlot {
+    Head,
+    Neck,
+    Body,
+    Hands,
+    Finger,
+    Legs,
+    Feet,
+    Wield,
+    Shield,
+}
+
+impl std::fmt::Display for WearSlot {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let name = match self {
+            WearSlot::Head => "head",
+            WearSlot::Neck => "neck",
+            WearSlot::Body => "body",
+            WearSlot::Hands => "hands",
+            WearSlot::Finger => "finger",
+            WearSlot::Legs => "legs",
+            WearSlot::Feet => "feet",
+            WearSlot::Wield => "wielded",
+            WearSlot::Shield => "shield",
+        };
+        write!(f, "{name}")
+    }
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct ItemView {
+    pub name: String,
+    pub weight: u32,
+    /// Items held inside this one, if it is a container.
+    pub contents: Vec<ItemView>,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, Default)]
+pub struct InventoryView {
+    pub carried: Vec<ItemView>,
+    pub worn: Vec<(WearSlot, ItemView)>,
+    pub weight: u32,
+    pub capacity: u32,
+    pub gold: u64,
 }
@@ -0,0 +1,542 @@
+use std::collections::{BTreeMap, BTreeSet};
+use std::fs;
+use std::net::SocketAddr;
+use std::path::{Path, PathBuf};
+
+use model::{CharacterOption, GameUpdate, Response, Vitals, WearSlot};
+use rand::Rng;
+use serde::{Deserialize, Serialize};
+
+use crate::combat::Combatant;
+use crate::item::ItemId;
+use crate::quest::QuestLog;
+use crate::social::Pronouns;
+use crate::world::{is_default, LocationId, World};
+use crate::Shared;
+
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Attribute {
+    Strength,
+    Dexterity,
+    Constitution,
+    Intelligence,
+    Wisdom,
+}
+
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, Default)]
+pub struct Attributes {
+    #[serde(default)]
+    pub strength: i32,
+    #[serde(default)]
+    pub dexterity: i32,
+    #[serde(default)]
+    pub constitution: i32,
+    #[serde(default)]
+    pub intelligence: i32,
+    #[serde(default)]
+    pub wisdom: i32,
+}
+
+impl Attributes {
+    pub fn get(&self, attribute: Attribute) -> i32 {
+        match attribute {
+            Attribute::Strength => self.strength,
+            Attribute::Dexterity => self.dexterity,
+            Attribute::Constitution => self.constitution,
+            Attribute::Intelligence => self.intelligence,
+            Attribute::Wisdom => self.wisdom,
+        }
+    }
+
+    pub fn get_mut(&mut self, attribute: Attribute) -> &mut i32 {
+        match attribute {
+            Attribute::Strength => &mut self.strength,
+            Attribute::Dexterity => &mut self.dexterity,
+            Attribute::Constitution => &mut self.constitution,
+            Attribute::Intelligence => &mut self.intelligence,
+            Attribute::Wisdom => &mut self.wisdom,
+        }
+    }
+
+    /// The bonus or penalty an attribute gives, 0 at the average score of 10.
+    pub fn modifier(&self, attribute: Attribute) -> i32 {
+        (self.get(attribute) - 10).div_euclid(2)
+    }
+
+    fn plus(self, other: Attributes) -> Attributes {
+        Attributes {
+            strength: self.strength + other.strength,
+            dexterity: self.dexterity + other.dexterity,
+            constitution: self.constitution + other.constitution,
+            intelligence: self.intelligence + other.intelligence,
+            wisdom: self.wisdom + other.wisdom,
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct RaceDef {
+    pub id: String,
+    pub name: String,
+    pub description: String,
+    /// Added to the base attributes.
+    #[serde(default)]
+    pub attributes: Attributes,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct ClassDef {
+    pub id: String,
+    pub name: String,
+    pub description: String,
+    /// Raised by one point on every level up.
+    pub primary: Attribute,
+    pub base_hp: i32,
+    pub base_mana: i32,
+    pub base_stamina: i32,
+    pub hp_per_level: i32,
+    pub mana_per_level: i32,
+    pub stamina_per_level: i32,
+}
+
+/// Character creation and progression rules, loaded from `data/characters.ron`.
+#[derive(Deserialize, Clone, Debug)]
+pub struct CharacterRules {
+    pub base_attributes: Attributes,
+    /// Experience needed to go from level 1 to 2.
+    pub experience_base: u64,
+    /// Each level needs this many times the experience of the one before.
+    pub experience_growth: f64,
+    pub max_level: u32,
+    /// Game ticks between regenerating hp, mana and stamina.
+    pub regen_ticks: u32,
+    /// Fraction of each maximum restored per regeneration.
+    pub regen_fraction: f64,
+    pub races: Vec<RaceDef>,
+    pub classes: Vec<ClassDef>,
+}
+
+impl CharacterRules {
+    pub fn race(&self, id: &str) -> Option<&RaceDef> {
+        self.races.iter().find(|race| race.id == id)
+    }
+
+    pub fn class(&self, id: &str) -> Option<&ClassDef> {
+        self.classes.iter().find(|class| class.id == id)
+    }
+
+    /// Total experience needed to reach `level`.
+    pub fn experience_for_level(&self, level: u32) -> u64 {
+        (1..level)
+            .map(|l| {
+                (self.experience_base as f64 * self.experience_growth.powi(l as i32 - 1)).round()
+                    as u64
+            })
+            .sum()
+    }
+
+    pub fn race_options(&self) -> Vec<CharacterOption> {
+        self.races
+            .iter()
+            .map(|race| CharacterOption {
+                id: race.id.clone(),
+                name: race.name.clone(),
+                description: race.description.clone(),
+            })
+            .collect()
+    }
+
+    pub fn class_options(&self) -> Vec<CharacterOption> {
+        self.classes
+            .iter()
+            .map(|class| CharacterOption {
+                id: class.id.clone(),
+                name: class.name.clone(),
+                description: class.description.clone(),
+            })
+            .collect()
+    }
+
+    pub fn new_character(&self, name: &str, race: &str, class: &str) -> Result<Character, String> {
+        let race = self.race(race).ok_or(format!("Unknown race {race}"))?;
+        let class = self.class(class).ok_or(format!("Unknown class {class}"))?;
+        let attributes = self.base_attributes.plus(race.attributes);
+        let con = attributes.modifier(Attribute::Constitution);
+
+        let max_hp = class.base_hp + con * 2;
+        let max_mana = class.base_mana + attributes.modifier(Attribute::Intelligence) * 2;
+        let max_stamina = class.base_stamina + con * 2;
+
+        Ok(Character {
+            name: name.to_string(),
+            race: race.id.clone(),
+            class: class.id.clone(),
+            level: 1,
+            experience: 0,
+            attributes,
+            hp: max_hp,
+            max_hp,
+            mana: max_mana,
+            max_mana,
+            stamina: max_stamina,
+            max_stamina,
+            role: Role::Player,
+            gold: 0,
+            pronouns: Pronouns::default(),
+            pvp: false,
+            crafting: 0,
+            reputation: BTreeMap::new(),
+        })
+    }
+}
+
+/// What a player is allowed to do beyond playing the game. Set by editing the save file.
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, Default, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Role {
+    #[default]
+    Player,
+    Builder,
+    /// Also receives script error reports.
+    Admin,
+}
+
+/// A player's character sheet.
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct Character {
+    pub name: String,
+    pub race: String,
+    pub class: String,
+    pub level: u32,
+    pub experience: u64,
+    pub attributes: Attributes,
+    pub hp: i32,
+    pub max_hp: i32,
+    pub mana: i32,
+    pub max_mana: i32,
+    pub stamina: i32,
+    pub max_stamina: i32,
+    #[serde(default)]
+    pub role: Role,
+    #[serde(default)]
+    pub gold: u64,
+    #[serde(default)]
+    pub pronouns: Pronouns,
+    /// Agreed to fight other players outside PvP areas.
+    #[serde(default)]
+    pub pvp: bool,
+    /// Skill at making things with `craft`.
+    #[serde(default)]
+    pub crafting: u32,
+    /// Standing with each faction, by id. Factions not listed are at their start.
+    #[serde(default)]
+    pub reputation: BTreeMap<String, i32>,
+}
+
+/// An item as stored in a save file.
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct SavedItem {
+    pub template: String,
+    #[serde(default)]
+    pub contents: Vec<SavedItem>,
+}
+
+/// Everything written to `<save_dir>/<name>.ron` for a player.
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct SaveFile {
+    pub character: Character,
+    pub location: LocationId,
+    #[serde(default)]
+    pub inventory: Vec<SavedItem>,
+    #[serde(default)]
+    pub equipment: BTreeMap<WearSlot, SavedItem>,
+    #[serde(default)]
+    pub quests: QuestLog,
+    #[serde(default)]
+    pub flags: BTreeSet<String>,
+    /// Game ticks until each skill can be used again, so logging out doesn't reset them.
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub cooldowns: BTreeMap<String, u32>,
+    #[serde(default, skip_serializing_if = "is_default")]
+    pub pvp_cooldown: u32,
+}
+
+/// Checks a requested name and returns it with a leading capital.
+pub fn validate_name(name: &str) -> Result<String, String> {
+    if !(3..=16).contains(&name.len()) {
+        return Err("Names must be 3 to 16 letters long.".to_string());
+    }
+    if !name.chars().all(|c| c.is_ascii_alphabetic()) {
+        return Err("Names may only contain letters.".to_string());
+    }
+
+    let lower = name.to_ascii_lowercase();
+    Ok(lower[..1].to_ascii_uppercase() + &lower[1..])
+}
+
+fn save_path(dir: &Path, name: &str) -> PathBuf {
+    dir.join(format!("{}.ron", name.to_ascii_lowercase()))
+}
+
+pub fn load_save(dir: &Path, name: &str) -> Result<Option<SaveFile>, String> {
+    let path = save_path(dir, name);
+    if !path.exists() {
+        return Ok(None);
+    }
+
+    let contents = fs::read_to_string(&path).map_err(|e| format!("{}: {e}", path.display()))?;
+    ron::from_str(&contents)
+        .map(Some)
+        .map_err(|e| format!("{}: {e}", path.display()))
+}
+
+pub fn write_save(dir: &Path, save: &SaveFile) -> Result<(), String> {
+    let path = save_path(dir, &save.character.name);
+    let contents = ron::ser::to_string_pretty(save, ron::ser::PrettyConfig::default())
+        .map_err(|e| format!("Couldn't serialize {}: {e}", save.character.name))?;
+    fs::create_dir_all(dir)
+        .and_then(|_| fs::write(&path, contents))
+        .map_err(|e| format!("{}: {e}", path.display()))
+}
+
+impl World {
+    pub fn save_dir(&self) -> PathBuf {
+        self.data_dir.join(&self.config.save_dir)
+    }
+
+    /// Spawns saved items, skipping any whose template no longer exists.
+    pub fn restore_item(&mut self, saved: &SavedItem) -> Option<ItemId> {
+        let id = match self.spawn_item(&saved.template) {
+            Ok(id) => id,
+            Err(e) => {
+                eprintln!("Dropping saved item: {e}");
+                return None;
+            }
+        };
+
+        let contents = saved
+            .contents
+            .iter()
+            .filter_map(|inner| self.restore_item(inner))
+            .collect();
+        self.items.get_mut(&id).unwrap().contents = contents;
+        Some(id)
+    }
+
+    pub fn saved_item(&self, id: ItemId) -> SavedItem {
+        let item = &self.items[&id];
+        SavedItem {
+            template: item.template.clone(),
+            contents: item
+                .contents
+                .iter()
+                .map(|inner| self.saved_item(*inner))
+                .collect(),
+        }
+    }
+}
+
+impl Shared {
+    pub(crate) fn save_file(&self, addr: &SocketAddr) -> Result<SaveFile, String> {
+        let session = self.session(addr)?;
+        Ok(SaveFile {
+            character: session.character.clone(),
+            location: self.saved_location(&session.location),
+            inventory: session
+                .inventory
+                .iter()
+                .map(|id| self.world.saved_item(*id))
+                .collect(),
+            equipment: session
+                .equipment
+                .iter()
+                .map(|(slot, id)| (*slot, self.world.saved_item(*id)))
+                .collect(),
+            quests: session.quests.clone(),
+            flags: session.flags.clone(),
+            cooldowns: session.cooldowns.clone(),
+            pvp_cooldown: session.pvp_cooldown,
+        })
+    }
+
+    pub fn save_character(&self, addr: &SocketAddr) -> Result<(), String> {
+        write_save(&self.world.save_dir(), &self.save_file(addr)?)
+    }
+
+    pub(crate) fn vitals(&self, addr: &SocketAddr) -> Result<Vitals, String> {
+        let character = &self.session(addr)?.character;
+        Ok(Vitals {
+            hp: character.hp,
+            max_hp: character.max_hp,
+            mana: character.mana,
+            max_mana: character.max_mana,
+            stamina: character.stamina,
+            max_stamina: character.max_stamina,
+            level: character.level,
+            experience: character.experience,
+            next_level: self
+                .world
+                .character_rules
+                .experience_for_level(character.level + 1),
+        })
+    }
+
+    pub(crate) fn send_vitals(&self, addr: &SocketAddr) {
+        if let Ok(vitals) = self.vitals(addr) {
+            self.send_to(addr, Response::Game(GameUpdate::Vitals(vitals)));
+        }
+        self.send_party_vitals(addr);
+    }
+
+    /// Adds experience and applies any level ups it earns.
+    pub fn gain_experience(&mut self, addr: &SocketAddr, amount: u64) {
+        let rules = &self.world.character_rules;
+        let Some(session) = self.peers.get_mut(addr) else {
+            return;
+        };
+        let character = &mut session.character;
+        character.experience += amount;
+        let old_level = character.level;
+
+        let mut levelled = false;
+        while character.level < rules.max_level
+            && character.experience >= rules.experience_for_level(character.level + 1)
+        {
+            let Some(class) = rules.class(&character.class) else {
+                break;
+            };
+
+            character.level += 1;
+            *character.attributes.get_mut(class.primary) += 1;
+            let con = character
+                .attributes
+                .modifier(Attribute::Constitution)
+                .max(0);
+            let int = character
+                .attributes
+                .modifier(Attribute::Intelligence)
+                .max(0);
+            character.max_hp += class.hp_per_level + con;
+            character.max_mana += class.mana_per_level + int;
+            character.max_stamina += class.stamina_per_level + con;
+            character.hp = character.max_hp;
+            character.mana = character.max_mana;
+            character.stamina = character.max_stamina;
+            levelled = true;
+        }
+
+        if levelled {
+            let level = character.level;
+            let name = session.name.clone();
+            let location = session.location.clone();
+            self.send_to(
+                addr,
+                Response::game_msg(&format!("You have reached level {level}!")),
+            );
+            self.broadcast_location(
+                &location,
+                &format!("{name} glows briefly as they reach level {level}."),
+                &[*addr],
+            );
+            if let Err(e) = self.save_character(addr) {
+                eprintln!("Failed to save {name}: {e}");
+            }
+            self.announce_new_skills(addr, old_level);
+        }
+        self.send_vitals(addr);
+    }
+
+    /// Rolls a d20 plus an attribute modifier and half the character's level, succeeding
+    /// if the total reaches `difficulty`.
+    pub(crate) fn attribute_check(
+        &mut self,
+        addr: &SocketAddr,
+        attribute: Attribute,
+        difficulty: i32,
+    ) -> bool {
+        let Some(session) = self.peers.get(addr) else {
+            return false;
+        };
+        let character = &session.character;
+        let bonus = character.attributes.modifier(attribute) + character.level as i32 / 2;
+        self.rng.gen_range(1..=20) + bonus >= difficulty
+    }
+
+    /// Restores a fraction of every player's vitals while they aren't fighting.
+    pub(crate) fn regen_tick(&mut self) {
+        self.regen_timer += 1;
+        if self.regen_timer < self.world.character_rules.regen_ticks {
+            return;
+        }
+        self.regen_timer = 0;
+
+        let fraction = self.world.character_rules.regen_fraction;
+        let regen = |current: &mut i32, max: i32| {
+            if *current < max {
+                *current = (*current + ((max as f64 * fraction).ceil() as i32).max(1)).min(max);
+                true
+            } else {
+                false
+            }
+        };
+
+        let mut changed = vec![];
+        for (addr, session) in self.peers.iter_mut() {
+            if self.fights.contains_key(&Combatant::Player(*addr)) {
+                continue;
+            }
+
+            let c = &mut session.character;
+            let hp = regen(&mut c.hp, c.max_hp);
+            let mana = regen(&mut c.mana, c.max_mana);
+            let stamina = regen(&mut c.stamina, c.max_stamina);
+            if hp || mana || stamina {
+                changed.push(*addr);
+            }
+        }
+
+        for addr in changed {
+            self.send_vitals(&addr);
+        }
+    }
+
+    /// `score`
+    pub fn score(&self, addr: &SocketAddr) -> Result<(), String> {
+        let vitals = self.vitals(addr)?;
+        let character = &self.session(addr)?.character;
+        let rules = &self.world.character_rules;
+        let race = rules
+            .race(&character.race)
+            .map_or(character.race.as_str(), |r| r.name.as_str());
+        let class = rules
+            .class(&character.class)
+            .map_or(character.class.as_str(), |c| c.name.as_str());
+        let a = &character.attributes;
+
+        let msg = format!(
+            "{} the {race} {class}, level {}\n\
+             Experience: {}/{}\n\
+             Hp: {}/{}  Mana: {}/{}  Stamina: {}/{}\n\
+             Str: {}  Dex: {}  Con: {}  Int: {}  Wis: {}\n\
+             Gold: {}  Crafting: {}",
+            character.name,
+            vitals.level,
+            vitals.experience,
+            vitals.next_level,
+            vitals.hp,
+            vitals.max_hp,
+            vitals.mana,
+            vitals.max_mana,
+            vitals.stamina,
+            vitals.max_stamina,
+            a.strength,
+            a.dexterity,
+            a.constitution,
+            a.intelligence,
+            a.wisdom,
+            character.gold,
+            character.crafting,
+        );
+        self.send_to(addr, Response::game_msg(&msg));
+        Ok(())
+    }
+}
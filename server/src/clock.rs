@@ -0,0 +1,187 @@
+use std::net::SocketAddr;
+
+use model::Response;
+use serde::Deserialize;
+
+use crate::Shared;
+
+const MINUTES_PER_DAY: u64 = 24 * 60;
+
+#[derive(Deserialize, Clone, Debug)]
+pub struct ClockConfig {
+    /// Game minutes that pass each tick, which with `tick_ms` sets how fast game time
+    /// runs against real time.
+    pub minutes_per_tick: u32,
+    /// Hour of the day the world starts at.
+    pub start_hour: u32,
+}
+
+impl Default for ClockConfig {
+    fn default() -> Self {
+        ClockConfig {
+            minutes_per_tick: 1,
+            start_hour: 8,
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Period {
+    Night,
+    Dawn,
+    Day,
+    Dusk,
+}
+
+impl Period {
+    pub fn of_hour(hour: u32) -> Period {
+        match hour {
+            5..=6 => Period::Dawn,
+            7..=18 => Period::Day,
+            19..=20 => Period::Dusk,
+            _ => Period::Night,
+        }
+    }
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Period::Night => "night",
+            Period::Dawn => "dawn",
+            Period::Day => "day",
+            Period::Dusk => "dusk",
+        }
+    }
+
+    /// How much light the sky gives outdoors before the weather dims it.
+    pub fn daylight(self) -> i32 {
+        match self {
+            Period::Night => 1,
+            Period::Dawn | Period::Dusk => 2,
+            Period::Day => 3,
+        }
+    }
+
+    /// Shown outdoors when this part of the day begins.
+    fn announcement(self) -> &'static str {
+        match self {
+            Period::Night => "Night falls, and the first stars prick the sky.",
+            Period::Dawn => "The sky pales in the east as dawn breaks.",
+            Period::Day => "The sun climbs clear of the horizon.",
+            Period::Dusk => "The sun sinks low and the shadows lengthen.",
+        }
+    }
+}
+
+/// Game time, counted in minutes since the world began.
+#[derive(Clone, Copy, Debug)]
+pub struct Clock {
+    pub minutes: u64,
+}
+
+impl Clock {
+    pub fn new(config: &ClockConfig) -> Self {
+        Clock {
+            minutes: u64::from(config.start_hour) * 60,
+        }
+    }
+
+    pub fn day(&self) -> u64 {
+        self.minutes / MINUTES_PER_DAY + 1
+    }
+
+    pub fn hour(&self) -> u32 {
+        (self.minutes % MINUTES_PER_DAY / 60) as u32
+    }
+
+    pub fn minute(&self) -> u32 {
+        (self.minutes % 60) as u32
+    }
+
+    pub fn period(&self) -> Period {
+        Period::of_hour(self.hour())
+    }
+}
+
+/// How well players can see somewhere.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Light {
+    /// Nothing can be seen.
+    Dark,
+    /// The room can be seen but not what's lying on the floor.
+    Dim,
+    Bright,
+}
+
+impl Shared {
+    /// Dark locations need a light source. Indoors is lit, and outdoors depends on the
+    /// time of day and the weather.
+    pub(crate) fn light_level(&self, location: &str) -> Light {
+        if self.has_light_source(location) {
+            return Light::Bright;
+        }
+        let location = &self.world.locations[location];
+        if location.dark {
+            return Light::Dark;
+        }
+        if !location.outdoors {
+            return Light::Bright;
+        }
+
+        let darkness = self
+            .world
+            .area_weather(&location.area)
+            .map_or(0, |weather| weather.darkness);
+        match self.clock.period().daylight() - darkness {
+            ..=0 => Light::Dark,
+            1 => Light::Dim,
+            _ => Light::Bright,
+        }
+    }
+
+    /// Whether anyone here is carrying a light, or one is lying on the floor.
+    fn has_light_source(&self, location: &str) -> bool {
+        let carried = self
+            .users_in_location(location)
+            .flat_map(|(_, session)| session.inventory.iter().chain(session.equipment.values()));
+        let worn = self
+            .world
+            .npcs_in_location(location)
+            .flat_map(|npc| npc.equipment.iter());
+        let floor = self.world.locations[location].items.iter();
+        carried
+            .chain(worn)
+            .chain(floor)
+            .any(|id| self.world.template(*id).light)
+    }
+
+    /// Moves the clock on, telling everyone outdoors when the day turns.
+    pub(crate) fn clock_tick(&mut self) {
+        let before = self.clock.period();
+        self.clock.minutes += u64::from(self.world.config.clock.minutes_per_tick);
+        let period = self.clock.period();
+        if period != before {
+            self.broadcast_outdoors(None, period.announcement());
+        }
+    }
+
+    /// `time`
+    pub fn time(&self, addr: &SocketAddr) -> Result<(), String> {
+        let session = self.session(addr)?;
+        let mut msg = format!(
+            "It is {:02}:{:02} on day {}, {}.",
+            self.clock.hour(),
+            self.clock.minute(),
+            self.clock.day(),
+            self.clock.period().name()
+        );
+        let location = &self.world.locations[&session.location];
+        match self.world.area_weather(&location.area) {
+            Some(weather) if location.outdoors => {
+                msg.push_str(&format!("\n{}", weather.description))
+            }
+            _ => {}
+        }
+        self.send_to(addr, Response::game_msg(&msg));
+        Ok(())
+    }
+}
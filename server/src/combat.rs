@@ -0,0 +1,659 @@
+use std::collections::BTreeMap;
+use std::net::SocketAddr;
+
+use model::{AttackResult, CombatEvent, GameUpdate, Response};
+use rand::seq::SliceRandom;
+use rand::Rng;
+use serde::Deserialize;
+
+use crate::character::Attribute;
+use crate::death::DeathRules;
+use crate::loot::{loot_name, Rarity};
+use crate::npc::{capitalize, NpcId};
+use crate::pvp::Zone;
+use crate::world::LocationId;
+use crate::Shared;
+
+/// Tunable combat formulas, loaded from `data/combat.ron`.
+#[derive(Deserialize, Clone, Debug)]
+pub struct CombatRules {
+    /// Game ticks between combat rounds.
+    pub round_ticks: u32,
+    /// Chance to hit when attack and defense are equal.
+    pub base_hit_chance: f64,
+    /// Added to the hit chance for each point of attack above the defender's defense.
+    pub hit_chance_per_point: f64,
+    pub min_hit_chance: f64,
+    pub max_hit_chance: f64,
+    /// Inclusive range of damage before bonuses.
+    pub base_damage: (i32, i32),
+    /// Extra damage for each point of attack.
+    pub damage_per_attack: f64,
+    pub critical_chance: f64,
+    pub critical_multiplier: f64,
+    pub flee_chance: f64,
+    /// Experience for killing a level 1 enemy, multiplied by the enemy's level.
+    pub experience_per_level: u64,
+    /// Adjusts the experience reward for each level the victim is above or below the killer.
+    pub experience_level_factor: f64,
+    /// Extra experience for each additional group member sharing a kill.
+    pub group_experience_bonus: f64,
+    /// Where players are sent after dying, unless their area has a recall point.
+    pub recall_location: LocationId,
+    pub death: DeathRules,
+    /// Game ticks before a player can turn PvP on or off again.
+    pub pvp_cooldown_ticks: u32,
+}
+
+#[derive(Clone, Copy, Debug, Default)]
+pub struct CombatStats {
+    pub level: u32,
+    pub attack: i32,
+    pub defense: i32,
+}
+
+impl CombatRules {
+    /// Checks that every roll the rules call for can be made.
+    pub fn validate(&self) -> Result<(), String> {
+        let (min, max) = self.base_damage;
+        if min > max {
+            return Err(format!(
+                "Combat base damage ({min}, {max}) is an empty roll"
+            ));
+        }
+        let chances = [
+            ("base_hit_chance", self.base_hit_chance),
+            ("min_hit_chance", self.min_hit_chance),
+            ("max_hit_chance", self.max_hit_chance),
+            ("critical_chance", self.critical_chance),
+            ("flee_chance", self.flee_chance),
+        ];
+        if let Some((name, _)) = chances
+            .iter()
+            .find(|(_, chance)| !(0.0..=1.0).contains(chance))
+        {
+            return Err(format!("Combat {name} must be between 0 and 1"));
+        }
+        if self.min_hit_chance > self.max_hit_chance {
+            return Err("Combat min_hit_chance is above max_hit_chance".to_string());
+        }
+        Ok(())
+    }
+
+    pub fn hit_chance(&self, attacker: &CombatStats, defender: &CombatStats) -> f64 {
+        let chance = self.base_hit_chance
+            + self.hit_chance_per_point * (attacker.attack - defender.defense) as f64;
+        chance.clamp(self.min_hit_chance, self.max_hit_chance)
+    }
+
+    pub fn roll_damage<R: Rng>(&self, attacker: &CombatStats, rng: &mut R) -> i32 {
+        let (min, max) = self.base_damage;
+        let bonus = (self.damage_per_attack * attacker.attack as f64).round() as i32;
+        (rng.gen_range(min..=max) + bonus).max(1)
+    }
+
+    /// Resolves a single attack. All randomness comes from `rng`, so results are
+    /// reproducible with a seeded generator.
+    pub fn resolve_attack<R: Rng>(
+        &self,
+        attacker: &CombatStats,
+        defender: &CombatStats,
+        rng: &mut R,
+    ) -> AttackResult {
+        if !rng.gen_bool(self.hit_chance(attacker, defender)) {
+            return AttackResult::Miss;
+        }
+
+        let damage = self.roll_damage(attacker, rng);
+        if rng.gen_bool(self.critical_chance) {
+            AttackResult::Critical {
+                damage: (damage as f64 * self.critical_multiplier).round() as i32,
+            }
+        } else {
+            AttackResult::Hit { damage }
+        }
+    }
+
+    pub fn experience_for(&self, killer_level: u32, victim_level: u32) -> u64 {
+        let difference = victim_level as f64 - killer_level as f64;
+        let factor = (1.0 + difference * self.experience_level_factor).max(0.0);
+        (self.experience_per_level as f64 * victim_level as f64 * factor).round() as u64
+    }
+}
+
+/// Anything that can take part in a fight.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub enum Combatant {
+    Player(SocketAddr),
+    Npc(NpcId),
+}
+
+impl Shared {
+    pub(crate) fn combatant_exists(&self, c: Combatant) -> bool {
+        match c {
+            Combatant::Player(addr) => self.peers.contains_key(&addr),
+            Combatant::Npc(id) => self.world.npcs.contains_key(&id),
+        }
+    }
+
+    pub(crate) fn combatant_name(&self, c: Combatant) -> String {
+        match c {
+            Combatant::Player(addr) => self.peers[&addr].name.clone(),
+            Combatant::Npc(id) => self.world.npc_template(id).name.clone(),
+        }
+    }
+
+    pub(crate) fn combatant_location(&self, c: Combatant) -> LocationId {
+        match c {
+            Combatant::Player(addr) => self.peers[&addr].location.clone(),
+            Combatant::Npc(id) => self.world.npcs[&id].location.clone(),
+        }
+    }
+
+    /// Current and maximum hit points.
+    pub(crate) fn combatant_hp(&self, c: Combatant) -> (i32, i32) {
+        match c {
+            Combatant::Player(addr) => {
+                let character = &self.peers[&addr].character;
+                (character.hp, character.max_hp)
+            }
+            Combatant::Npc(id) => (
+                self.world.npcs[&id].hp,
+                self.world.npc_template(id).stats.max_hp,
+            ),
+        }
+    }
+
+    fn combatant_stats(&self, c: Combatant) -> CombatStats {
+        let (attack_bonus, defense_bonus) = self.affect_bonuses(c);
+        let stats = match c {
+            Combatant::Player(addr) => {
+                let session = &self.peers[&addr];
+                let (attack, defense) = session
+                    .equipment
+                    .values()
+                    .map(|id| self.world.template(*id))
+                    .fold((0, 0), |(a, d), t| (a + t.attack, d + t.defense));
+                let character = &session.character;
+                let level = character.level as i32;
+                CombatStats {
+                    level: character.level,
+                    attack: level + character.attributes.modifier(Attribute::Strength) + attack,
+                    defense: level / 2
+                        + character.attributes.modifier(Attribute::Dexterity)
+                        + defense,
+                }
+            }
+            Combatant::Npc(id) => {
+                let (attack, defense) = self.world.npcs[&id]
+                    .equipment
+                    .iter()
+                    .map(|id| self.world.template(*id))
+                    .fold((0, 0), |(a, d), t| (a + t.attack, d + t.defense));
+                let stats = &self.world.npc_template(id).stats;
+                CombatStats {
+                    level: stats.level,
+                    attack: stats.attack + attack,
+                    defense: stats.defense + defense,
+                }
+            }
+        };
+        CombatStats {
+            attack: stats.attack + attack_bonus,
+            defense: stats.defense + defense_bonus,
+            ..stats
+        }
+    }
+
+    pub(crate) fn damage(&mut self, c: Combatant, amount: i32) {
+        match c {
+            Combatant::Player(addr) => {
+                self.peers.get_mut(&addr).unwrap().character.hp -= amount;
+                self.send_vitals(&addr);
+            }
+            Combatant::Npc(id) => self.world.npcs.get_mut(&id).unwrap().hp -= amount,
+        }
+    }
+
+    /// Restores hit points, up to the maximum. Returns how many were restored.
+    pub(crate) fn heal(&mut self, c: Combatant, amount: i32) -> i32 {
+        let (hp, max_hp) = self.combatant_hp(c);
+        let amount = amount.min(max_hp - hp).max(0);
+        self.damage(c, -amount);
+        amount
+    }
+
+    /// Finds an NPC or, failing that, a player in `location` matching `keyword`.
+    pub(crate) fn find_combatant(&self, location: &str, keyword: &str) -> Option<Combatant> {
+        self.world
+            .find_npc(location, keyword)
+            .map(Combatant::Npc)
+            .or_else(|| {
+                self.find_user_in_location(location, keyword)
+                    .map(Combatant::Player)
+            })
+    }
+
+    pub(crate) fn is_fighting(&self, c: Combatant) -> bool {
+        self.fights.contains_key(&c)
+    }
+
+    /// Makes `attacker` start attacking `target`, who fights back if not already busy.
+    pub(crate) fn start_fight(&mut self, attacker: Combatant, target: Combatant) {
+        self.fights.insert(attacker, target);
+        self.fights.entry(target).or_insert(attacker);
+    }
+
+    /// Stops `c` fighting and stops anyone attacking it.
+    pub(crate) fn end_fights(&mut self, c: Combatant) {
+        self.fights
+            .retain(|attacker, target| *attacker != c && *target != c);
+    }
+
+    /// `kill <target>`, also used for `attack`.
+    pub fn attack(&mut self, addr: &SocketAddr, args: &[&str]) -> Result<(), String> {
+        let target_kw = args.first().ok_or("Attack whom?")?;
+        let player = Combatant::Player(*addr);
+        let location = self.session(addr)?.location.clone();
+
+        if self.is_fighting(player) {
+            return Err("You are already fighting!".to_string());
+        }
+
+        let target = self
+            .find_combatant(&location, target_kw)
+            .ok_or(format!("You don't see {target_kw} here."))?;
+        if target == player {
+            return Err("You can't attack yourself.".to_string());
+        }
+        self.check_attack(player, target)?;
+
+        self.announce_attack(player, target);
+        self.start_fight(player, target);
+        Ok(())
+    }
+
+    pub(crate) fn announce_attack(&self, attacker: Combatant, target: Combatant) {
+        let location = self.combatant_location(attacker);
+        let attacker_name = self.combatant_name(attacker);
+        let target_name = self.combatant_name(target);
+        self.broadcast_location(
+            &location,
+            &capitalize(&format!("{attacker_name} attacks {target_name}!")),
+            &[],
+        );
+    }
+
+    /// `flee`
+    pub fn flee(&mut self, addr: &SocketAddr) -> Result<(), String> {
+        let player = Combatant::Player(*addr);
+        if !self.is_fighting(player) {
+            return Err("You aren't fighting anyone.".to_string());
+        }
+
+        let session = self.session(addr)?;
+        let location = session.location.clone();
+        let name = session.name.clone();
+        let exits = self.world.locations[&location]
+            .exits
+            .keys()
+            .copied()
+            .filter(|dir| {
+                self.world.check_exit(&location, *dir).is_ok()
+                    && self.blocking_guard(&location, *dir).is_none()
+            })
+            .collect::<Vec<_>>();
+
+        let escaped = self.rng.gen_bool(self.world.combat.flee_chance);
+        let Some(dir) = exits.choose(&mut self.rng).copied().filter(|_| escaped) else {
+            self.send_to(addr, Response::game_msg("You fail to escape!"));
+            self.broadcast_location(
+                &location,
+                &format!("{name} tries to flee but fails."),
+                &[*addr],
+            );
+            return Ok(());
+        };
+
+        self.end_fights(player);
+        self.send_to(addr, Response::game_msg("You flee head over heels!"));
+        self.broadcast_location(&location, &format!("{name} flees!"), &[*addr]);
+        self.move_user(addr, dir)
+    }
+
+    /// Resolves a combat round once every `round_ticks` game ticks.
+    pub(crate) fn combat_tick(&mut self) {
+        self.combat_timer += 1;
+        if self.combat_timer < self.world.combat.round_ticks {
+            return;
+        }
+        self.combat_timer = 0;
+
+        let mut attackers = self.fights.keys().copied().collect::<Vec<_>>();
+        attackers.sort();
+
+        let mut events: BTreeMap<LocationId, Vec<CombatEvent>> = BTreeMap::new();
+        let mut deaths = vec![];
+
+        for attacker in attackers {
+            let Some(target) = self.fights.get(&attacker).copied() else {
+                continue;
+            };
+            let dead = |c| deaths.iter().any(|(victim, _)| *victim == c);
+            if dead(attacker) || dead(target) {
+                continue;
+            }
+            if !self.combatant_exists(attacker) {
+                self.fights.remove(&attacker);
+                continue;
+            }
+
+            let location = self.combatant_location(attacker);
+            if !self.combatant_exists(target) || self.combatant_location(target) != location {
+                self.fights.remove(&attacker);
+                continue;
+            }
+
+            let result = self.world.combat.resolve_attack(
+                &self.combatant_stats(attacker),
+                &self.combatant_stats(target),
+                &mut self.rng,
+            );
+            if let AttackResult::Hit { damage } | AttackResult::Critical { damage } = result {
+                self.damage(target, damage);
+            }
+
+            let (hp, max_hp) = self.combatant_hp(target);
+            events.entry(location).or_default().push(CombatEvent {
+                attacker: self.combatant_name(attacker),
+                defender: self.combatant_name(target),
+                result,
+                defender_hp: hp,
+                defender_max_hp: max_hp,
+            });
+
+            if hp <= 0 {
+                deaths.push((target, attacker));
+            }
+        }
+
+        for (location, events) in events {
+            let res = Response::Game(GameUpdate::CombatRound(events));
+            for (_, session) in self.users_in_location(&location) {
+                let _ = session.send.send(res.clone());
+            }
+        }
+
+        for (victim, killer) in deaths {
+            self.kill(victim, Some(killer));
+        }
+    }
+
+    /// Drops an NPC's equipment and loot, handing it straight to a party member if the
+    /// party's loot rule says so.
+    fn drop_loot(&mut self, id: NpcId, killer: Option<Combatant>) {
+        let location = self.world.npcs[&id].location.clone();
+        let name = capitalize(&self.world.npc_template(id).name);
+
+        let mut items = std::mem::take(&mut self.world.npcs.get_mut(&id).unwrap().equipment)
+            .into_iter()
+            .map(|item| (item, Rarity::Common))
+            .collect::<Vec<_>>();
+        if let Some(table) = self.world.npc_template(id).loot.clone() {
+            let player = match killer {
+                Some(Combatant::Player(addr)) => Some(addr),
+                _ => None,
+            };
+            items.extend(self.roll_loot_for(&table, player.as_ref()));
+        }
+
+        for (item, rarity) in items {
+            let item_name = loot_name(self.world.item_name(item), rarity);
+
+            let recipient = match killer {
+                Some(Combatant::Player(addr)) => self.loot_recipient(&addr),
+                _ => None,
+            };
+            match recipient {
+                Some(member) => {
+                    let member_name = self.peers[&member].name.clone();
+                    self.peers.get_mut(&member).unwrap().inventory.push(item);
+                    self.send_to(
+                        &member,
+                        Response::game_msg(&format!("You receive {item_name}.")),
+                    );
+                    self.broadcast_location(
+                        &location,
+                        &format!("{member_name} receives {item_name}."),
+                        &[member],
+                    );
+                    let _ = self.send_inventory(&member);
+                }
+                None => {
+                    self.world
+                        .locations
+                        .get_mut(&location)
+                        .unwrap()
+                        .items
+                        .push(item);
+                    self.broadcast_location(&location, &format!("{name} drops {item_name}."), &[]);
+                }
+            }
+        }
+    }
+
+    /// Kills `victim`, rewarding `killer` if it was a player.
+    pub(crate) fn kill(&mut self, victim: Combatant, killer: Option<Combatant>) {
+        let location = self.combatant_location(victim);
+        let victim_name = self.combatant_name(victim);
+        let victim_level = self.combatant_stats(victim).level;
+        let victim_template = match victim {
+            Combatant::Npc(id) => Some(self.world.npcs[&id].template.clone()),
+            Combatant::Player(_) => None,
+        };
+        self.end_fights(victim);
+        self.affects.remove(&victim);
+
+        self.broadcast_location(
+            &location,
+            &capitalize(&format!("{victim_name} is DEAD!")),
+            &[],
+        );
+
+        if let Some(Combatant::Player(addr)) = killer {
+            self.share_experience(&addr, victim_level);
+            if let Some(template) = &victim_template {
+                for member in self.party_members_here(&addr) {
+                    self.quest_kill(&member, template);
+                    self.reputation_kill(&member, template);
+                }
+            }
+        }
+
+        match victim {
+            Combatant::Npc(id) => {
+                self.drop_loot(id, killer);
+                self.world.remove_npc(id);
+            }
+            Combatant::Player(addr) => {
+                let arena = self.world.zone(&location) == Zone::Arena;
+                let lost = match arena {
+                    true => 0,
+                    false => {
+                        self.leave_corpse(&addr, killer);
+                        self.death_penalty(&addr)
+                    }
+                };
+                let recall = self.world.recall_point(&location);
+                let session = self.peers.get_mut(&addr).unwrap();
+                session.character.hp = session.character.max_hp;
+                session.location = recall.clone();
+                let name = session.name.clone();
+
+                let mut msg = String::from("You have been KILLED!");
+                if lost > 0 {
+                    msg.push_str(&format!("\nYou lose {lost} experience."));
+                }
+                self.send_to(&addr, Response::game_msg(&msg));
+                self.broadcast_location(
+                    &recall,
+                    &format!("{name} appears in a flash of light."),
+                    &[addr],
+                );
+                let _ = self.look(&addr, &[]);
+                let _ = self.send_inventory(&addr);
+                self.send_vitals(&addr);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use rand::rngs::StdRng;
+    use rand::SeedableRng;
+
+    use super::*;
+
+    fn rules() -> CombatRules {
+        ron::from_str(
+            r#"(
+                round_ticks: 1,
+                base_hit_chance: 0.7,
+                hit_chance_per_point: 0.03,
+                min_hit_chance: 0.05,
+                max_hit_chance: 0.95,
+                base_damage: (1, 4),
+                damage_per_attack: 0.5,
+                critical_chance: 0.05,
+                critical_multiplier: 2.0,
+                flee_chance: 0.5,
+                experience_per_level: 20,
+                experience_level_factor: 0.1,
+                group_experience_bonus: 0.2,
+                recall_location: "village:square",
+                death: (corpse_item: "corpse", corpse_decay_ticks: 600),
+                pvp_cooldown_ticks: 150,
+            )"#,
+        )
+        .unwrap()
+    }
+
+    const ATTACKER: CombatStats = CombatStats {
+        level: 1,
+        attack: 4,
+        defense: 0,
+    };
+    const DEFENDER: CombatStats = CombatStats {
+        level: 1,
+        attack: 0,
+        defense: 2,
+    };
+
+    fn attacks(rules: &CombatRules, seed: u64, n: usize) -> Vec<AttackResult> {
+        let mut rng = StdRng::seed_from_u64(seed);
+        (0..n)
+            .map(|_| rules.resolve_attack(&ATTACKER, &DEFENDER, &mut rng))
+            .collect()
+    }
+
+    #[test]
+    fn same_seed_same_fight() {
+        let rules = rules();
+        assert_eq!(attacks(&rules, 7, 100), attacks(&rules, 7, 100));
+    }
+
+    #[test]
+    fn hit_chance_is_clamped() {
+        let rules = rules();
+        let strong = CombatStats {
+            attack: 100,
+            ..ATTACKER
+        };
+        let weak = CombatStats {
+            attack: -100,
+            ..ATTACKER
+        };
+        assert_eq!(rules.hit_chance(&strong, &DEFENDER), 0.95);
+        assert_eq!(rules.hit_chance(&weak, &DEFENDER), 0.05);
+    }
+
+    #[test]
+    fn certain_misses_and_hits() {
+        let never = CombatRules {
+            min_hit_chance: 0.0,
+            max_hit_chance: 0.0,
+            ..rules()
+        };
+        assert!(attacks(&never, 1, 200)
+            .iter()
+            .all(|result| *result == AttackResult::Miss));
+
+        let always = CombatRules {
+            min_hit_chance: 1.0,
+            max_hit_chance: 1.0,
+            critical_chance: 0.0,
+            ..rules()
+        };
+        assert!(attacks(&always, 1, 200)
+            .iter()
+            .all(|result| matches!(result, AttackResult::Hit { .. })));
+
+        let critical = CombatRules {
+            min_hit_chance: 1.0,
+            max_hit_chance: 1.0,
+            critical_chance: 1.0,
+            ..rules()
+        };
+        assert!(attacks(&critical, 1, 200)
+            .iter()
+            .all(|result| matches!(result, AttackResult::Critical { .. })));
+    }
+
+    #[test]
+    fn damage_stays_in_bounds() {
+        let rules = rules();
+        // base 1..=4 plus half the attacker's attack of 4
+        let (min, max) = (3, 6);
+        let results = attacks(&rules, 42, 2000);
+        for result in &results {
+            match *result {
+                AttackResult::Miss => {}
+                AttackResult::Hit { damage } => assert!((min..=max).contains(&damage)),
+                AttackResult::Critical { damage } => {
+                    assert!((min * 2..=max * 2).contains(&damage))
+                }
+            }
+        }
+        assert!(results.contains(&AttackResult::Hit { damage: min }));
+        assert!(results.contains(&AttackResult::Hit { damage: max }));
+        assert!(results.contains(&AttackResult::Miss));
+        assert!(results
+            .iter()
+            .any(|result| matches!(result, AttackResult::Critical { .. })));
+    }
+
+    #[test]
+    fn experience_scales_with_level_difference() {
+        let rules = rules();
+        assert_eq!(rules.experience_for(1, 1), 20);
+        assert_eq!(rules.experience_for(1, 3), 72);
+        assert_eq!(rules.experience_for(5, 3), 48);
+        assert_eq!(rules.experience_for(30, 1), 0);
+    }
+
+    #[test]
+    fn validation_catches_impossible_rolls() {
+        assert!(rules().validate().is_ok());
+        let empty = CombatRules {
+            base_damage: (5, 1),
+            ..rules()
+        };
+        assert!(empty.validate().is_err());
+        let chance = CombatRules {
+            flee_chance: 1.5,
+            ..rules()
+        };
+        assert!(chance.validate().is_err());
+    }
+}
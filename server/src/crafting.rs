@@ -0,0 +1,270 @@
+use std::collections::{BTreeMap, HashSet};
+use std::net::SocketAddr;
+
+use model::Response;
+use rand::Rng;
+use serde::Deserialize;
+
+use crate::character::Attribute;
+use crate::instance::base_location;
+use crate::item::ItemId;
+use crate::world::{LocationId, World};
+use crate::Shared;
+
+/// How crafting works, from `crafting.ron`.
+#[derive(Deserialize, Clone, Debug)]
+pub struct CraftingRules {
+    /// The highest crafting skill a character can reach.
+    pub max_skill: u32,
+    /// Added to a recipe's success chance for each point of skill beyond what it needs.
+    pub chance_per_skill: f64,
+    /// A successful craft teaches a point of skill unless the crafter's skill is this
+    /// far past what the recipe needs.
+    pub learning_range: u32,
+    pub recipes: Vec<RecipeDef>,
+}
+
+/// Something players can make with `craft`.
+#[derive(Deserialize, Clone, Debug)]
+pub struct RecipeDef {
+    pub id: String,
+    pub name: String,
+    /// Item templates used up, and how many of each.
+    pub inputs: BTreeMap<String, u32>,
+    /// Item templates that must be carried but aren't used up.
+    #[serde(default)]
+    pub tools: Vec<String>,
+    /// The location it has to be made in, if any.
+    #[serde(default)]
+    pub station: Option<LocationId>,
+    /// Crafting skill needed to try it.
+    #[serde(default)]
+    pub skill: u32,
+    /// Item templates made, and how many of each.
+    pub outputs: BTreeMap<String, u32>,
+    /// Chance of success at exactly the skill needed. Failing wastes the inputs.
+    #[serde(default = "always")]
+    pub chance: f64,
+    /// Its modifier counts towards the crafter's skill for the success chance.
+    #[serde(default)]
+    pub attribute: Option<Attribute>,
+}
+
+fn always() -> f64 {
+    1.0
+}
+
+impl RecipeDef {
+    fn matches(&self, name: &str) -> bool {
+        let name = name.to_lowercase();
+        self.id == name || self.name.to_lowercase().contains(&name)
+    }
+}
+
+impl World {
+    /// Checks that recipes only use items and locations that exist.
+    pub(crate) fn validate_crafting(&self) -> Result<(), String> {
+        let mut ids = HashSet::new();
+        for recipe in &self.crafting.recipes {
+            if !ids.insert(&recipe.id) {
+                return Err(format!("Duplicate recipe {}", recipe.id));
+            }
+            if let Some(item) = recipe
+                .inputs
+                .keys()
+                .chain(&recipe.tools)
+                .chain(recipe.outputs.keys())
+                .find(|item| !self.item_templates.contains_key(*item))
+            {
+                return Err(format!("Recipe {} uses unknown item {item}", recipe.id));
+            }
+            if let Some(station) = recipe
+                .station
+                .as_ref()
+                .filter(|station| !self.locations.contains_key(*station))
+            {
+                return Err(format!(
+                    "Recipe {} needs unknown location {station}",
+                    recipe.id
+                ));
+            }
+            if recipe.outputs.is_empty() {
+                return Err(format!("Recipe {} makes nothing", recipe.id));
+            }
+        }
+        Ok(())
+    }
+
+    fn find_recipe(&self, name: &str) -> Option<&RecipeDef> {
+        self.crafting
+            .recipes
+            .iter()
+            .find(|recipe| recipe.matches(name))
+    }
+}
+
+impl Shared {
+    /// Picks out the carried items a recipe will use up, or says what's missing.
+    fn recipe_inputs(&self, addr: &SocketAddr, recipe: &RecipeDef) -> Result<Vec<ItemId>, String> {
+        let inventory = &self.session(addr)?.inventory;
+        let mut used = vec![];
+        for (template, count) in &recipe.inputs {
+            let found = inventory
+                .iter()
+                .filter(|id| self.world.items[*id].template == *template)
+                .take(*count as usize)
+                .copied()
+                .collect::<Vec<_>>();
+            if found.len() < *count as usize {
+                let name = &self.world.item_templates[template].name;
+                return Err(format!("You need {count} x {name} to make that."));
+            }
+            used.extend(found);
+        }
+        for tool in &recipe.tools {
+            if !inventory
+                .iter()
+                .any(|id| self.world.items[id].template == *tool)
+            {
+                let name = &self.world.item_templates[tool].name;
+                return Err(format!("You need {name} to make that."));
+            }
+        }
+        Ok(used)
+    }
+
+    /// `recipes`
+    pub fn list_recipes(&self, addr: &SocketAddr) -> Result<(), String> {
+        let skill = self.session(addr)?.character.crafting;
+        let mut lines = vec![format!("Your crafting skill is {skill}.")];
+        for recipe in &self.world.crafting.recipes {
+            let inputs = recipe
+                .inputs
+                .iter()
+                .map(|(item, count)| format!("{count} x {}", self.world.item_templates[item].name))
+                .collect::<Vec<_>>();
+            let mut line = format!(
+                "{} (skill {}): {}",
+                recipe.name,
+                recipe.skill,
+                inputs.join(", ")
+            );
+            if !recipe.tools.is_empty() {
+                let tools = recipe
+                    .tools
+                    .iter()
+                    .map(|tool| self.world.item_templates[tool].name.as_str())
+                    .collect::<Vec<_>>();
+                line.push_str(&format!("; using {}", tools.join(", ")));
+            }
+            if let Some(station) = &recipe.station {
+                line.push_str(&format!("; at {}", self.world.locations[station].name));
+            }
+            lines.push(line);
+        }
+        self.send_to(addr, Response::game_msg(&lines.join("\n")));
+        Ok(())
+    }
+
+    /// `craft <recipe>`
+    pub fn craft(&mut self, addr: &SocketAddr, args: &[&str]) -> Result<(), String> {
+        if args.is_empty() {
+            return Err("Craft what?".to_string());
+        }
+        let name = args.join(" ");
+        let recipe = self
+            .world
+            .find_recipe(&name)
+            .ok_or(format!("You don't know how to make {name}."))?
+            .clone();
+
+        let session = self.session(addr)?;
+        let character = &session.character;
+        if character.crafting < recipe.skill {
+            return Err(format!(
+                "You need a crafting skill of {} to make that.",
+                recipe.skill
+            ));
+        }
+        if let Some(station) = &recipe.station {
+            if base_location(&session.location) != station {
+                return Err(format!(
+                    "You can only make that at {}.",
+                    self.world.locations[station].name
+                ));
+            }
+        }
+        let inputs = self.recipe_inputs(addr, &recipe)?;
+        let output_weight = recipe
+            .outputs
+            .iter()
+            .map(|(item, count)| self.world.item_templates[item].weight * count)
+            .sum::<u32>();
+        let input_weight = inputs
+            .iter()
+            .map(|id| self.world.item_weight(*id))
+            .sum::<u32>();
+        self.check_capacity(addr, output_weight.saturating_sub(input_weight))?;
+
+        let rules = &self.world.crafting;
+        let bonus = character.crafting - recipe.skill
+            + recipe
+                .attribute
+                .map_or(0, |a| character.attributes.modifier(a).max(0) as u32);
+        let chance = recipe.chance + bonus as f64 * rules.chance_per_skill;
+        let learns = character.crafting < rules.max_skill
+            && character.crafting < recipe.skill + rules.learning_range;
+        let name = session.name.clone();
+        let location = session.location.clone();
+
+        let session = self.peers.get_mut(addr).unwrap();
+        session.inventory.retain(|id| !inputs.contains(id));
+        for id in inputs {
+            self.world.destroy_item(id);
+        }
+
+        if !self.rng.gen_bool(chance.clamp(0.0, 1.0)) {
+            self.send_to(
+                addr,
+                Response::game_msg(&format!(
+                    "You ruin the materials trying to make the {}.",
+                    recipe.name.to_lowercase()
+                )),
+            );
+            self.broadcast_location(
+                &location,
+                &format!("{name} tries to make something, but ruins it."),
+                &[*addr],
+            );
+            return self.send_inventory(addr);
+        }
+
+        let mut made = vec![];
+        for (template, count) in &recipe.outputs {
+            for _ in 0..*count {
+                made.push(self.world.spawn_item(template)?);
+            }
+        }
+        let item_names = made
+            .iter()
+            .map(|id| self.world.item_name(*id).to_string())
+            .collect::<Vec<_>>()
+            .join(", ");
+        let session = self.peers.get_mut(addr).unwrap();
+        session.inventory.extend(made);
+        if learns {
+            session.character.crafting += 1;
+        }
+        let skill = session.character.crafting;
+
+        self.send_to(addr, Response::game_msg(&format!("You make {item_names}.")));
+        self.broadcast_location(&location, &format!("{name} makes {item_names}."), &[*addr]);
+        if learns {
+            self.send_to(
+                addr,
+                Response::game_msg(&format!("Your crafting skill rises to {skill}.")),
+            );
+        }
+        self.send_inventory(addr)
+    }
+}
@@ -0,0 +1,177 @@
+use std::net::SocketAddr;
+
+use serde::Deserialize;
+
+use crate::combat::Combatant;
+use crate::item::{ItemFlag, ItemId};
+use crate::npc::capitalize;
+use crate::world::{LocationId, World};
+use crate::Shared;
+
+/// What happens to players who die, from `combat.ron`.
+#[derive(Deserialize, Clone, Debug)]
+pub struct DeathRules {
+    /// Item template for the corpse a dead player's belongings are left in. It must be
+    /// a container that can't be picked up.
+    pub corpse_item: String,
+    /// Game ticks before a corpse rots away, spilling whatever is left on the floor.
+    pub corpse_decay_ticks: u32,
+    /// Share of the experience earned towards the next level that is lost on dying.
+    #[serde(default)]
+    pub experience_penalty: f64,
+    /// Lets anyone loot the corpse of a player killed by another player. Otherwise only
+    /// its owner can.
+    #[serde(default)]
+    pub pvp_looting: bool,
+}
+
+/// Marks an item as a dead player's corpse.
+#[derive(Clone, Debug)]
+pub struct Corpse {
+    pub owner: String,
+    /// e.g. "the corpse of Ann"
+    pub name: String,
+    /// Game ticks until it rots away.
+    pub decay: u32,
+    /// Anyone may take things from it.
+    pub open: bool,
+}
+
+impl World {
+    /// Checks that the corpse template is usable and every area's recall point exists.
+    pub(crate) fn validate_death(&self) -> Result<(), String> {
+        let corpse = &self.combat.death.corpse_item;
+        let template = self
+            .item_templates
+            .get(corpse)
+            .ok_or(format!("Unknown corpse item {corpse}"))?;
+        if !template.has_flag(ItemFlag::Container) || !template.has_flag(ItemFlag::NoTake) {
+            return Err(format!(
+                "Corpse item {corpse} must be a container that can't be taken"
+            ));
+        }
+        for area in self.areas.values() {
+            if let Some(recall) = area
+                .source
+                .recall
+                .as_ref()
+                .filter(|recall| !self.locations.contains_key(*recall))
+            {
+                return Err(format!(
+                    "Area {} recalls to unknown location {recall}",
+                    area.id
+                ));
+            }
+        }
+        Ok(())
+    }
+
+    /// Where a player who dies in `location` wakes up again.
+    pub fn recall_point(&self, location: &str) -> LocationId {
+        let area = &self.locations[location].area;
+        self.areas[area]
+            .source
+            .recall
+            .clone()
+            .unwrap_or_else(|| self.combat.recall_location.clone())
+    }
+
+    /// Finds the location whose floor `id` is lying on.
+    fn item_location(&self, id: ItemId) -> Option<LocationId> {
+        self.locations
+            .values()
+            .find(|location| location.items.contains(&id))
+            .map(|location| location.id.clone())
+    }
+}
+
+impl Shared {
+    /// Leaves everything a dead player was carrying and wearing in a corpse where they
+    /// fell.
+    pub(crate) fn leave_corpse(&mut self, addr: &SocketAddr, killer: Option<Combatant>) {
+        let rules = self.world.combat.death.clone();
+        let Ok(corpse) = self.world.spawn_item(&rules.corpse_item) else {
+            return;
+        };
+        let session = self.peers.get_mut(addr).unwrap();
+        let contents = session
+            .inventory
+            .drain(..)
+            .chain(std::mem::take(&mut session.equipment).into_values())
+            .collect::<Vec<_>>();
+        let location = session.location.clone();
+        let name = session.name.clone();
+
+        let item = self.world.items.get_mut(&corpse).unwrap();
+        item.contents = contents;
+        item.corpse = Some(Corpse {
+            owner: name.clone(),
+            name: format!("the corpse of {name}"),
+            decay: rules.corpse_decay_ticks,
+            open: rules.pvp_looting && matches!(killer, Some(Combatant::Player(_))),
+        });
+        self.world
+            .locations
+            .get_mut(&location)
+            .unwrap()
+            .items
+            .push(corpse);
+    }
+
+    /// Takes away some of the experience a player has earned towards their next level.
+    pub(crate) fn death_penalty(&mut self, addr: &SocketAddr) -> u64 {
+        let penalty = self.world.combat.death.experience_penalty;
+        let rules = &self.world.character_rules;
+        let Some(session) = self.peers.get_mut(addr) else {
+            return 0;
+        };
+        let character = &mut session.character;
+        let floor = rules.experience_for_level(character.level);
+        let progress = character.experience.saturating_sub(floor);
+        let lost = (progress as f64 * penalty.clamp(0.0, 1.0)).round() as u64;
+        character.experience -= lost;
+        lost
+    }
+
+    /// Stops players taking things from corpses that aren't theirs to loot.
+    pub(crate) fn check_corpse_access(
+        &self,
+        addr: &SocketAddr,
+        container: ItemId,
+    ) -> Result<(), String> {
+        let Some(corpse) = &self.world.items[&container].corpse else {
+            return Ok(());
+        };
+        if corpse.open || corpse.owner == self.session(addr)?.name {
+            return Ok(());
+        }
+        Err(format!("You can't loot {}.", corpse.name))
+    }
+
+    /// Rots away corpses whose time is up, leaving what was in them on the floor.
+    pub(crate) fn corpse_tick(&mut self) {
+        let mut rotted = vec![];
+        for item in self.world.items.values_mut() {
+            if let Some(corpse) = &mut item.corpse {
+                corpse.decay = corpse.decay.saturating_sub(1);
+                if corpse.decay == 0 {
+                    rotted.push(item.id);
+                }
+            }
+        }
+        rotted.sort();
+
+        for id in rotted {
+            let Some(location) = self.world.item_location(id) else {
+                continue;
+            };
+            let name = self.world.item_name(id).to_string();
+            let contents = std::mem::take(&mut self.world.items.get_mut(&id).unwrap().contents);
+            let floor = &mut self.world.locations.get_mut(&location).unwrap().items;
+            floor.retain(|item| *item != id);
+            floor.extend(contents);
+            self.world.destroy_item(id);
+            self.broadcast_location(&location, &format!("{} rots away.", capitalize(&name)), &[]);
+        }
+    }
+}
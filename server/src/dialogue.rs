@@ -0,0 +1,304 @@
+use std::collections::BTreeMap;
+use std::net::SocketAddr;
+
+use model::{DialogueView, GameUpdate, Response};
+use serde::{Deserialize, Serialize};
+
+use crate::character::Attribute;
+use crate::npc::{capitalize, NpcId};
+use crate::world::World;
+use crate::{Shared, UserSession};
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub enum Condition {
+    /// The player is working on the quest.
+    QuestActive(String),
+    /// The player has turned the quest in.
+    QuestDone(String),
+    /// The player has neither started nor finished the quest.
+    QuestNotStarted(String),
+    /// The player is carrying an item made from this template.
+    HasItem(String),
+    Stat {
+        attribute: Attribute,
+        min: i32,
+    },
+    MinLevel(u32),
+    Flag(String),
+    NotFlag(String),
+    /// The player's reputation with a faction is at least `min`.
+    Reputation {
+        faction: String,
+        min: i32,
+    },
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub enum DialogueEffect {
+    GiveItem(String),
+    StartQuest(String),
+    /// Sets a flag on the player's character, remembered between sessions.
+    SetFlag(String),
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct DialogueChoice {
+    pub text: String,
+    /// All must hold for the choice to be shown.
+    #[serde(default)]
+    pub conditions: Vec<Condition>,
+    #[serde(default)]
+    pub effects: Vec<DialogueEffect>,
+    /// The node to go to, or `None` to end the conversation.
+    #[serde(default)]
+    pub next: Option<String>,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct DialogueNode {
+    /// What the NPC says on reaching this node.
+    pub text: String,
+    #[serde(default)]
+    pub choices: Vec<DialogueChoice>,
+}
+
+/// A branching conversation as written in an area file.
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct DialogueTree {
+    pub id: String,
+    pub start: String,
+    pub nodes: BTreeMap<String, DialogueNode>,
+}
+
+/// Where a player is in a conversation with an NPC.
+pub struct Conversation {
+    pub npc: NpcId,
+    pub tree: String,
+    /// Indices into the current node's choices, in the order they were shown.
+    pub choices: Vec<usize>,
+    pub node: String,
+}
+
+impl World {
+    /// Checks that dialogue trees only refer to things that exist.
+    pub(crate) fn validate_dialogues(&self) -> Result<(), String> {
+        for template in self.npc_templates.values() {
+            if let Some(tree) = &template.dialogue_tree {
+                if !self.dialogues.contains_key(tree) {
+                    return Err(format!(
+                        "NPC {} has unknown dialogue tree {tree}",
+                        template.id
+                    ));
+                }
+            }
+        }
+
+        for tree in self.dialogues.values() {
+            let err = |what: String| Err(format!("Dialogue tree {}: {what}", tree.id));
+            if !tree.nodes.contains_key(&tree.start) {
+                return err(format!("unknown start node {}", tree.start));
+            }
+
+            for choice in tree.nodes.values().flat_map(|node| &node.choices) {
+                if let Some(next) = &choice.next {
+                    if !tree.nodes.contains_key(next) {
+                        return err(format!("unknown node {next}"));
+                    }
+                }
+                for effect in &choice.effects {
+                    match effect {
+                        DialogueEffect::GiveItem(item)
+                            if !self.item_templates.contains_key(item) =>
+                        {
+                            return err(format!("unknown item {item}"))
+                        }
+                        DialogueEffect::StartQuest(quest) if !self.quests.contains_key(quest) => {
+                            return err(format!("unknown quest {quest}"))
+                        }
+                        _ => {}
+                    }
+                }
+            }
+        }
+        Ok(())
+    }
+
+    fn condition_holds(&self, session: &UserSession, condition: &Condition) -> bool {
+        match condition {
+            Condition::QuestActive(id) => session.quests.active.contains_key(id),
+            Condition::QuestDone(id) => session.quests.completed.contains(id),
+            Condition::QuestNotStarted(id) => {
+                !session.quests.active.contains_key(id) && !session.quests.completed.contains(id)
+            }
+            Condition::HasItem(template) => session
+                .inventory
+                .iter()
+                .chain(session.equipment.values())
+                .any(|id| &self.items[id].template == template),
+            Condition::Stat { attribute, min } => {
+                session.character.attributes.get(*attribute) >= *min
+            }
+            Condition::MinLevel(level) => session.character.level >= *level,
+            Condition::Flag(flag) => session.flags.contains(flag),
+            Condition::NotFlag(flag) => !session.flags.contains(flag),
+            Condition::Reputation { faction, min } => {
+                self.reputation(&session.character, faction) >= *min
+            }
+        }
+    }
+}
+
+impl Shared {
+    /// Starts a conversation with an NPC that has a dialogue tree.
+    pub(crate) fn start_dialogue(&mut self, addr: &SocketAddr, npc: NpcId) -> Result<(), String> {
+        let tree = self
+            .world
+            .npc_template(npc)
+            .dialogue_tree
+            .clone()
+            .ok_or("They have nothing to say.")?;
+        let start = self.world.dialogues[&tree].start.clone();
+
+        self.peers
+            .get_mut(addr)
+            .ok_or("No session for address")?
+            .conversation = Some(Conversation {
+            npc,
+            tree,
+            choices: vec![],
+            node: start.clone(),
+        });
+        self.enter_dialogue_node(addr, &start)
+    }
+
+    fn enter_dialogue_node(&mut self, addr: &SocketAddr, node_id: &str) -> Result<(), String> {
+        let session = self.session(addr)?;
+        let conversation = session
+            .conversation
+            .as_ref()
+            .ok_or("You aren't talking to anyone.")?;
+        let node = &self.world.dialogues[&conversation.tree].nodes[node_id];
+        let npc = conversation.npc;
+
+        let choices = node
+            .choices
+            .iter()
+            .enumerate()
+            .filter(|(_, choice)| {
+                choice
+                    .conditions
+                    .iter()
+                    .all(|condition| self.world.condition_holds(session, condition))
+            })
+            .map(|(i, _)| i)
+            .collect::<Vec<_>>();
+        let view = DialogueView {
+            npc: capitalize(&self.world.npc_template(npc).name),
+            text: node.text.clone(),
+            choices: choices
+                .iter()
+                .map(|i| node.choices[*i].text.clone())
+                .collect(),
+        };
+
+        let session = self.peers.get_mut(addr).unwrap();
+        if choices.is_empty() {
+            session.conversation = None;
+        } else if let Some(conversation) = &mut session.conversation {
+            conversation.node = node_id.to_string();
+            conversation.choices = choices;
+        }
+        self.send_to(addr, Response::Game(GameUpdate::Dialogue(view)));
+        Ok(())
+    }
+
+    /// A bare number picks that choice in the current conversation.
+    pub fn choose_dialogue(&mut self, addr: &SocketAddr, number: usize) -> Result<(), String> {
+        let session = self.session(addr)?;
+        let conversation = session
+            .conversation
+            .as_ref()
+            .ok_or("You aren't talking to anyone.")?;
+
+        let npc = conversation.npc;
+        let here = self
+            .world
+            .npcs
+            .get(&npc)
+            .is_some_and(|n| n.location == session.location);
+        if !here {
+            self.peers.get_mut(addr).unwrap().conversation = None;
+            return Err("There's nobody here to talk to.".to_string());
+        }
+
+        let index = number
+            .checked_sub(1)
+            .and_then(|i| conversation.choices.get(i))
+            .ok_or(format!("There is no choice {number}."))?;
+        let choice = self.world.dialogues[&conversation.tree].nodes[&conversation.node].choices
+            [*index]
+            .clone();
+        // things may have changed since the choices were shown
+        if !choice
+            .conditions
+            .iter()
+            .all(|condition| self.world.condition_holds(session, condition))
+        {
+            return Err(format!("There is no choice {number}."));
+        }
+
+        for effect in &choice.effects {
+            self.apply_dialogue_effect(addr, effect)?;
+        }
+
+        match &choice.next {
+            Some(next) => self.enter_dialogue_node(addr, next),
+            None => {
+                self.peers.get_mut(addr).unwrap().conversation = None;
+                self.send_to(
+                    addr,
+                    Response::Game(GameUpdate::Dialogue(DialogueView {
+                        npc: capitalize(&self.world.npc_template(npc).name),
+                        text: String::new(),
+                        choices: vec![],
+                    })),
+                );
+                Ok(())
+            }
+        }
+    }
+
+    fn apply_dialogue_effect(
+        &mut self,
+        addr: &SocketAddr,
+        effect: &DialogueEffect,
+    ) -> Result<(), String> {
+        match effect {
+            DialogueEffect::GiveItem(template) => {
+                let id = self.world.spawn_item(template)?;
+                let name = self.world.item_name(id).to_string();
+                self.peers
+                    .get_mut(addr)
+                    .ok_or("No session for address")?
+                    .inventory
+                    .push(id);
+                self.send_to(addr, Response::game_msg(&format!("You receive {name}.")));
+                self.send_inventory(addr)
+            }
+            DialogueEffect::StartQuest(id) => {
+                if self.world.can_accept(self.session(addr)?, id).is_ok() {
+                    self.start_quest(addr, id)?;
+                }
+                Ok(())
+            }
+            DialogueEffect::SetFlag(flag) => {
+                self.peers
+                    .get_mut(addr)
+                    .ok_or("No session for address")?
+                    .flags
+                    .insert(flag.clone());
+                Ok(())
+            }
+        }
+    }
+}
@@ -0,0 +1,460 @@
+use std::net::SocketAddr;
+
+use model::Response;
+use serde::{Deserialize, Serialize};
+
+use crate::character::Attribute;
+use crate::combat::Combatant;
+use crate::world::{is_default, Direction, LocationId, World};
+use crate::Shared;
+
+/// A door across an exit, as written in an area file. It only needs writing on one side
+/// of the exit; the way back shares it.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct DoorDef {
+    /// What it's called, e.g. "door" or "trapdoor". Players can use any word of it.
+    pub name: String,
+    /// Whether it starts closed, and is closed again whenever its area resets.
+    #[serde(default, skip_serializing_if = "is_default")]
+    pub closed: bool,
+    /// Whether it starts locked, and is locked again whenever its area resets.
+    #[serde(default, skip_serializing_if = "is_default")]
+    pub locked: bool,
+    /// Item template that locks and unlocks it.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub key: Option<String>,
+    /// Difficulty of picking the lock, or `None` if it can't be picked.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub pick: Option<i32>,
+}
+
+impl DoorDef {
+    fn matches(&self, keyword: &str) -> bool {
+        let keyword = keyword.to_lowercase();
+        self.name
+            .split_whitespace()
+            .any(|word| word.starts_with(&keyword))
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct DoorState {
+    pub closed: bool,
+    pub locked: bool,
+}
+
+impl World {
+    /// The exit leading back from wherever `location`'s `dir` exit goes, preferring the
+    /// opposite direction.
+    pub fn reverse_exit(&self, location: &str, dir: Direction) -> Option<(LocationId, Direction)> {
+        let to = &self.locations[location].exits.get(&dir)?.to;
+        let back = &self.locations[to].exits;
+        let reverse = if back
+            .get(&dir.opposite())
+            .is_some_and(|exit| exit.to == location)
+        {
+            dir.opposite()
+        } else {
+            *back.iter().find(|(_, exit)| exit.to == location)?.0
+        };
+        Some((to.clone(), reverse))
+    }
+
+    /// Gives the way back through every door the same door, and checks their keys exist.
+    pub(crate) fn link_doors(&mut self) -> Result<(), String> {
+        let mut doors = vec![];
+        for location in self.locations.values() {
+            for (dir, exit) in &location.exits {
+                let Some(door) = &exit.door else {
+                    continue;
+                };
+                if let Some(key) = door
+                    .key
+                    .as_ref()
+                    .filter(|key| !self.item_templates.contains_key(*key))
+                {
+                    return Err(format!(
+                        "Door {} from {} needs unknown key {key}",
+                        dir.name(),
+                        location.id
+                    ));
+                }
+                if door.locked && !door.closed {
+                    return Err(format!(
+                        "Door {} from {} is locked but open",
+                        dir.name(),
+                        location.id
+                    ));
+                }
+                if let Some(reverse) = self.reverse_exit(&location.id, *dir) {
+                    doors.push((reverse, door.clone()));
+                }
+            }
+        }
+
+        for ((location, dir), door) in doors {
+            let exit = self
+                .locations
+                .get_mut(&location)
+                .unwrap()
+                .exits
+                .get_mut(&dir)
+                .unwrap();
+            exit.door.get_or_insert(door);
+        }
+        Ok(())
+    }
+
+    /// Sets a door's state on both sides.
+    pub fn set_door(&mut self, location: &str, dir: Direction, state: DoorState) {
+        let reverse = self.reverse_exit(location, dir);
+        self.locations
+            .get_mut(location)
+            .unwrap()
+            .doors
+            .insert(dir, state);
+        if let Some((to, back)) = reverse {
+            self.locations
+                .get_mut(&to)
+                .unwrap()
+                .doors
+                .insert(back, state);
+        }
+    }
+
+    /// Puts every door and hidden exit in an area back the way the area file has them.
+    pub fn reset_doors(&mut self, area: &str) {
+        let mut doors = vec![];
+        for location in self.locations.values_mut().filter(|l| l.area == area) {
+            location.hidden = location
+                .exits
+                .iter()
+                .filter(|(_, exit)| exit.hidden.is_some())
+                .map(|(dir, _)| *dir)
+                .collect();
+            for (dir, exit) in &location.exits {
+                if let Some(door) = &exit.door {
+                    let state = DoorState {
+                        closed: door.closed,
+                        locked: door.locked,
+                    };
+                    doors.push((location.id.clone(), *dir, state));
+                }
+            }
+        }
+
+        for (location, dir, state) in doors {
+            self.set_door(&location, dir, state);
+        }
+    }
+
+    /// Whether anything can go through `location`'s `dir` exit, and why not.
+    pub fn check_exit(&self, location: &str, dir: Direction) -> Result<(), String> {
+        let location = &self.locations[location];
+        let exit = location
+            .exits
+            .get(&dir)
+            .filter(|_| !location.hidden.contains(&dir))
+            .ok_or("You can't go that way.")?;
+        if let (Some(door), Some(state)) = (&exit.door, location.doors.get(&dir)) {
+            if state.closed {
+                return Err(format!("The {} is closed.", door.name));
+            }
+        }
+        Ok(())
+    }
+}
+
+impl Shared {
+    /// Finds a door next to the player by direction or name.
+    fn find_door(
+        &self,
+        addr: &SocketAddr,
+        keyword: &str,
+    ) -> Result<(LocationId, Direction), String> {
+        let location = &self.world.locations[&self.session(addr)?.location];
+        let dir = Direction::parse(keyword);
+        location
+            .exits
+            .iter()
+            .filter(|(dir, _)| !location.hidden.contains(dir))
+            .find(|(exit_dir, exit)| {
+                exit.door.as_ref().is_some_and(|door| {
+                    dir.map_or_else(|| door.matches(keyword), |dir| dir == **exit_dir)
+                })
+            })
+            .map(|(dir, _)| (location.id.clone(), *dir))
+            .ok_or(format!("You see no {keyword} here."))
+    }
+
+    fn door(&self, location: &str, dir: Direction) -> (String, DoorState) {
+        let location = &self.world.locations[location];
+        let door = location.exits[&dir].door.as_ref().unwrap();
+        (door.name.clone(), location.doors[&dir])
+    }
+
+    /// Tells the player, everyone with them and everyone on the other side what happened.
+    fn door_message(
+        &self,
+        addr: &SocketAddr,
+        location: &str,
+        dir: Direction,
+        you: &str,
+        others: &str,
+        beyond: &str,
+    ) {
+        let name = &self.peers[addr].name;
+        self.send_to(addr, Response::game_msg(you));
+        self.broadcast_location(location, &format!("{name} {others}"), &[*addr]);
+        let to = &self.world.locations[location].exits[&dir].to;
+        self.broadcast_location(to, beyond, &[]);
+    }
+
+    fn has_key(&self, addr: &SocketAddr, key: &Option<String>) -> bool {
+        let Ok(session) = self.session(addr) else {
+            return false;
+        };
+        key.as_ref().is_some_and(|key| {
+            session
+                .inventory
+                .iter()
+                .chain(session.equipment.values())
+                .any(|id| &self.world.items[id].template == key)
+        })
+    }
+
+    /// `open <door|direction|container>`
+    pub fn open_door(&mut self, addr: &SocketAddr, args: &[&str]) -> Result<(), String> {
+        let keyword = args.first().ok_or("Open what?")?;
+        let (location, dir) = match self.find_door(addr, keyword) {
+            Ok(door) => door,
+            Err(e) => return self.open_chest(addr, keyword).map_err(|_| e),
+        };
+        let (name, state) = self.door(&location, dir);
+        if !state.closed {
+            return Err(format!("The {name} is already open."));
+        }
+        if state.locked {
+            return Err(format!("The {name} is locked."));
+        }
+
+        self.world.set_door(
+            &location,
+            dir,
+            DoorState {
+                closed: false,
+                ..state
+            },
+        );
+        self.door_message(
+            addr,
+            &location,
+            dir,
+            &format!("You open the {name}."),
+            &format!("opens the {name}."),
+            &format!("The {name} opens."),
+        );
+        Ok(())
+    }
+
+    /// `close <door|direction>`
+    pub fn close_door(&mut self, addr: &SocketAddr, args: &[&str]) -> Result<(), String> {
+        let (location, dir) = self.find_door(addr, args.first().ok_or("Close what?")?)?;
+        let (name, state) = self.door(&location, dir);
+        if state.closed {
+            return Err(format!("The {name} is already closed."));
+        }
+
+        self.world.set_door(
+            &location,
+            dir,
+            DoorState {
+                closed: true,
+                ..state
+            },
+        );
+        self.door_message(
+            addr,
+            &location,
+            dir,
+            &format!("You close the {name}."),
+            &format!("closes the {name}."),
+            &format!("The {name} closes."),
+        );
+        Ok(())
+    }
+
+    /// `lock <door|direction>`
+    pub fn lock_door(&mut self, addr: &SocketAddr, args: &[&str]) -> Result<(), String> {
+        let (location, dir) = self.find_door(addr, args.first().ok_or("Lock what?")?)?;
+        let (name, state) = self.door(&location, dir);
+        let key = self.world.locations[&location].exits[&dir]
+            .door
+            .as_ref()
+            .unwrap()
+            .key
+            .clone();
+        if key.is_none() {
+            return Err(format!("The {name} has no lock."));
+        }
+        if !state.closed {
+            return Err(format!("You'll have to close the {name} first."));
+        }
+        if state.locked {
+            return Err(format!("The {name} is already locked."));
+        }
+        if !self.has_key(addr, &key) {
+            return Err("You don't have the key.".to_string());
+        }
+
+        self.world.set_door(
+            &location,
+            dir,
+            DoorState {
+                locked: true,
+                ..state
+            },
+        );
+        self.door_message(
+            addr,
+            &location,
+            dir,
+            &format!("You lock the {name}. *Click*"),
+            &format!("locks the {name}."),
+            &format!("You hear a click from the {name}."),
+        );
+        Ok(())
+    }
+
+    /// `unlock <door|direction>`
+    pub fn unlock_door(&mut self, addr: &SocketAddr, args: &[&str]) -> Result<(), String> {
+        let (location, dir) = self.find_door(addr, args.first().ok_or("Unlock what?")?)?;
+        let (name, state) = self.door(&location, dir);
+        if !state.locked {
+            return Err(format!("The {name} isn't locked."));
+        }
+        let key = self.world.locations[&location].exits[&dir]
+            .door
+            .as_ref()
+            .unwrap()
+            .key
+            .clone();
+        if !self.has_key(addr, &key) {
+            return Err("You don't have the key.".to_string());
+        }
+
+        self.world.set_door(
+            &location,
+            dir,
+            DoorState {
+                locked: false,
+                ..state
+            },
+        );
+        self.door_message(
+            addr,
+            &location,
+            dir,
+            &format!("You unlock the {name}. *Click*"),
+            &format!("unlocks the {name}."),
+            &format!("You hear a click from the {name}."),
+        );
+        Ok(())
+    }
+
+    /// `pick <door|direction>`
+    pub fn pick_lock(&mut self, addr: &SocketAddr, args: &[&str]) -> Result<(), String> {
+        if self.is_fighting(Combatant::Player(*addr)) {
+            return Err("You're a little busy for that!".to_string());
+        }
+        let (location, dir) = self.find_door(addr, args.first().ok_or("Pick what?")?)?;
+        let (name, state) = self.door(&location, dir);
+        if !state.locked {
+            return Err(format!("The {name} isn't locked."));
+        }
+        let difficulty = self.world.locations[&location].exits[&dir]
+            .door
+            .as_ref()
+            .unwrap()
+            .pick
+            .ok_or(format!("The lock on the {name} can't be picked."))?;
+
+        if !self.attribute_check(addr, Attribute::Dexterity, difficulty) {
+            let player = &self.peers[addr].name;
+            self.send_to(addr, Response::game_msg("You fail to pick the lock."));
+            self.broadcast_location(
+                &location,
+                &format!("{player} fiddles with the lock on the {name}."),
+                &[*addr],
+            );
+            return Ok(());
+        }
+
+        self.world.set_door(
+            &location,
+            dir,
+            DoorState {
+                locked: false,
+                ..state
+            },
+        );
+        self.door_message(
+            addr,
+            &location,
+            dir,
+            &format!("You pick the lock on the {name}. *Click*"),
+            &format!("picks the lock on the {name}."),
+            &format!("You hear a click from the {name}."),
+        );
+        Ok(())
+    }
+
+    /// `search`
+    pub fn search(&mut self, addr: &SocketAddr) -> Result<(), String> {
+        let session = self.session(addr)?;
+        let location = session.location.clone();
+        let player = session.name.clone();
+        let hidden = self.world.locations[&location]
+            .hidden
+            .iter()
+            .map(|dir| {
+                (
+                    *dir,
+                    self.world.locations[&location].exits[dir]
+                        .hidden
+                        .unwrap_or(0),
+                )
+            })
+            .collect::<Vec<_>>();
+
+        self.broadcast_location(&location, &format!("{player} searches the area."), &[*addr]);
+        let mut found = false;
+        for (dir, difficulty) in hidden {
+            if self.attribute_check(addr, Attribute::Wisdom, difficulty) {
+                found = true;
+                self.world
+                    .locations
+                    .get_mut(&location)
+                    .unwrap()
+                    .hidden
+                    .remove(&dir);
+                self.send_to(
+                    addr,
+                    Response::game_msg(&format!("You find a hidden way {}!", dir.name())),
+                );
+                self.broadcast_location(
+                    &location,
+                    &format!("{player} finds a hidden way {}!", dir.name()),
+                    &[*addr],
+                );
+            }
+        }
+        if found {
+            let area = self.world.locations[&location].area.clone();
+            self.send_maps_in_area(&area);
+        } else {
+            self.send_to(addr, Response::game_msg("You find nothing unusual."));
+        }
+        Ok(())
+    }
+}
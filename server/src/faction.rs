@@ -0,0 +1,253 @@
+use std::collections::BTreeMap;
+use std::net::SocketAddr;
+
+use model::Response;
+use serde::Deserialize;
+
+use crate::character::Character;
+use crate::dialogue::Condition;
+use crate::npc::{Behaviour, NpcId, NpcTemplate};
+use crate::world::World;
+use crate::Shared;
+
+/// How a faction's NPCs treat a player.
+#[derive(Deserialize, Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum Attitude {
+    /// Attacks on sight and won't trade.
+    Hostile,
+    /// Only attacks if aggressive anyway.
+    #[default]
+    Neutral,
+    /// Leaves the player alone even if aggressive.
+    Friendly,
+}
+
+/// A band of reputation, e.g. "liked" from 100 up to the next standing's `min`.
+#[derive(Deserialize, Clone, Debug)]
+pub struct StandingDef {
+    pub name: String,
+    pub min: i32,
+    /// Multiplies what the faction's shops charge. Above 1 it also divides what they pay.
+    #[serde(default = "no_change")]
+    pub price: f64,
+    #[serde(default)]
+    pub attitude: Attitude,
+}
+
+fn no_change() -> f64 {
+    1.0
+}
+
+/// A faction from `factions.ron`.
+#[derive(Deserialize, Clone, Debug)]
+pub struct FactionDef {
+    pub id: String,
+    pub name: String,
+    /// Reputation characters start with.
+    #[serde(default)]
+    pub start: i32,
+    /// Reputation changes, by faction, for killing one of its members.
+    #[serde(default)]
+    pub kill: BTreeMap<String, i32>,
+}
+
+/// The contents of `factions.ron`.
+#[derive(Deserialize, Clone, Debug)]
+pub struct FactionRules {
+    pub min_reputation: i32,
+    pub max_reputation: i32,
+    /// From lowest to highest.
+    pub standings: Vec<StandingDef>,
+    pub factions: Vec<FactionDef>,
+}
+
+impl World {
+    /// Checks that standings are in order with positive prices, and factions are only
+    /// referred to if they exist.
+    pub(crate) fn validate_factions(&self) -> Result<(), String> {
+        let rules = &self.factions;
+        if rules.standings.is_empty() {
+            return Err("There must be at least one standing".to_string());
+        }
+        if let Some(standing) = rules
+            .standings
+            .iter()
+            .find(|standing| !standing.price.is_finite() || standing.price <= 0.0)
+        {
+            return Err(format!(
+                "Standing {} must have a positive price",
+                standing.name
+            ));
+        }
+        let economy = &self.economy;
+        if let Some(standing) = rules
+            .standings
+            .iter()
+            .find(|standing| standing.price * economy.buy_multiplier < economy.sell_multiplier)
+        {
+            return Err(format!(
+                "Standing {} makes buying cheaper than selling",
+                standing.name
+            ));
+        }
+        if rules
+            .standings
+            .windows(2)
+            .any(|pair| pair[0].min >= pair[1].min)
+        {
+            return Err("Standings must go from lowest to highest".to_string());
+        }
+        let check = |id: &str, user: String| match self.faction(id) {
+            Some(_) => Ok(()),
+            None => Err(format!("{user} refers to unknown faction {id}")),
+        };
+
+        for faction in &rules.factions {
+            for id in faction.kill.keys() {
+                check(id, format!("Faction {}", faction.id))?;
+            }
+        }
+        for template in self.npc_templates.values() {
+            if let Some(id) = &template.faction {
+                check(id, format!("NPC {}", template.id))?;
+            }
+        }
+        for quest in self.quests.values() {
+            for id in quest.rewards.reputation.keys() {
+                check(id, format!("Quest {}", quest.id))?;
+            }
+        }
+        for tree in self.dialogues.values() {
+            let conditions = tree
+                .nodes
+                .values()
+                .flat_map(|node| &node.choices)
+                .flat_map(|choice| &choice.conditions);
+            for condition in conditions {
+                if let Condition::Reputation { faction, .. } = condition {
+                    check(faction, format!("Dialogue tree {}", tree.id))?;
+                }
+            }
+        }
+        Ok(())
+    }
+
+    pub fn faction(&self, id: &str) -> Option<&FactionDef> {
+        self.factions
+            .factions
+            .iter()
+            .find(|faction| faction.id == id)
+    }
+
+    pub fn reputation(&self, character: &Character, faction: &str) -> i32 {
+        match character.reputation.get(faction) {
+            Some(reputation) => *reputation,
+            None => self.faction(faction).map_or(0, |faction| faction.start),
+        }
+    }
+
+    /// The standing that a reputation falls in.
+    pub fn standing(&self, reputation: i32) -> &StandingDef {
+        let standings = &self.factions.standings;
+        standings
+            .iter()
+            .rev()
+            .find(|standing| reputation >= standing.min)
+            .unwrap_or(&standings[0])
+    }
+
+    /// Where a character stands with an NPC's faction, if it belongs to one.
+    fn npc_standing(&self, template: &NpcTemplate, character: &Character) -> Option<&StandingDef> {
+        let faction = template.faction.as_ref()?;
+        Some(self.standing(self.reputation(character, faction)))
+    }
+
+    /// How much a shopkeeper's prices change for a character.
+    pub fn price_multiplier(&self, shop: NpcId, character: &Character) -> f64 {
+        self.npc_standing(self.npc_template(shop), character)
+            .map_or(1.0, |standing| standing.price)
+    }
+
+    /// How an NPC feels about a character.
+    pub fn attitude(&self, npc: NpcId, character: &Character) -> Attitude {
+        self.npc_standing(self.npc_template(npc), character)
+            .map_or(Attitude::Neutral, |standing| standing.attitude)
+    }
+
+    /// Whether an NPC attacks a character on sight.
+    pub fn attacks_on_sight(&self, npc: NpcId, character: &Character) -> bool {
+        match self.attitude(npc, character) {
+            Attitude::Hostile => true,
+            Attitude::Neutral => self.npc_template(npc).has_behaviour(&Behaviour::Aggressive),
+            Attitude::Friendly => false,
+        }
+    }
+}
+
+impl Shared {
+    /// Changes a player's reputation with a faction, telling them how it went.
+    pub(crate) fn change_reputation(&mut self, addr: &SocketAddr, faction: &str, amount: i32) {
+        let Some(def) = self.world.faction(faction) else {
+            return;
+        };
+        let Ok(session) = self.session(addr) else {
+            return;
+        };
+        let rules = &self.world.factions;
+        let before = self.world.reputation(&session.character, faction);
+        let after = (before + amount).clamp(rules.min_reputation, rules.max_reputation);
+        if after == before {
+            return;
+        }
+        let old_standing = self.world.standing(before).name.clone();
+        let new_standing = self.world.standing(after).name.clone();
+        let name = def.name.clone();
+
+        self.peers
+            .get_mut(addr)
+            .unwrap()
+            .character
+            .reputation
+            .insert(faction.to_string(), after);
+        let change = if after > before {
+            "improves"
+        } else {
+            "worsens"
+        };
+        let mut msg = format!("Your standing with {name} {change}.");
+        if new_standing != old_standing {
+            msg.push_str(&format!("\nYou are now {new_standing} by {name}."));
+        }
+        self.send_to(addr, Response::game_msg(&msg));
+    }
+
+    /// Changes a player's reputation for killing an NPC made from `template`.
+    pub(crate) fn reputation_kill(&mut self, addr: &SocketAddr, template: &str) {
+        let Some(faction) = &self.world.npc_templates[template].faction else {
+            return;
+        };
+        let Some(def) = self.world.faction(faction) else {
+            return;
+        };
+        for (faction, amount) in def.kill.clone() {
+            self.change_reputation(addr, &faction, amount);
+        }
+    }
+
+    /// `reputation`
+    pub fn show_reputation(&self, addr: &SocketAddr) -> Result<(), String> {
+        let character = &self.session(addr)?.character;
+        let mut msg = String::from("Your standing:");
+        for faction in &self.world.factions.factions {
+            let reputation = self.world.reputation(character, &faction.id);
+            msg.push_str(&format!(
+                "\n  {:<24} {:>6}  {}",
+                faction.name,
+                reputation,
+                self.world.standing(reputation).name
+            ));
+        }
+        self.send_to(addr, Response::game_msg(&msg));
+        Ok(())
+    }
+}
@@ -0,0 +1,249 @@
+use std::net::SocketAddr;
+
+use model::Response;
+
+use crate::combat::Combatant;
+use crate::party::PartyId;
+use crate::reset::SpawnRule;
+use crate::world::{Area, LocationId, World};
+use crate::Shared;
+
+/// Who a copy of an instanced area belongs to.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum InstanceOwner {
+    Party(PartyId),
+    /// A player who isn't in a party, by name.
+    Player(String),
+}
+
+/// A private copy of an instanced area.
+#[derive(Clone, Debug)]
+pub struct Instance {
+    /// The area it's a copy of.
+    pub area: String,
+    pub owner: InstanceOwner,
+    /// Where its owners came in from, and where anyone still inside is sent when it
+    /// closes.
+    pub entrance: LocationId,
+    /// Game ticks since it was made.
+    pub age: u32,
+}
+
+/// The id of the location a copy in an instance was made from.
+pub fn base_location(location: &str) -> &str {
+    location.split('#').next().unwrap_or(location)
+}
+
+/// The id an instance gives one of its area's locations.
+fn instance_location(location: &str, instance: &str) -> LocationId {
+    let n = instance.rsplit('#').next().unwrap_or_default();
+    format!("{location}#{n}")
+}
+
+impl World {
+    /// Whether `area` is only ever visited through copies.
+    pub fn is_instanced(&self, area: &str) -> bool {
+        self.areas[area].source.instanced
+    }
+
+    /// Builds a copy of `area` called `id`, with its own locations, NPCs and items.
+    fn create_instance(&mut self, area: &str, id: &str) -> Result<(), String> {
+        let template = &self.areas[area];
+        let mut def = template.source.clone();
+        let inside = def
+            .locations
+            .iter()
+            .map(|location| location.id.clone())
+            .collect::<Vec<_>>();
+        let rename = |location: &mut LocationId| {
+            if inside.contains(location) {
+                *location = instance_location(location, id);
+            }
+        };
+
+        def.id = id.to_string();
+        def.instanced = false;
+        // templates are shared with the original
+        def.npcs.clear();
+        def.items.clear();
+        def.quests.clear();
+        def.dialogues.clear();
+        for location in &mut def.locations {
+            rename(&mut location.id);
+            location.landmark = false;
+            for exit in location.exits.values_mut() {
+                rename(&mut exit.to);
+            }
+        }
+        for spawn in &mut def.spawns {
+            match spawn {
+                SpawnRule::Npc { location, .. } | SpawnRule::Item { location, .. } => {
+                    rename(location)
+                }
+            }
+        }
+        if let Some(recall) = &mut def.recall {
+            rename(recall);
+        }
+
+        self.areas.insert(
+            id.to_string(),
+            Area {
+                id: id.to_string(),
+                name: template.name.clone(),
+                reset_ticks: template.reset_ticks,
+                reset_timer: 0,
+                reset_when_empty: template.reset_when_empty,
+                spawns: vec![],
+                weather: template.weather.clone(),
+                weather_timer: 0,
+                ambient: vec![],
+                path: template.path.clone(),
+                source: def.clone(),
+            },
+        );
+        if let Err(e) = self.apply_area(def) {
+            self.remove_instance(id);
+            return Err(e);
+        }
+        self.reset_area(id)
+    }
+
+    /// Takes an instance's locations and everything in them out of the world.
+    fn remove_instance(&mut self, id: &str) {
+        let locations = self
+            .locations
+            .values()
+            .filter(|location| location.area == id)
+            .map(|location| location.id.clone())
+            .collect::<Vec<_>>();
+        for location in locations {
+            self.remove_location(&location);
+        }
+        self.areas.remove(id);
+    }
+}
+
+impl Shared {
+    fn instance_owner(&self, addr: &SocketAddr) -> Result<InstanceOwner, String> {
+        Ok(match self.party_of(addr) {
+            Some(party) => InstanceOwner::Party(party),
+            None => InstanceOwner::Player(self.session(addr)?.name.clone()),
+        })
+    }
+
+    /// Where a player moving from `from` to `to` really ends up. Stepping into an
+    /// instanced area from outside leads into their party's copy of it, which is made
+    /// if they don't have one yet.
+    pub(crate) fn instance_destination(
+        &mut self,
+        addr: &SocketAddr,
+        from: &str,
+        to: &str,
+    ) -> Result<LocationId, String> {
+        let area = self.world.locations[to].area.clone();
+        if !self.world.is_instanced(&area) || self.world.locations[from].area == area {
+            return Ok(to.to_string());
+        }
+
+        let owner = self.instance_owner(addr)?;
+        let existing = self
+            .instances
+            .iter()
+            .find(|(_, instance)| instance.area == area && instance.owner == owner)
+            .map(|(id, _)| id.clone());
+        let id = match existing {
+            Some(id) => id,
+            None => {
+                self.next_instance += 1;
+                let id = format!("{area}#{}", self.next_instance);
+                self.world.create_instance(&area, &id)?;
+                self.instances.insert(
+                    id.clone(),
+                    Instance {
+                        area,
+                        owner,
+                        entrance: from.to_string(),
+                        age: 0,
+                    },
+                );
+                id
+            }
+        };
+        Ok(instance_location(to, &id))
+    }
+
+    /// Where to put a player back in the world if they were saved inside an instance.
+    pub(crate) fn saved_location(&self, location: &str) -> LocationId {
+        let area = &self.world.locations[location].area;
+        match self.instances.get(area) {
+            Some(instance) => instance.entrance.clone(),
+            None => location.to_string(),
+        }
+    }
+
+    /// Closes instances that everyone has left or that have run out of time.
+    pub(crate) fn instance_tick(&mut self) {
+        let mut closing = vec![];
+        for (id, instance) in self.instances.iter_mut() {
+            instance.age += 1;
+            let occupied = self
+                .peers
+                .values()
+                .any(|session| self.world.locations[&session.location].area == *id);
+            let expired = self.world.areas[&instance.area]
+                .source
+                .instance_ticks
+                .is_some_and(|ticks| instance.age >= ticks);
+            if !occupied || expired {
+                closing.push(id.clone());
+            }
+        }
+        closing.sort();
+
+        for id in closing {
+            let instance = self.instances.remove(&id).unwrap();
+            let entrance = match self.world.locations.contains_key(&instance.entrance) {
+                true => instance.entrance,
+                false => self.world.config.start_location.clone(),
+            };
+            let corpses = self
+                .world
+                .locations
+                .values_mut()
+                .filter(|location| location.area == id)
+                .flat_map(|location| {
+                    let (corpses, rest) = std::mem::take(&mut location.items)
+                        .into_iter()
+                        .partition::<Vec<_>, _>(|item| self.world.items[item].corpse.is_some());
+                    location.items = rest;
+                    corpses
+                })
+                .collect::<Vec<_>>();
+            let inside = self
+                .peers
+                .iter()
+                .filter(|(_, session)| self.world.locations[&session.location].area == id)
+                .map(|(addr, _)| *addr)
+                .collect::<Vec<_>>();
+            for addr in inside {
+                self.end_fights(Combatant::Player(addr));
+                self.stop_walking(&addr, "You stop travelling.");
+                self.peers.get_mut(&addr).unwrap().location = entrance.clone();
+                self.send_to(
+                    &addr,
+                    Response::game_msg("The way behind you collapses, and you find yourself back where you started."),
+                );
+                let _ = self.look(&addr, &[]);
+            }
+            // nobody's belongings are lost with it
+            self.world
+                .locations
+                .get_mut(&entrance)
+                .unwrap()
+                .items
+                .extend(corpses);
+            self.world.remove_instance(&id);
+        }
+    }
+}
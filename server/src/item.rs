@@ -0,0 +1,632 @@
+use std::net::SocketAddr;
+
+use model::{GameUpdate, InventoryView, ItemView, Response, WearSlot};
+use serde::{Deserialize, Serialize};
+
+use crate::death::Corpse;
+use crate::world::{is_default, World};
+use crate::Shared;
+
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct ItemId(pub u64);
+
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ItemFlag {
+    /// Other items can be put inside, up to the template's `capacity`.
+    Container,
+    /// Can't be picked up from the floor.
+    NoTake,
+    /// Can't be dropped or given away once carried.
+    NoDrop,
+}
+
+/// The shared definition of an item, loaded from an area file.
+#[derive(Serialize, Deserialize, Clone, Debug, Default)]
+pub struct ItemTemplate {
+    pub id: String,
+    pub name: String,
+    pub keywords: Vec<String>,
+    #[serde(default, skip_serializing_if = "String::is_empty")]
+    pub description: String,
+    #[serde(default, skip_serializing_if = "is_default")]
+    pub weight: u32,
+    #[serde(default, skip_serializing_if = "is_default")]
+    pub value: u32,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub slot: Option<WearSlot>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub flags: Vec<ItemFlag>,
+    /// Maximum weight of contents for containers.
+    #[serde(default, skip_serializing_if = "is_default")]
+    pub capacity: u32,
+    /// Bonus to attack while worn or wielded.
+    #[serde(default, skip_serializing_if = "is_default")]
+    pub attack: i32,
+    /// Bonus to defense while worn or wielded.
+    #[serde(default, skip_serializing_if = "is_default")]
+    pub defense: i32,
+    /// Name of a script in `data/scripts` whose `on_use` hook runs for `use`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub script: Option<String>,
+    /// Loot table a container placed by an area reset is filled from when first opened.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub loot: Option<String>,
+    /// Lights up dark places while carried or lying on the floor.
+    #[serde(default, skip_serializing_if = "is_default")]
+    pub light: bool,
+}
+
+impl ItemTemplate {
+    pub fn has_flag(&self, flag: ItemFlag) -> bool {
+        self.flags.contains(&flag)
+    }
+
+    /// Whether every word of `keyword`, e.g. "rusty sword", starts one of its keywords.
+    pub fn matches(&self, keyword: &str) -> bool {
+        let keyword = keyword.to_lowercase();
+        let mut words = keyword.split_whitespace().peekable();
+        words.peek().is_some()
+            && words.all(|word| self.keywords.iter().any(|k| k.starts_with(word)))
+    }
+}
+
+/// A single instance of an item template somewhere in the world.
+pub struct Item {
+    pub id: ItemId,
+    pub template: String,
+    pub contents: Vec<ItemId>,
+    /// Loot table still to be rolled into the container.
+    pub loot: Option<String>,
+    pub corpse: Option<Corpse>,
+}
+
+impl World {
+    pub fn spawn_item(&mut self, template: &str) -> Result<ItemId, String> {
+        if !self.item_templates.contains_key(template) {
+            return Err(format!("Unknown item template {template}"));
+        }
+
+        let id = ItemId(self.next_item_id);
+        self.next_item_id += 1;
+        self.items.insert(
+            id,
+            Item {
+                id,
+                template: template.to_string(),
+                contents: vec![],
+                loot: None,
+                corpse: None,
+            },
+        );
+        Ok(id)
+    }
+
+    /// Removes an item and everything inside it from the world.
+    pub fn destroy_item(&mut self, id: ItemId) {
+        if let Some(item) = self.items.remove(&id) {
+            for inner in item.contents {
+                self.destroy_item(inner);
+            }
+        }
+    }
+
+    pub fn template(&self, id: ItemId) -> &ItemTemplate {
+        &self.item_templates[&self.items[&id].template]
+    }
+
+    pub fn item_name(&self, id: ItemId) -> &str {
+        match &self.items[&id].corpse {
+            Some(corpse) => &corpse.name,
+            None => &self.template(id).name,
+        }
+    }
+
+    /// Whether `keyword` names an item. Corpses also answer to their owner's name.
+    pub fn item_matches(&self, id: ItemId, keyword: &str) -> bool {
+        self.template(id).matches(keyword)
+            || self.items[&id].corpse.as_ref().is_some_and(|corpse| {
+                corpse
+                    .owner
+                    .to_lowercase()
+                    .starts_with(&keyword.to_lowercase())
+            })
+    }
+
+    /// The weight of an item including its contents.
+    pub fn item_weight(&self, id: ItemId) -> u32 {
+        self.template(id).weight + self.contents_weight(id)
+    }
+
+    /// Everything inside an item, including inside other containers in it.
+    pub fn nested_contents(&self, id: ItemId) -> Vec<ItemId> {
+        self.items[&id]
+            .contents
+            .iter()
+            .flat_map(|inner| std::iter::once(*inner).chain(self.nested_contents(*inner)))
+            .collect()
+    }
+
+    pub fn contents_weight(&self, id: ItemId) -> u32 {
+        self.items[&id]
+            .contents
+            .iter()
+            .map(|inner| self.item_weight(*inner))
+            .sum()
+    }
+
+    pub fn find_item(&self, ids: &[ItemId], keyword: &str) -> Option<ItemId> {
+        ids.iter()
+            .copied()
+            .find(|id| self.item_matches(*id, keyword))
+    }
+
+    pub fn item_view(&self, id: ItemId) -> ItemView {
+        ItemView {
+            name: self.item_name(id).to_string(),
+            weight: self.item_weight(id),
+            contents: self.items[&id]
+                .contents
+                .iter()
+                .map(|inner| self.item_view(*inner))
+                .collect(),
+        }
+    }
+
+    pub fn describe_item(&self, id: ItemId) -> String {
+        let template = self.template(id);
+        let mut desc = if template.description.is_empty() {
+            format!("You see nothing special about {}.", self.item_name(id))
+        } else {
+            template.description.clone()
+        };
+
+        if template.has_flag(ItemFlag::Container) {
+            let contents = &self.items[&id].contents;
+            if contents.is_empty() {
+                desc.push_str("\nIt is empty.");
+            } else {
+                desc.push_str("\nIt contains:");
+                for inner in contents {
+                    desc.push_str(&format!("\n  {}", self.item_name(*inner)));
+                }
+            }
+        }
+
+        desc
+    }
+}
+
+impl Shared {
+    pub(crate) fn carried_weight(&self, addr: &SocketAddr) -> Result<u32, String> {
+        let session = self.session(addr)?;
+        Ok(session
+            .inventory
+            .iter()
+            .chain(session.equipment.values())
+            .map(|id| self.world.item_weight(*id))
+            .sum())
+    }
+
+    pub(crate) fn check_capacity(&self, addr: &SocketAddr, extra: u32) -> Result<(), String> {
+        let session = self.session(addr)?;
+        if self.carried_weight(addr)? + extra > session.carry_capacity() {
+            return Err(format!("{} can't carry that much weight.", session.name));
+        }
+        Ok(())
+    }
+
+    pub(crate) fn send_inventory(&self, addr: &SocketAddr) -> Result<(), String> {
+        let session = self.session(addr)?;
+        let view = InventoryView {
+            carried: session
+                .inventory
+                .iter()
+                .map(|id| self.world.item_view(*id))
+                .collect(),
+            worn: session
+                .equipment
+                .iter()
+                .map(|(slot, id)| (*slot, self.world.item_view(*id)))
+                .collect(),
+            weight: self.carried_weight(addr)?,
+            capacity: session.carry_capacity(),
+            gold: session.character.gold,
+        };
+        self.send_to(addr, Response::Game(GameUpdate::Inventory(view)));
+        self.send_fetch_quests(addr);
+        Ok(())
+    }
+
+    pub fn show_inventory(&self, addr: &SocketAddr) -> Result<(), String> {
+        let session = self.session(addr)?;
+        let mut msg = String::from("You are carrying:");
+        if session.inventory.is_empty() {
+            msg.push_str("\n  Nothing.");
+        }
+        for id in &session.inventory {
+            msg.push_str(&format!("\n  {}", self.world.item_name(*id)));
+        }
+        for (slot, id) in &session.equipment {
+            msg.push_str(&format!("\n  <{slot}> {}", self.world.item_name(*id)));
+        }
+        msg.push_str(&format!(
+            "\nTotal weight: {}/{}",
+            self.carried_weight(addr)?,
+            session.carry_capacity()
+        ));
+
+        self.send_to(addr, Response::game_msg(&msg));
+        self.send_inventory(addr)
+    }
+
+    /// `get <item>` or `get <item> from <container>`
+    pub fn get_item(&mut self, addr: &SocketAddr, args: &[&str]) -> Result<(), String> {
+        let (item_kw, container_kw) = split_args(args, &["from"]);
+        if item_kw.is_empty() || container_kw.as_ref().is_some_and(|kw| kw.is_empty()) {
+            return Err("Get what?".to_string());
+        }
+        let item_kw = item_kw.as_str();
+        let container_kw = container_kw.as_deref();
+        let session = self.session(addr)?;
+        let location = session.location.clone();
+        let name = session.name.clone();
+
+        let (id, source) = match container_kw {
+            None => {
+                let floor = &self.world.locations[&location].items;
+                let id = self
+                    .world
+                    .find_item(floor, item_kw)
+                    .ok_or(format!("You don't see a {item_kw} here."))?;
+                (id, None)
+            }
+            Some(container_kw) => {
+                let container = self.find_container(addr, container_kw)?;
+                self.check_corpse_access(addr, container)?;
+                let id = self
+                    .world
+                    .find_item(&self.world.items[&container].contents, item_kw)
+                    .ok_or(format!("There is no {item_kw} in there."))?;
+                (id, Some(container))
+            }
+        };
+
+        if self.world.template(id).has_flag(ItemFlag::NoTake) {
+            return Err("You can't take that.".to_string());
+        }
+        // Taking something out of a bag you're already carrying doesn't change the load.
+        let already_carried = match source {
+            Some(container) => self.is_carrying(addr, container)?,
+            None => false,
+        };
+        if !already_carried {
+            self.check_capacity(addr, self.world.item_weight(id))?;
+        }
+
+        match source {
+            None => self
+                .world
+                .locations
+                .get_mut(&location)
+                .unwrap()
+                .items
+                .retain(|i| *i != id),
+            Some(container) => self
+                .world
+                .items
+                .get_mut(&container)
+                .unwrap()
+                .contents
+                .retain(|i| *i != id),
+        }
+        self.peers.get_mut(addr).unwrap().inventory.push(id);
+
+        let item_name = self.world.item_name(id);
+        let (to_self, to_others) = match source {
+            None => (
+                format!("You pick up {item_name}."),
+                format!("{name} picks up {item_name}."),
+            ),
+            Some(container) => {
+                let container_name = self.world.item_name(container);
+                (
+                    format!("You take {item_name} from {container_name}."),
+                    format!("{name} takes {item_name} from {container_name}."),
+                )
+            }
+        };
+        self.send_to(addr, Response::game_msg(&to_self));
+        self.broadcast_location(&location, &to_others, &[*addr]);
+        self.send_inventory(addr)
+    }
+
+    /// `drop <item>`
+    pub fn drop_item(&mut self, addr: &SocketAddr, args: &[&str]) -> Result<(), String> {
+        let item_kw = args.join(" ");
+        if item_kw.is_empty() {
+            return Err("Drop what?".to_string());
+        }
+        let id = self.take_from_inventory(addr, &item_kw)?;
+        let session = self.session(addr)?;
+        let location = session.location.clone();
+        let name = session.name.clone();
+
+        self.world
+            .locations
+            .get_mut(&location)
+            .unwrap()
+            .items
+            .push(id);
+
+        let item_name = self.world.item_name(id);
+        self.send_to(addr, Response::game_msg(&format!("You drop {item_name}.")));
+        self.broadcast_location(&location, &format!("{name} drops {item_name}."), &[*addr]);
+        self.send_inventory(addr)
+    }
+
+    /// `give <item> [to] <player>`
+    pub fn give_item(&mut self, addr: &SocketAddr, args: &[&str]) -> Result<(), String> {
+        let (item_kw, target_kw) = match split_args(args, &["to"]) {
+            (item_kw, Some(target_kw)) => (item_kw, target_kw),
+            // `give sword bob`
+            (_, None) => match args.split_last() {
+                Some((target_kw, item_kw)) => (item_kw.join(" "), target_kw.to_string()),
+                None => (String::new(), String::new()),
+            },
+        };
+        if item_kw.is_empty() || target_kw.is_empty() {
+            return Err("Give what to whom?".to_string());
+        }
+        let (item_kw, target_kw) = (item_kw.as_str(), target_kw.as_str());
+        let session = self.session(addr)?;
+        let location = session.location.clone();
+        let name = session.name.clone();
+
+        let target = self
+            .find_user_in_location(&location, target_kw)
+            .ok_or(format!("{target_kw} isn't here."))?;
+        if target == *addr {
+            return Err("You can't give things to yourself.".to_string());
+        }
+
+        let id = self
+            .world
+            .find_item(&session.inventory, item_kw)
+            .ok_or(format!("You aren't carrying a {item_kw}."))?;
+        if self.world.template(id).has_flag(ItemFlag::NoDrop) {
+            return Err("You can't let go of it!".to_string());
+        }
+        self.check_capacity(&target, self.world.item_weight(id))?;
+
+        self.peers
+            .get_mut(addr)
+            .unwrap()
+            .inventory
+            .retain(|i| *i != id);
+        let target_session = self.peers.get_mut(&target).unwrap();
+        target_session.inventory.push(id);
+        let target_name = target_session.name.clone();
+
+        let item_name = self.world.item_name(id);
+        self.send_to(
+            addr,
+            Response::game_msg(&format!("You give {item_name} to {target_name}.")),
+        );
+        self.send_to(
+            &target,
+            Response::game_msg(&format!("{name} gives you {item_name}.")),
+        );
+        self.broadcast_location(
+            &location,
+            &format!("{name} gives {item_name} to {target_name}."),
+            &[*addr, target],
+        );
+        self.send_inventory(addr)?;
+        self.send_inventory(&target)
+    }
+
+    /// `put <item> [in] <container>`
+    pub fn put_item(&mut self, addr: &SocketAddr, args: &[&str]) -> Result<(), String> {
+        let (item_kw, container_kw) = split_args(args, &["in", "into"]);
+        let container_kw = container_kw.unwrap_or_default();
+        if item_kw.is_empty() || container_kw.is_empty() {
+            return Err("Put what in what?".to_string());
+        }
+        let (item_kw, container_kw) = (item_kw.as_str(), container_kw.as_str());
+        let session = self.session(addr)?;
+        let location = session.location.clone();
+        let name = session.name.clone();
+
+        let id = self
+            .world
+            .find_item(&session.inventory, item_kw)
+            .ok_or(format!("You aren't carrying a {item_kw}."))?;
+        if self.world.template(id).has_flag(ItemFlag::NoDrop) {
+            return Err("You can't let go of it!".to_string());
+        }
+        let container = self.find_container(addr, container_kw)?;
+        if id == container {
+            return Err("You can't put something inside itself.".to_string());
+        }
+
+        let capacity = self.world.template(container).capacity;
+        if self.world.contents_weight(container) + self.world.item_weight(id) > capacity {
+            return Err(format!("{} is too full.", self.world.item_name(container)));
+        }
+
+        self.peers
+            .get_mut(addr)
+            .unwrap()
+            .inventory
+            .retain(|i| *i != id);
+        self.world
+            .items
+            .get_mut(&container)
+            .unwrap()
+            .contents
+            .push(id);
+
+        let item_name = self.world.item_name(id);
+        let container_name = self.world.item_name(container);
+        self.send_to(
+            addr,
+            Response::game_msg(&format!("You put {item_name} in {container_name}.")),
+        );
+        self.broadcast_location(
+            &location,
+            &format!("{name} puts {item_name} in {container_name}."),
+            &[*addr],
+        );
+        self.send_inventory(addr)
+    }
+
+    /// `wear <item>`, also used for `wield`.
+    pub fn wear_item(&mut self, addr: &SocketAddr, args: &[&str]) -> Result<(), String> {
+        let item_kw = args.join(" ");
+        if item_kw.is_empty() {
+            return Err("Wear what?".to_string());
+        }
+        let session = self.session(addr)?;
+        let location = session.location.clone();
+        let name = session.name.clone();
+
+        let id = self
+            .world
+            .find_item(&session.inventory, &item_kw)
+            .ok_or(format!("You aren't carrying a {item_kw}."))?;
+        let slot = self
+            .world
+            .template(id)
+            .slot
+            .ok_or(format!("You can't wear {}.", self.world.item_name(id)))?;
+        if let Some(worn) = session.equipment.get(&slot) {
+            return Err(format!(
+                "You are already using {} ({slot}).",
+                self.world.item_name(*worn)
+            ));
+        }
+
+        let session = self.peers.get_mut(addr).unwrap();
+        session.inventory.retain(|i| *i != id);
+        session.equipment.insert(slot, id);
+
+        let item_name = self.world.item_name(id);
+        let (to_self, to_others) = if slot == WearSlot::Wield {
+            (
+                format!("You wield {item_name}."),
+                format!("{name} wields {item_name}."),
+            )
+        } else {
+            (
+                format!("You wear {item_name}."),
+                format!("{name} wears {item_name}."),
+            )
+        };
+        self.send_to(addr, Response::game_msg(&to_self));
+        self.broadcast_location(&location, &to_others, &[*addr]);
+        self.send_inventory(addr)
+    }
+
+    /// `remove <item>`
+    pub fn remove_item(&mut self, addr: &SocketAddr, args: &[&str]) -> Result<(), String> {
+        let item_kw = args.join(" ");
+        if item_kw.is_empty() {
+            return Err("Remove what?".to_string());
+        }
+        let session = self.session(addr)?;
+        let location = session.location.clone();
+        let name = session.name.clone();
+
+        let (slot, id) = session
+            .equipment
+            .iter()
+            .find(|(_, id)| self.world.item_matches(**id, &item_kw))
+            .map(|(slot, id)| (*slot, *id))
+            .ok_or(format!("You aren't using a {item_kw}."))?;
+
+        let session = self.peers.get_mut(addr).unwrap();
+        session.equipment.remove(&slot);
+        session.inventory.push(id);
+
+        let item_name = self.world.item_name(id);
+        self.send_to(
+            addr,
+            Response::game_msg(&format!("You stop using {item_name}.")),
+        );
+        self.broadcast_location(
+            &location,
+            &format!("{name} stops using {item_name}."),
+            &[*addr],
+        );
+        self.send_inventory(addr)
+    }
+
+    /// Removes a droppable item from the user's inventory and returns it.
+    fn take_from_inventory(&mut self, addr: &SocketAddr, keyword: &str) -> Result<ItemId, String> {
+        let session = self.session(addr)?;
+        let id = self
+            .world
+            .find_item(&session.inventory, keyword)
+            .ok_or(format!("You aren't carrying a {keyword}."))?;
+        if self.world.template(id).has_flag(ItemFlag::NoDrop) {
+            return Err("You can't let go of it!".to_string());
+        }
+
+        self.peers
+            .get_mut(addr)
+            .unwrap()
+            .inventory
+            .retain(|i| *i != id);
+        Ok(id)
+    }
+
+    /// Finds a container the user is carrying or that is lying in their location, and
+    /// opens it.
+    pub(crate) fn find_container(
+        &mut self,
+        addr: &SocketAddr,
+        keyword: &str,
+    ) -> Result<ItemId, String> {
+        let session = self.session(addr)?;
+        let floor = &self.world.locations[&session.location].items;
+        let id = self
+            .world
+            .find_item(&session.inventory, keyword)
+            .or_else(|| self.world.find_item(floor, keyword))
+            .ok_or(format!("You don't see a {keyword} here."))?;
+
+        if !self.world.template(id).has_flag(ItemFlag::Container) {
+            return Err(format!("{} is not a container.", self.world.item_name(id)));
+        }
+        self.open_container(addr, id);
+        Ok(id)
+    }
+
+    fn is_carrying(&self, addr: &SocketAddr, id: ItemId) -> Result<bool, String> {
+        Ok(self.session(addr)?.inventory.contains(&id))
+    }
+
+    /// Destroys everything a user is carrying or wearing.
+    pub(crate) fn destroy_belongings(&mut self, addr: &SocketAddr) {
+        if let Some(session) = self.peers.get_mut(addr) {
+            let items = session
+                .inventory
+                .drain(..)
+                .chain(std::mem::take(&mut session.equipment).into_values())
+                .collect::<Vec<_>>();
+            for id in items {
+                self.world.destroy_item(id);
+            }
+        }
+    }
+}
+
+/// Splits `args` around the first of `separators`, e.g. `["rusty", "sword", "from",
+/// "chest"]` into `("rusty sword", Some("chest"))`. Without a separator every word
+/// belongs to the first part.
+fn split_args(args: &[&str], separators: &[&str]) -> (String, Option<String>) {
+    match args.iter().position(|arg| separators.contains(arg)) {
+        Some(pos) => (args[..pos].join(" "), Some(args[pos + 1..].join(" "))),
+        None => (args.join(" "), None),
+    }
+}
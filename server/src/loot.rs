@@ -0,0 +1,512 @@
+use std::collections::{BTreeSet, HashMap};
+use std::net::SocketAddr;
+
+use model::Response;
+use rand::Rng;
+use serde::{Deserialize, Serialize};
+
+use crate::item::{ItemFlag, ItemId};
+use crate::world::World;
+use crate::{Shared, UserSession};
+
+fn one() -> u32 {
+    1
+}
+
+/// How rare a drop is. Entries without a weight of their own use their tier's.
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, Default, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Rarity {
+    #[default]
+    Common,
+    Uncommon,
+    Rare,
+    Epic,
+    Legendary,
+}
+
+impl Rarity {
+    pub fn weight(self) -> u32 {
+        match self {
+            Rarity::Common => 100,
+            Rarity::Uncommon => 30,
+            Rarity::Rare => 8,
+            Rarity::Epic => 2,
+            Rarity::Legendary => 1,
+        }
+    }
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Rarity::Common => "common",
+            Rarity::Uncommon => "uncommon",
+            Rarity::Rare => "rare",
+            Rarity::Epic => "epic",
+            Rarity::Legendary => "legendary",
+        }
+    }
+}
+
+/// Something that must be true of whoever the loot is for.
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub enum LootCondition {
+    MinLevel(u32),
+    MaxLevel(u32),
+    Class(String),
+    /// The quest is in progress.
+    OnQuest(String),
+    /// The quest has been finished.
+    CompletedQuest(String),
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub enum Loot {
+    /// Item template id.
+    Item(String),
+    /// Rolls another table.
+    Table(String),
+    Nothing,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct LootEntry {
+    pub loot: Loot,
+    #[serde(default)]
+    pub rarity: Rarity,
+    /// Overrides the rarity's weight.
+    #[serde(default)]
+    pub weight: Option<u32>,
+    /// How many of the item drop, or how many times the table is rolled.
+    #[serde(default = "one")]
+    pub min: u32,
+    #[serde(default = "one")]
+    pub max: u32,
+    /// The entry is skipped unless all of these hold.
+    #[serde(default)]
+    pub conditions: Vec<LootCondition>,
+}
+
+impl LootEntry {
+    pub fn weight(&self) -> u32 {
+        self.weight.unwrap_or(self.rarity.weight())
+    }
+}
+
+/// A weighted loot table from `loot.ron`.
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct LootTable {
+    pub id: String,
+    /// Entries that drop every time their conditions hold.
+    #[serde(default)]
+    pub always: Vec<LootEntry>,
+    /// How many times to pick one of `entries` by weight.
+    #[serde(default = "one")]
+    pub rolls: u32,
+    #[serde(default)]
+    pub entries: Vec<LootEntry>,
+}
+
+/// What's known about whoever the loot is for, to check conditions against.
+#[derive(Clone, Debug, Default)]
+pub struct LootContext {
+    pub level: u32,
+    pub class: String,
+    pub active_quests: BTreeSet<String>,
+    pub completed_quests: BTreeSet<String>,
+}
+
+impl LootContext {
+    pub fn for_player(session: &UserSession) -> Self {
+        LootContext {
+            level: session.character.level,
+            class: session.character.class.clone(),
+            active_quests: session.quests.active.keys().cloned().collect(),
+            completed_quests: session.quests.completed.clone(),
+        }
+    }
+
+    pub fn allows(&self, conditions: &[LootCondition]) -> bool {
+        conditions.iter().all(|condition| match condition {
+            LootCondition::MinLevel(level) => self.level >= *level,
+            LootCondition::MaxLevel(level) => self.level <= *level,
+            LootCondition::Class(class) => &self.class == class,
+            LootCondition::OnQuest(quest) => self.active_quests.contains(quest),
+            LootCondition::CompletedQuest(quest) => self.completed_quests.contains(quest),
+        })
+    }
+}
+
+/// A single rolled drop, before any items are spawned.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct LootDrop {
+    pub item: String,
+    pub count: u32,
+    pub rarity: Rarity,
+}
+
+impl World {
+    /// Checks that loot tables only refer to things that exist and never contain
+    /// themselves.
+    pub(crate) fn validate_loot(&self) -> Result<(), String> {
+        for table in self.loot_tables.values() {
+            for entry in table.always.iter().chain(&table.entries) {
+                if entry.min > entry.max {
+                    return Err(format!("Loot table {} has min above max", table.id));
+                }
+                for condition in &entry.conditions {
+                    if let LootCondition::OnQuest(quest) | LootCondition::CompletedQuest(quest) =
+                        condition
+                    {
+                        if !self.quests.contains_key(quest) {
+                            return Err(format!(
+                                "Loot table {} checks unknown quest {quest}",
+                                table.id
+                            ));
+                        }
+                    }
+                }
+                match &entry.loot {
+                    Loot::Item(item) if !self.item_templates.contains_key(item) => {
+                        return Err(format!("Loot table {} drops unknown item {item}", table.id));
+                    }
+                    Loot::Table(inner) if !self.loot_tables.contains_key(inner) => {
+                        return Err(format!(
+                            "Loot table {} rolls unknown table {inner}",
+                            table.id
+                        ));
+                    }
+                    _ => {}
+                }
+            }
+            self.check_nesting(&table.id, &mut vec![])?;
+        }
+
+        for template in self.npc_templates.values() {
+            if let Some(table) = template
+                .loot
+                .as_ref()
+                .filter(|table| !self.loot_tables.contains_key(*table))
+            {
+                return Err(format!(
+                    "NPC {} drops from unknown loot table {table}",
+                    template.id
+                ));
+            }
+        }
+        for template in self.item_templates.values() {
+            if let Some(table) = &template.loot {
+                if !self.loot_tables.contains_key(table) {
+                    return Err(format!(
+                        "Item {} holds unknown loot table {table}",
+                        template.id
+                    ));
+                }
+                if !template.has_flag(ItemFlag::Container) {
+                    return Err(format!(
+                        "Item {} holds loot but isn't a container",
+                        template.id
+                    ));
+                }
+            }
+        }
+        Ok(())
+    }
+
+    fn check_nesting<'a>(&'a self, id: &'a str, path: &mut Vec<&'a str>) -> Result<(), String> {
+        if path.contains(&id) {
+            path.push(id);
+            return Err(format!("Loot tables nest in a loop: {}", path.join(" -> ")));
+        }
+        path.push(id);
+        let table = &self.loot_tables[id];
+        for entry in table.always.iter().chain(&table.entries) {
+            if let Loot::Table(inner) = &entry.loot {
+                self.check_nesting(inner, path)?;
+            }
+        }
+        path.pop();
+        Ok(())
+    }
+
+    /// Rolls a loot table. All randomness comes from `rng`, so drop rates can be
+    /// checked with a seeded generator.
+    pub fn roll_loot<R: Rng>(
+        &self,
+        table: &str,
+        context: &LootContext,
+        rng: &mut R,
+    ) -> Vec<LootDrop> {
+        let mut drops = vec![];
+        roll_table(&self.loot_tables, table, context, rng, &mut drops);
+        drops
+    }
+
+    /// Spawns the items for some drops.
+    pub fn spawn_loot(&mut self, drops: &[LootDrop]) -> Vec<(ItemId, Rarity)> {
+        let mut items = vec![];
+        for drop in drops {
+            for _ in 0..drop.count {
+                match self.spawn_item(&drop.item) {
+                    Ok(id) => items.push((id, drop.rarity)),
+                    Err(e) => eprintln!("Couldn't spawn loot: {e}"),
+                }
+            }
+        }
+        items
+    }
+}
+
+impl Shared {
+    /// Rolls a loot table for a player, or for nobody in particular.
+    pub(crate) fn roll_loot_for(
+        &mut self,
+        table: &str,
+        addr: Option<&SocketAddr>,
+    ) -> Vec<(ItemId, Rarity)> {
+        let context = addr
+            .and_then(|addr| self.peers.get(addr))
+            .map(LootContext::for_player)
+            .unwrap_or_default();
+        let drops = self.world.roll_loot(table, &context, &mut self.rng);
+        self.world.spawn_loot(&drops)
+    }
+
+    /// Fills a container from its loot table the first time someone looks inside.
+    pub(crate) fn open_container(&mut self, addr: &SocketAddr, id: ItemId) {
+        let Some(table) = self
+            .world
+            .items
+            .get_mut(&id)
+            .and_then(|item| item.loot.take())
+        else {
+            return;
+        };
+        let items = self.roll_loot_for(&table, Some(addr));
+        self.world
+            .items
+            .get_mut(&id)
+            .unwrap()
+            .contents
+            .extend(items.into_iter().map(|(item, _)| item));
+    }
+
+    /// `open <container>`
+    pub(crate) fn open_chest(&mut self, addr: &SocketAddr, keyword: &str) -> Result<(), String> {
+        let id = self.find_container(addr, keyword)?;
+        let session = self.session(addr)?;
+        let msg = format!("{} opens {}.", session.name, self.world.item_name(id));
+        self.broadcast_location(&session.location, &msg, &[*addr]);
+        self.send_to(addr, Response::game_msg(&self.world.describe_item(id)));
+        Ok(())
+    }
+}
+
+fn roll_table<R: Rng>(
+    tables: &HashMap<String, LootTable>,
+    table: &str,
+    context: &LootContext,
+    rng: &mut R,
+    drops: &mut Vec<LootDrop>,
+) {
+    let Some(table) = tables.get(table) else {
+        return;
+    };
+
+    for entry in &table.always {
+        if context.allows(&entry.conditions) {
+            roll_entry(tables, entry, context, rng, drops);
+        }
+    }
+
+    let entries = table
+        .entries
+        .iter()
+        .filter(|entry| entry.weight() > 0 && context.allows(&entry.conditions))
+        .collect::<Vec<_>>();
+    let total = entries.iter().map(|entry| entry.weight()).sum::<u32>();
+    if total == 0 {
+        return;
+    }
+    for _ in 0..table.rolls {
+        let mut pick = rng.gen_range(0..total);
+        for entry in &entries {
+            if pick < entry.weight() {
+                roll_entry(tables, entry, context, rng, drops);
+                break;
+            }
+            pick -= entry.weight();
+        }
+    }
+}
+
+fn roll_entry<R: Rng>(
+    tables: &HashMap<String, LootTable>,
+    entry: &LootEntry,
+    context: &LootContext,
+    rng: &mut R,
+    drops: &mut Vec<LootDrop>,
+) {
+    let count = rng.gen_range(entry.min..=entry.max);
+    match &entry.loot {
+        Loot::Item(item) if count > 0 => drops.push(LootDrop {
+            item: item.clone(),
+            count,
+            rarity: entry.rarity,
+        }),
+        Loot::Table(table) => {
+            for _ in 0..count {
+                roll_table(tables, table, context, rng, drops);
+            }
+        }
+        _ => {}
+    }
+}
+
+/// How a drop is named in messages, e.g. "a wolf fang (rare)".
+pub fn loot_name(name: &str, rarity: Rarity) -> String {
+    match rarity {
+        Rarity::Common => name.to_string(),
+        rarity => format!("{name} ({})", rarity.name()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::BTreeMap;
+
+    use rand::rngs::StdRng;
+    use rand::SeedableRng;
+
+    use super::*;
+
+    const ROLLS: usize = 20_000;
+
+    fn tables(ron: &str) -> HashMap<String, LootTable> {
+        ron::from_str::<Vec<LootTable>>(ron)
+            .unwrap()
+            .into_iter()
+            .map(|table| (table.id.clone(), table))
+            .collect()
+    }
+
+    fn roll(
+        tables: &HashMap<String, LootTable>,
+        table: &str,
+        context: &LootContext,
+        rng: &mut StdRng,
+    ) -> Vec<LootDrop> {
+        let mut drops = vec![];
+        roll_table(tables, table, context, rng, &mut drops);
+        drops
+    }
+
+    /// How often each item drops per roll of `table`, by template.
+    fn rates(
+        tables: &HashMap<String, LootTable>,
+        table: &str,
+        context: &LootContext,
+        seed: u64,
+    ) -> BTreeMap<String, f64> {
+        let mut rng = StdRng::seed_from_u64(seed);
+        let mut counts = BTreeMap::<String, u32>::new();
+        for _ in 0..ROLLS {
+            for drop in roll(tables, table, context, &mut rng) {
+                *counts.entry(drop.item).or_default() += 1;
+            }
+        }
+        counts
+            .into_iter()
+            .map(|(item, count)| (item, count as f64 / ROLLS as f64))
+            .collect()
+    }
+
+    fn assert_near(rates: &BTreeMap<String, f64>, item: &str, expected: f64) {
+        let rate = rates.get(item).copied().unwrap_or(0.0);
+        assert!(
+            (rate - expected).abs() < 0.015,
+            "{item} dropped at {rate}, expected about {expected}"
+        );
+    }
+
+    #[test]
+    fn weighted_rates_match_configuration() {
+        let tables = tables(
+            r#"[(
+                id: "test",
+                entries: [
+                    (loot: Item("bread")),
+                    (loot: Item("torch"), weight: Some(50)),
+                    (loot: Nothing, weight: Some(50)),
+                ],
+            )]"#,
+        );
+        let rates = rates(&tables, "test", &LootContext::default(), 1);
+        assert_near(&rates, "bread", 0.5);
+        assert_near(&rates, "torch", 0.25);
+    }
+
+    #[test]
+    fn nested_tables_use_their_rarity_weights() {
+        let tables = tables(
+            r#"[
+                (id: "chest", entries: [(loot: Table("trinkets"))]),
+                (
+                    id: "trinkets",
+                    entries: [
+                        (loot: Item("gem"), rarity: Rare),
+                        (loot: Item("ring"), rarity: Epic),
+                    ],
+                ),
+            ]"#,
+        );
+        let rates = rates(&tables, "chest", &LootContext::default(), 2);
+        assert_near(&rates, "gem", 0.8);
+        assert_near(&rates, "ring", 0.2);
+    }
+
+    #[test]
+    fn conditions_remove_entries_from_the_roll() {
+        let tables = tables(
+            r#"[(
+                id: "rack",
+                entries: [
+                    (loot: Item("sword"), conditions: [Class("warrior")]),
+                    (loot: Item("staff")),
+                ],
+            )]"#,
+        );
+        let mage = LootContext {
+            class: "mage".to_string(),
+            ..Default::default()
+        };
+        let warrior = LootContext {
+            class: "warrior".to_string(),
+            ..Default::default()
+        };
+        assert!(!rates(&tables, "rack", &mage, 3).contains_key("sword"));
+        assert_near(&rates(&tables, "rack", &warrior, 3), "sword", 0.5);
+    }
+
+    #[test]
+    fn same_seed_same_drops() {
+        let tables = tables(
+            r#"[(
+                id: "wolf",
+                always: [(loot: Item("pelt"))],
+                rolls: 2,
+                entries: [
+                    (loot: Item("fang"), min: 1, max: 3),
+                    (loot: Item("claw"), rarity: Uncommon),
+                    (loot: Nothing, rarity: Rare),
+                ],
+            )]"#,
+        );
+        let context = LootContext::default();
+        let roll_all = |seed| {
+            let mut rng = StdRng::seed_from_u64(seed);
+            (0..100)
+                .map(|_| roll(&tables, "wolf", &context, &mut rng))
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(roll_all(9), roll_all(9));
+    }
+}
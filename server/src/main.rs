@@ -1,19 +1,94 @@
+mod character;
+mod clock;
+mod combat;
+mod crafting;
+mod death;
+mod dialogue;
+mod door;
+mod faction;
+mod instance;
+mod item;
+mod loot;
+mod map;
+mod npc;
+mod olc;
+mod party;
+mod pvp;
+mod quest;
 mod request;
-
-use std::collections::HashMap;
+mod reset;
+mod script;
+mod shop;
+mod skill;
+mod social;
+mod speech;
+mod trade;
+mod travel;
+mod weather;
+mod world;
+
+use std::collections::{BTreeMap, BTreeSet, HashMap, HashSet};
 use std::net::SocketAddr;
+use std::path::Path;
 use std::sync::Arc;
-
-use model::{ChatMessage, Response, UserAction};
+use std::time::Duration;
+
+use character::{Character, SaveFile};
+use clock::Clock;
+use combat::Combatant;
+use dialogue::Conversation;
+use instance::Instance;
+use item::ItemId;
+use model::{ChatMessage, Response, ServerResponse, UserAction, WearSlot};
+use olc::Snapshot;
+use party::{Party, PartyId};
+use quest::QuestLog;
+use rand::rngs::StdRng;
+use rand::SeedableRng;
 use request::send_response;
+use skill::Affect;
 use tokio::net::{TcpListener, TcpStream};
 use tokio::sync::{mpsc, Mutex};
 use tokio_stream::StreamExt;
-use tokio_util::codec::{BytesCodec, Framed, LengthDelimitedCodec};
+use tokio_util::codec::{Framed, LengthDelimitedCodec};
+use trade::Trade;
+use world::{LocationId, World};
+
+/// How much weight a user with average strength can carry, including worn equipment.
+const CARRY_CAPACITY: u32 = 100;
+/// Extra carrying capacity for each point of strength above 10.
+const CARRY_PER_STRENGTH: i32 = 5;
 
 pub struct UserSession {
     name: String,
     send: mpsc::UnboundedSender<model::Response>,
+    location: LocationId,
+    inventory: Vec<ItemId>,
+    equipment: BTreeMap<WearSlot, ItemId>,
+    character: Character,
+    quests: QuestLog,
+    /// Story flags set by dialogue.
+    flags: BTreeSet<String>,
+    conversation: Option<Conversation>,
+    /// Game ticks until each skill can be used again, by skill id.
+    cooldowns: BTreeMap<String, u32>,
+    /// Area edits a builder can take back, most recent last.
+    undo: Vec<Snapshot>,
+    /// Game ticks until the player can turn PvP on or off again.
+    pvp_cooldown: u32,
+    /// Where the player is walking to with `travel` or `walk`.
+    walk: Option<LocationId>,
+}
+
+impl UserSession {
+    pub fn carry_capacity(&self) -> u32 {
+        let bonus = (self.character.attributes.strength - 10) * CARRY_PER_STRENGTH;
+        CARRY_CAPACITY.saturating_add_signed(bonus)
+    }
+
+    pub fn equipment_ids(&self) -> Vec<ItemId> {
+        self.equipment.values().copied().collect()
+    }
 }
 
 struct User {
@@ -25,16 +100,48 @@ impl User {
     pub async fn new(
         state: Arc<Mutex<Shared>>,
         lines: Framed<TcpStream, LengthDelimitedCodec>,
+        save: SaveFile,
     ) -> std::io::Result<(User, String)> {
         let addr = lines.get_ref().peer_addr()?;
 
         let (tx, rx) = mpsc::unbounded_channel();
 
         let mut state = state.lock().await;
-        let name = state.gen_username();
+        let name = save.character.name.clone();
+        let location = if state.world.locations.contains_key(&save.location) {
+            save.location
+        } else {
+            state.world.config.start_location.clone()
+        };
+        let inventory = save
+            .inventory
+            .iter()
+            .filter_map(|item| state.world.restore_item(item))
+            .collect();
+        let equipment = save
+            .equipment
+            .iter()
+            .filter_map(|(slot, item)| Some((*slot, state.world.restore_item(item)?)))
+            .collect();
+        let mut cooldowns = save.cooldowns;
+        cooldowns.retain(|id, ticks| {
+            *ticks > 0 && state.world.skills.iter().any(|skill| skill.id == *id)
+        });
+
         let session = UserSession {
             name: name.clone(),
             send: tx,
+            location,
+            inventory,
+            equipment,
+            character: save.character,
+            quests: state.world.restore_quests(save.quests),
+            flags: save.flags,
+            conversation: None,
+            cooldowns,
+            undo: vec![],
+            pvp_cooldown: save.pvp_cooldown,
+            walk: None,
         };
         state.add_user(addr, session);
 
@@ -46,26 +153,98 @@ impl User {
 // would make adding and removing users easier
 pub struct Shared {
     peers: HashMap<SocketAddr, UserSession>,
+    /// Lowercased names of players part way through logging in, so nobody else can
+    /// take them before they join.
+    logging_in: HashSet<String>,
     rooms: HashMap<String, Vec<SocketAddr>>,
-    user_count: usize,
+    world: World,
+    rng: StdRng,
+    /// Who each combatant is currently attacking.
+    fights: HashMap<Combatant, Combatant>,
+    /// Buffs and debuffs on each combatant.
+    affects: HashMap<Combatant, Vec<Affect>>,
+    /// Trades in progress and invitations to trade.
+    trades: Vec<Trade>,
+    parties: HashMap<PartyId, Party>,
+    next_party_id: u64,
+    /// Copies of instanced areas, by the id of the copy.
+    instances: HashMap<String, Instance>,
+    next_instance: u64,
+    /// Ticks since the last combat round.
+    combat_timer: u32,
+    /// Ticks since players last regenerated.
+    regen_timer: u32,
+    /// Ticks since shops last restocked.
+    restock_timer: u32,
+    /// The time of day in the game world.
+    clock: Clock,
+    /// How many script hooks are currently running inside each other.
+    script_depth: u32,
 }
 
 impl Shared {
-    pub fn new() -> Self {
+    pub fn new(world: World) -> Self {
+        let rng = match world.config.rng_seed {
+            Some(seed) => StdRng::seed_from_u64(seed),
+            None => StdRng::from_entropy(),
+        };
+        let clock = Clock::new(&world.config.clock);
         Shared {
             peers: HashMap::new(),
+            logging_in: HashSet::new(),
             rooms: HashMap::from([(String::from("main"), vec![])]),
-            user_count: 0,
+            world,
+            rng,
+            fights: HashMap::new(),
+            affects: HashMap::new(),
+            trades: vec![],
+            parties: HashMap::new(),
+            next_party_id: 0,
+            instances: HashMap::new(),
+            next_instance: 0,
+            combat_timer: 0,
+            regen_timer: 0,
+            restock_timer: 0,
+            clock,
+            script_depth: 0,
+        }
+    }
+
+    /// Advances the game world by one tick.
+    pub fn tick(&mut self) {
+        self.npc_tick();
+        self.combat_tick();
+        self.corpse_tick();
+        self.pvp_tick();
+        self.travel_tick();
+        self.regen_tick();
+        self.skill_tick();
+        self.shop_tick();
+        self.reset_tick();
+        self.instance_tick();
+        self.clock_tick();
+        self.weather_tick();
+        self.script_tick();
+    }
+
+    fn session(&self, addr: &SocketAddr) -> Result<&UserSession, String> {
+        Ok(self.peers.get(addr).ok_or("No session for address")?)
+    }
+
+    fn send_to(&self, addr: &SocketAddr, res: Response) {
+        if let Some(session) = self.peers.get(addr) {
+            let _ = session.send.send(res);
         }
     }
 
     fn add_user(&mut self, addr: SocketAddr, session: UserSession) {
         self.peers.insert(addr, session);
-        self.user_count += 1;
     }
 
-    fn gen_username(&self) -> String {
-        format!("User-{}", self.user_count)
+    fn is_online(&self, name: &str) -> bool {
+        self.peers
+            .values()
+            .any(|session| session.name.eq_ignore_ascii_case(name))
     }
 
     fn add_user_to_room(&mut self, user: &SocketAddr, room_name: &str) -> Result<(), String> {
@@ -103,13 +282,13 @@ impl Shared {
 
         session
             .send
-            .send(Response::private_msg(msg, &from_name))
+            .send(Response::private_msg(msg, from_name))
             .map_err(|e| format!("Error sending pm: {e:?}"))
     }
 
     async fn broadcast(
         &mut self,
-        sender: &SocketAddr,
+        _sender: &SocketAddr,
         message: &str,
         from: Option<&str>,
         room_name: &str,
@@ -140,7 +319,18 @@ impl Shared {
 #[tokio::main]
 async fn main() -> Result<(), Box<dyn std::error::Error>> {
     let listener = TcpListener::bind("127.0.0.1:8080").await?;
-    let state = Arc::new(Mutex::new(Shared::new()));
+    let world = World::load(&Path::new(env!("CARGO_MANIFEST_DIR")).join("data"))?;
+    let tick_length = Duration::from_millis(world.config.tick_ms);
+    let state = Arc::new(Mutex::new(Shared::new(world)));
+
+    let tick_state = Arc::clone(&state);
+    tokio::spawn(async move {
+        let mut interval = tokio::time::interval(tick_length);
+        loop {
+            interval.tick().await;
+            tick_state.lock().await.tick();
+        }
+    });
 
     loop {
         let (socket, addr) = listener.accept().await?;
@@ -159,27 +349,38 @@ async fn process(
     state: Arc<Mutex<Shared>>,
     addr: SocketAddr,
 ) -> Result<(), Box<dyn std::error::Error>> {
-    let bytes = Framed::new(stream, LengthDelimitedCodec::new());
-    let (mut user, name) = User::new(state.clone(), bytes).await?;
-
-    let username = match user.bytes.next().await {
-        Some(Ok(msg)) => {
-            let name_req: UserAction = bincode::deserialize(&msg[..]).unwrap();
-            if let UserAction::Chat(ChatMessage::Username(name)) = name_req {
-                name
-            } else {
-                return Ok(());
+    let mut bytes = Framed::new(stream, LengthDelimitedCodec::new());
+
+    // keep asking until the client picks a usable name
+    let save = loop {
+        let username = match bytes.next().await {
+            Some(Ok(msg)) => {
+                let name_req: UserAction = bincode::deserialize(&msg[..]).unwrap();
+                if let UserAction::Chat(ChatMessage::Username(name)) = name_req {
+                    name
+                } else {
+                    return Ok(());
+                }
             }
+            _ => return Ok(()),
+        };
+
+        println!("Got username: {username}");
+
+        if let Some(save) = login(&state, &mut bytes, &username).await? {
+            break save;
         }
-        _ => return Ok(()),
     };
-
-    println!("Got username: {username}");
+    let reserved = save.character.name.to_lowercase();
+    let joined = User::new(state.clone(), bytes, save).await;
+    // the player is in `peers` now, or failed to join
+    state.lock().await.logging_in.remove(&reserved);
+    let (mut user, name) = joined?;
 
     send_response(
         &mut user.bytes,
         Response::Server(model::ServerResponse::JoinedServer {
-            username: username.clone(),
+            username: name.clone(),
         }),
     )
     .await;
@@ -192,6 +393,16 @@ async fn process(
         state
             .broadcast(&addr, &format!("{name} has joined the chat."), None, "main")
             .await;
+
+        let location = state.peers[&addr].location.clone();
+        state.broadcast_location(&location, &format!("{name} appears."), &[addr]);
+        state.look(&addr, &[])?;
+        state.npcs_greet(&addr);
+        state.send_inventory(&addr)?;
+        state.send_vitals(&addr);
+        state.send_quest_log(&addr);
+        state.send_skills(&addr);
+        state.fire_enter(&addr);
     }
 
     loop {
@@ -227,8 +438,122 @@ async fn process(
         let mut state = state.lock().await;
         let msg = format!("{name} has left the chat.");
         state.broadcast(&addr, &msg, None, "main").await;
+
+        let location = state.peers[&addr].location.clone();
+        state.broadcast_location(&location, &format!("{name} vanishes."), &[addr]);
+        state.end_fights(Combatant::Player(addr));
+        state.affects.remove(&Combatant::Player(addr));
+        state.cancel_trades(&addr);
+        let _ = state.leave_party(&addr);
+        if let Err(e) = state.save_character(&addr) {
+            eprintln!("Failed to save {name}: {e}");
+        }
+        state.destroy_belongings(&addr);
         state.remove_user(&addr);
     }
 
     Ok(())
 }
+
+/// Loads the saved character for `username`, or takes the client through creating one.
+/// Returns `None` if the name was rejected or the client disconnected part way through.
+/// On success the name stays reserved until the caller has added the player.
+async fn login(
+    state: &Arc<Mutex<Shared>>,
+    bytes: &mut Framed<TcpStream, LengthDelimitedCodec>,
+    username: &str,
+) -> Result<Option<SaveFile>, Box<dyn std::error::Error>> {
+    let name = {
+        let mut state = state.lock().await;
+        // reserved under the same lock as the check, so two clients can't both get past it
+        match character::validate_name(username) {
+            Ok(name) if state.is_online(&name) || !state.logging_in.insert(name.to_lowercase()) => {
+                Err(format!("{name} is already playing."))
+            }
+            result => result,
+        }
+    };
+    let name = match name {
+        Ok(name) => name,
+        Err(reason) => {
+            send_response(bytes, login_failed(reason)).await;
+            return Ok(None);
+        }
+    };
+
+    let result = load_or_create(state, bytes, &name)
+        .await
+        .map_err(|e| e.to_string());
+    if !matches!(result, Ok(Some(_))) {
+        state.lock().await.logging_in.remove(&name.to_lowercase());
+    }
+    Ok(result?)
+}
+
+fn login_failed(reason: String) -> Response {
+    Response::Server(ServerResponse::LoginFailed { reason })
+}
+
+/// Loads the saved character called `name`, or takes the client through creating one.
+async fn load_or_create(
+    state: &Arc<Mutex<Shared>>,
+    bytes: &mut Framed<TcpStream, LengthDelimitedCodec>,
+    name: &str,
+) -> Result<Option<SaveFile>, Box<dyn std::error::Error>> {
+    let choices = {
+        let state = state.lock().await;
+        if let Some(save) = character::load_save(&state.world.save_dir(), name)? {
+            return Ok(Some(save));
+        }
+
+        let rules = &state.world.character_rules;
+        Response::Server(ServerResponse::ChooseCharacter {
+            races: rules.race_options(),
+            classes: rules.class_options(),
+        })
+    };
+
+    send_response(bytes, choices.clone()).await;
+
+    while let Some(Ok(msg)) = bytes.next().await {
+        let req: UserAction = bincode::deserialize(&msg[..])?;
+        let UserAction::CreateCharacter { race, class } = req else {
+            continue;
+        };
+
+        let created = {
+            let state = state.lock().await;
+            state
+                .world
+                .character_rules
+                .new_character(name, &race, &class)
+                .map(|character| SaveFile {
+                    character: Character {
+                        gold: state.world.economy.starting_gold,
+                        ..character
+                    },
+                    location: state.world.config.start_location.clone(),
+                    inventory: vec![],
+                    equipment: BTreeMap::new(),
+                    quests: QuestLog::default(),
+                    flags: BTreeSet::new(),
+                    cooldowns: BTreeMap::new(),
+                    pvp_cooldown: 0,
+                })
+                .and_then(|save| {
+                    character::write_save(&state.world.save_dir(), &save)?;
+                    Ok(save)
+                })
+        };
+
+        match created {
+            Ok(save) => return Ok(Some(save)),
+            Err(reason) => {
+                send_response(bytes, login_failed(reason)).await;
+                send_response(bytes, choices.clone()).await;
+            }
+        }
+    }
+
+    Ok(None)
+}
@@ -0,0 +1,136 @@
+use std::collections::{HashMap, VecDeque};
+use std::net::SocketAddr;
+
+use model::{GameUpdate, MapDirection, MapExit, MapRoom, MapView, Response};
+
+use crate::world::{Direction, LocationId, World};
+use crate::Shared;
+
+/// How many rooms the map reaches out from the player in each direction.
+const MAP_RADIUS: i32 = 3;
+
+impl Direction {
+    /// Where the room this way is drawn on the map, or `None` for up and down.
+    fn map_offset(self) -> Option<(i32, i32)> {
+        match self {
+            Direction::North => Some((0, -1)),
+            Direction::East => Some((1, 0)),
+            Direction::South => Some((0, 1)),
+            Direction::West => Some((-1, 0)),
+            Direction::Up | Direction::Down => None,
+        }
+    }
+
+    fn map_direction(self) -> MapDirection {
+        match self {
+            Direction::North => MapDirection::North,
+            Direction::East => MapDirection::East,
+            Direction::South => MapDirection::South,
+            Direction::West => MapDirection::West,
+            Direction::Up => MapDirection::Up,
+            Direction::Down => MapDirection::Down,
+        }
+    }
+}
+
+impl World {
+    /// Lays out the locations around `location` on a grid by walking outwards through
+    /// exits nobody needs to search for. Exits that would put a room where another
+    /// already is, and ways up and down, aren't followed.
+    pub fn map_around(&self, location: &str) -> MapView {
+        let mut placed = HashMap::<LocationId, (i32, i32)>::from([(location.to_string(), (0, 0))]);
+        let mut taken = HashMap::from([((0, 0), location.to_string())]);
+        let mut queue = VecDeque::from([location.to_string()]);
+
+        while let Some(id) = queue.pop_front() {
+            let (x, y) = placed[&id];
+            let here = &self.locations[&id];
+            for (dir, exit) in &here.exits {
+                let Some((dx, dy)) = dir.map_offset() else {
+                    continue;
+                };
+                let pos = (x + dx, y + dy);
+                if here.hidden.contains(dir)
+                    || pos.0.abs() > MAP_RADIUS
+                    || pos.1.abs() > MAP_RADIUS
+                    || placed.contains_key(&exit.to)
+                    || taken.contains_key(&pos)
+                {
+                    continue;
+                }
+                placed.insert(exit.to.clone(), pos);
+                taken.insert(pos, exit.to.clone());
+                queue.push_back(exit.to.clone());
+            }
+        }
+
+        let mut rooms = placed
+            .iter()
+            .map(|(id, &(x, y))| {
+                let here = &self.locations[id];
+                let exits = here
+                    .exits
+                    .iter()
+                    .filter(|(dir, _)| !here.hidden.contains(dir))
+                    .map(|(dir, exit)| {
+                        let odd = dir.map_offset().is_some_and(|(dx, dy)| {
+                            let pos = (x + dx, y + dy);
+                            match (placed.get(&exit.to), taken.get(&pos)) {
+                                (Some(at), _) => *at != pos,
+                                (None, Some(_)) => true,
+                                (None, None) => false,
+                            }
+                        });
+                        MapExit {
+                            dir: dir.map_direction(),
+                            odd,
+                        }
+                    })
+                    .collect();
+                MapRoom {
+                    x,
+                    y,
+                    name: here.name.clone(),
+                    exits,
+                }
+            })
+            .collect::<Vec<_>>();
+        rooms.sort_by_key(|room| (room.y, room.x));
+
+        MapView {
+            location: self.locations[location].name.clone(),
+            rooms,
+        }
+    }
+}
+
+impl Shared {
+    pub(crate) fn send_map(&self, addr: &SocketAddr) {
+        let Ok(session) = self.session(addr) else {
+            return;
+        };
+        let map = self.world.map_around(&session.location);
+        self.send_to(addr, Response::Game(GameUpdate::Map(map)));
+    }
+
+    /// Sends everyone in `area` their map again, after its exits have changed.
+    pub(crate) fn send_maps_in_area(&self, area: &str) {
+        for (addr, session) in &self.peers {
+            if self.world.locations[&session.location].area == area {
+                self.send_map(addr);
+            }
+        }
+    }
+
+    /// `map`
+    pub fn show_map(&self, addr: &SocketAddr) -> Result<(), String> {
+        let session = self.session(addr)?;
+        let map = self.world.map_around(&session.location);
+        let mut msg = format!("Map around {}:", map.location);
+        for line in map.render() {
+            msg.push_str(&format!("\n{line}"));
+        }
+        self.send_to(addr, Response::game_msg(&msg));
+        Ok(())
+    }
+}
@@ -0,0 +1,338 @@
+use std::collections::BTreeMap;
+use std::net::SocketAddr;
+
+use model::SpeechKind;
+use rand::seq::SliceRandom;
+use rand::Rng;
+use serde::{Deserialize, Serialize};
+
+use crate::combat::Combatant;
+use crate::item::ItemId;
+use crate::reset::SpawnId;
+use crate::shop::ShopDef;
+use crate::social::Pronouns;
+use crate::world::{is_default, Direction, LocationId, World};
+use crate::Shared;
+
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct NpcId(pub u64);
+
+#[derive(Serialize, Deserialize, Clone, Debug, Default)]
+pub struct NpcStats {
+    pub level: u32,
+    pub max_hp: i32,
+    #[serde(default, skip_serializing_if = "is_default")]
+    pub attack: i32,
+    #[serde(default, skip_serializing_if = "is_default")]
+    pub defense: i32,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub enum Behaviour {
+    /// Each tick, moves through a random exit within its own area with this probability.
+    Wander { chance: f64 },
+    /// Attacks any player it shares a location with.
+    Aggressive,
+    /// Stays put and greets players as they arrive.
+    Shopkeeper,
+    /// Stops players from leaving in `direction`.
+    Guard { direction: Direction },
+}
+
+/// The shared definition of an NPC, loaded from an area file.
+#[derive(Serialize, Deserialize, Clone, Debug, Default)]
+pub struct NpcTemplate {
+    pub id: String,
+    pub name: String,
+    pub keywords: Vec<String>,
+    /// The line shown in room descriptions, e.g. "A guard leans on his spear here."
+    pub long: String,
+    #[serde(default, skip_serializing_if = "String::is_empty")]
+    pub description: String,
+    /// Used when players aim socials at the NPC.
+    #[serde(default, skip_serializing_if = "is_default")]
+    pub pronouns: Pronouns,
+    pub stats: NpcStats,
+    /// Things the NPC says out loud from time to time.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub dialogue: Vec<String>,
+    /// Actions rendered as "<name> <emote>", e.g. "scratches behind an ear."
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub emotes: Vec<String>,
+    /// Probability each tick of saying or emoting something.
+    #[serde(default, skip_serializing_if = "is_default")]
+    pub chatter_chance: f64,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub behaviours: Vec<Behaviour>,
+    /// The faction it belongs to, which decides how it treats players.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub faction: Option<String>,
+    /// Loot table rolled when the NPC dies.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub loot: Option<String>,
+    /// Makes the NPC a shopkeeper that players can `buy` from and `sell` to.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub shop: Option<ShopDef>,
+    /// Id of the dialogue tree used when players `talk` to this NPC.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub dialogue_tree: Option<String>,
+    /// Name of a script in `data/scripts` whose hooks run for this NPC.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub script: Option<String>,
+}
+
+impl NpcTemplate {
+    pub fn matches(&self, keyword: &str) -> bool {
+        let keyword = keyword.to_lowercase();
+        self.keywords.iter().any(|k| k.starts_with(&keyword))
+    }
+
+    pub fn has_behaviour(&self, behaviour: &Behaviour) -> bool {
+        self.behaviours.contains(behaviour)
+    }
+
+    pub fn wander_chance(&self) -> Option<f64> {
+        self.behaviours.iter().find_map(|b| match b {
+            Behaviour::Wander { chance } => Some(*chance),
+            _ => None,
+        })
+    }
+
+    pub fn guards(&self, dir: Direction) -> bool {
+        self.has_behaviour(&Behaviour::Guard { direction: dir })
+    }
+}
+
+/// A single NPC in the world.
+pub struct Npc {
+    pub id: NpcId,
+    pub template: String,
+    pub location: LocationId,
+    pub hp: i32,
+    /// How many of each item template a shopkeeper has for sale.
+    pub stock: BTreeMap<String, u32>,
+    /// Items it's wearing or wielding, dropped when it dies.
+    pub equipment: Vec<ItemId>,
+    /// The area rule that spawned it, if any.
+    pub spawn: Option<SpawnId>,
+}
+
+impl World {
+    /// Checks that every NPC's chances are probabilities.
+    pub(crate) fn validate_npcs(&self) -> Result<(), String> {
+        for template in self.npc_templates.values() {
+            if !(0.0..=1.0).contains(&template.chatter_chance) {
+                return Err(format!(
+                    "NPC {} has a chatter chance outside 0 to 1",
+                    template.id
+                ));
+            }
+            if template
+                .wander_chance()
+                .is_some_and(|chance| !(0.0..=1.0).contains(&chance))
+            {
+                return Err(format!(
+                    "NPC {} has a wander chance outside 0 to 1",
+                    template.id
+                ));
+            }
+        }
+        Ok(())
+    }
+
+    pub fn spawn_npc(&mut self, template: &str, location: &str) -> Result<NpcId, String> {
+        let hp = self
+            .npc_templates
+            .get(template)
+            .ok_or(format!("Unknown NPC template {template}"))?
+            .stats
+            .max_hp;
+        let stock = self.initial_stock(template);
+
+        let id = NpcId(self.next_npc_id);
+        self.next_npc_id += 1;
+        self.npcs.insert(
+            id,
+            Npc {
+                id,
+                template: template.to_string(),
+                location: location.to_string(),
+                hp,
+                stock,
+                equipment: vec![],
+                spawn: None,
+            },
+        );
+        Ok(id)
+    }
+
+    /// Removes an NPC from the world along with anything it's wearing.
+    pub fn remove_npc(&mut self, id: NpcId) {
+        if let Some(npc) = self.npcs.remove(&id) {
+            for item in npc.equipment {
+                self.destroy_item(item);
+            }
+        }
+    }
+
+    pub fn npc_template(&self, id: NpcId) -> &NpcTemplate {
+        &self.npc_templates[&self.npcs[&id].template]
+    }
+
+    pub fn npcs_in_location<'a>(&'a self, location: &'a str) -> impl Iterator<Item = &'a Npc> + 'a {
+        self.npcs
+            .values()
+            .filter(move |npc| npc.location == location)
+    }
+
+    pub fn find_npc(&self, location: &str, keyword: &str) -> Option<NpcId> {
+        self.npcs_in_location(location)
+            .find(|npc| self.npc_template(npc.id).matches(keyword))
+            .map(|npc| npc.id)
+    }
+}
+
+impl Shared {
+    pub fn npc_say(&self, id: NpcId, msg: &str) {
+        let npc = &self.world.npcs[&id];
+        let name = capitalize(&self.world.npc_template(id).name);
+        self.broadcast_speech(
+            &npc.location,
+            SpeechKind::Say,
+            &format!("{name} says, '{msg}'"),
+            &[],
+        );
+    }
+
+    pub fn npc_emote(&self, id: NpcId, action: &str) {
+        let npc = &self.world.npcs[&id];
+        let name = capitalize(&self.world.npc_template(id).name);
+        self.broadcast_speech(
+            &npc.location,
+            SpeechKind::Emote,
+            &format!("{name} {action}"),
+            &[],
+        );
+    }
+
+    /// Runs every NPC's behaviours for one game tick.
+    pub(crate) fn npc_tick(&mut self) {
+        let mut ids = self.world.npcs.keys().copied().collect::<Vec<_>>();
+        ids.sort();
+
+        for id in ids {
+            let template = self.world.npc_template(id).clone();
+            let location = self.world.npcs[&id].location.clone();
+            let fighting = self.is_fighting(Combatant::Npc(id));
+
+            if !fighting && !template.has_behaviour(&Behaviour::Shopkeeper) {
+                if let Some(chance) = template.wander_chance() {
+                    if self.rng.gen_bool(chance) {
+                        self.npc_wander(id);
+                        continue;
+                    }
+                }
+            }
+
+            let mut players = self
+                .users_in_location(&location)
+                .map(|(addr, _)| *addr)
+                .collect::<Vec<_>>();
+            players.sort();
+            // Nobody around to hear it.
+            if players.is_empty() {
+                continue;
+            }
+
+            // Aggressive NPCs, and any NPC whose faction hates a player, attack on sight.
+            let targets = players
+                .iter()
+                .copied()
+                .filter(|addr| self.world.attacks_on_sight(id, &self.peers[addr].character))
+                .collect::<Vec<_>>();
+            if !fighting && !targets.is_empty() {
+                let target = Combatant::Player(*targets.choose(&mut self.rng).unwrap());
+                if self.check_attack(Combatant::Npc(id), target).is_ok() {
+                    self.announce_attack(Combatant::Npc(id), target);
+                    self.start_fight(Combatant::Npc(id), target);
+                    continue;
+                }
+            }
+            if template.has_behaviour(&Behaviour::Aggressive) {
+                continue;
+            }
+
+            if template.chatter_chance > 0.0 && self.rng.gen_bool(template.chatter_chance) {
+                let say = !template.dialogue.is_empty()
+                    && (template.emotes.is_empty() || self.rng.gen_bool(0.5));
+                if say {
+                    let line = template.dialogue.choose(&mut self.rng).unwrap();
+                    self.npc_say(id, line);
+                } else if let Some(action) = template.emotes.choose(&mut self.rng) {
+                    self.npc_emote(id, action);
+                }
+            }
+        }
+    }
+
+    fn npc_wander(&mut self, id: NpcId) {
+        let npc = &self.world.npcs[&id];
+        let from = npc.location.clone();
+        let area = &self.world.locations[&from].area;
+
+        let exits = self.world.locations[&from]
+            .exits
+            .iter()
+            .filter(|(dir, exit)| {
+                &self.world.locations[&exit.to].area == area
+                    && self.world.check_exit(&from, **dir).is_ok()
+            })
+            .map(|(dir, exit)| (*dir, exit.to.clone()))
+            .collect::<Vec<_>>();
+        let Some((dir, to)) = exits.choose(&mut self.rng).cloned() else {
+            return;
+        };
+
+        let name = capitalize(&self.world.npc_template(id).name);
+        self.broadcast_location(&from, &format!("{name} leaves {}.", dir.name()), &[]);
+        self.world.npcs.get_mut(&id).unwrap().location = to.clone();
+        self.broadcast_location(&to, &format!("{name} arrives."), &[]);
+    }
+
+    /// Lets NPCs react to a player arriving in their location.
+    pub(crate) fn npcs_greet(&self, addr: &SocketAddr) {
+        let Ok(session) = self.session(addr) else {
+            return;
+        };
+
+        for npc in self.world.npcs_in_location(&session.location) {
+            let template = self.world.npc_template(npc.id);
+            if self.world.attacks_on_sight(npc.id, &session.character) {
+                match template.has_behaviour(&Behaviour::Aggressive) {
+                    true => {
+                        self.npc_emote(npc.id, &format!("bares its teeth at {}.", session.name))
+                    }
+                    false => self.npc_emote(npc.id, &format!("glares at {}.", session.name)),
+                }
+            } else if template.has_behaviour(&Behaviour::Shopkeeper) {
+                self.npc_say(npc.id, &format!("Welcome, {}!", session.name));
+            }
+        }
+    }
+
+    /// Returns the name of an NPC stopping the user from leaving in `dir`, if any.
+    pub(crate) fn blocking_guard(&self, location: &str, dir: Direction) -> Option<String> {
+        self.world
+            .npcs_in_location(location)
+            .find(|npc| self.world.npc_template(npc.id).guards(dir))
+            .map(|npc| capitalize(&self.world.npc_template(npc.id).name))
+    }
+}
+
+pub fn capitalize(s: &str) -> String {
+    let mut chars = s.chars();
+    match chars.next() {
+        Some(first) => first.to_uppercase().chain(chars).collect(),
+        None => String::new(),
+    }
+}
@@ -0,0 +1,769 @@
+use std::collections::BTreeSet;
+use std::net::SocketAddr;
+use std::str::FromStr;
+
+use model::{Response, WearSlot};
+
+use crate::character::Role;
+use crate::door::DoorState;
+use crate::item::ItemTemplate;
+use crate::npc::{capitalize, NpcStats, NpcTemplate};
+use crate::reset;
+use crate::social::Pronouns;
+use crate::world::{write_ron, AreaDef, Direction, Exit, Location, LocationDef, LocationId, World};
+use crate::Shared;
+
+/// How many edits each builder can undo.
+const UNDO_LIMIT: usize = 20;
+
+/// An area as it was before and after a builder's edit.
+pub struct Snapshot {
+    pub area: String,
+    pub before: AreaDef,
+    /// Undoing is refused once the area no longer looks like this.
+    pub after: AreaDef,
+}
+
+impl World {
+    /// Brings the live world in line with an edited area file. Nothing changes if the
+    /// edit clashes with another area or leaves the world invalid.
+    pub(crate) fn apply_area(&mut self, def: AreaDef) -> Result<(), String> {
+        let area = def.id.clone();
+        let others = self
+            .areas
+            .values()
+            .filter(|other| other.id != area)
+            .map(|other| &other.source)
+            .collect::<Vec<_>>();
+        for other in &others {
+            if let Some(location) = def
+                .locations
+                .iter()
+                .find(|l| other.locations.iter().any(|o| o.id == l.id))
+            {
+                return Err(format!("Location {} already exists.", location.id));
+            }
+            if let Some(item) = def
+                .items
+                .iter()
+                .find(|t| other.items.iter().any(|o| o.id == t.id))
+            {
+                return Err(format!("Item {} already exists.", item.id));
+            }
+            if let Some(npc) = def
+                .npcs
+                .iter()
+                .find(|t| other.npcs.iter().any(|o| o.id == t.id))
+            {
+                return Err(format!("NPC {} already exists.", npc.id));
+            }
+        }
+
+        let kept = def.locations.iter().map(|l| &l.id).collect::<BTreeSet<_>>();
+        let removed = self
+            .locations
+            .values()
+            .filter(|l| l.area == area && !kept.contains(&l.id))
+            .map(|l| l.id.clone())
+            .collect::<Vec<_>>();
+        for location in others.iter().flat_map(|other| &other.locations) {
+            if let Some(exit) = location.exits.values().find(|e| removed.contains(&e.to)) {
+                return Err(format!("{} still has an exit to {}.", location.id, exit.to));
+            }
+        }
+        if let Some(exit) = def
+            .locations
+            .iter()
+            .flat_map(|l| l.exits.values())
+            .find(|e| {
+                !kept.contains(&e.to) && self.locations.get(&e.to).is_none_or(|to| to.area == area)
+            })
+        {
+            return Err(format!("There's no location {}.", exit.to));
+        }
+
+        // The edit is tried out on the live world and undone if it doesn't validate.
+        // Removed locations keep their contents until then.
+        let saved = (
+            self.locations.clone(),
+            self.item_templates.clone(),
+            self.npc_templates.clone(),
+            self.areas[&area].clone(),
+        );
+        if let Err(e) = self.rebuild_area(def, &removed) {
+            let (locations, item_templates, npc_templates, live) = saved;
+            self.locations = locations;
+            self.item_templates = item_templates;
+            self.npc_templates = npc_templates;
+            self.areas.insert(area, live);
+            return Err(e);
+        }
+        let (mut locations, ..) = saved;
+        for id in removed {
+            if let Some(location) = locations.remove(&id) {
+                self.locations.insert(id.clone(), location);
+                self.remove_location(&id);
+            }
+        }
+        Ok(())
+    }
+
+    /// Changes the live world to match `def`, with `removed` locations gone, then
+    /// checks that everything still fits together.
+    fn rebuild_area(&mut self, def: AreaDef, removed: &[LocationId]) -> Result<(), String> {
+        let area = def.id.clone();
+        for id in removed {
+            self.locations.remove(id);
+        }
+        for template in &def.items {
+            self.item_templates
+                .insert(template.id.clone(), template.clone());
+        }
+        for template in &def.npcs {
+            self.npc_templates
+                .insert(template.id.clone(), template.clone());
+        }
+
+        let mut spawns = vec![];
+        for location_def in &def.locations {
+            spawns.extend(reset::location_spawns(location_def));
+            let location = self
+                .locations
+                .entry(location_def.id.clone())
+                .or_insert_with(|| Location {
+                    id: location_def.id.clone(),
+                    area: area.clone(),
+                    name: String::new(),
+                    description: String::new(),
+                    exits: Default::default(),
+                    items: vec![],
+                    script: None,
+                    doors: Default::default(),
+                    hidden: BTreeSet::new(),
+                    outdoors: false,
+                    dark: false,
+                    landmark: false,
+                });
+            let old_exits = std::mem::replace(&mut location.exits, location_def.exits.clone());
+            location.name = location_def.name.clone();
+            location.description = location_def.description.clone();
+            location.script = location_def.script.clone();
+            location.outdoors = location_def.outdoors;
+            location.dark = location_def.dark;
+            location.landmark = location_def.landmark;
+            // Exits stay found until the next reset, but new hidden exits start hidden.
+            location.hidden.retain(|dir| {
+                location_def
+                    .exits
+                    .get(dir)
+                    .is_some_and(|exit| exit.hidden.is_some())
+            });
+            for (dir, exit) in &location_def.exits {
+                if exit.hidden.is_some() && !old_exits.contains_key(dir) {
+                    location.hidden.insert(*dir);
+                }
+            }
+        }
+
+        let live = self.areas.get_mut(&area).unwrap();
+        live.name = def.name.clone();
+        live.reset_ticks = def.reset_ticks;
+        live.reset_when_empty = def.reset_when_empty;
+        live.spawns = spawns.into_iter().chain(def.spawns.clone()).collect();
+        live.ambient = def.ambient.clone();
+        if def.weather.is_none() || live.weather.is_none() {
+            live.weather = def.weather.clone();
+        }
+        live.source = def;
+
+        self.link_doors()?;
+        for location in self.locations.values_mut() {
+            let Location { exits, doors, .. } = location;
+            doors.retain(|dir, _| exits.get(dir).is_some_and(|exit| exit.door.is_some()));
+            for (dir, exit) in exits.iter() {
+                if let Some(door) = &exit.door {
+                    doors.entry(*dir).or_insert(DoorState {
+                        closed: door.closed,
+                        locked: door.locked,
+                    });
+                }
+            }
+        }
+
+        self.validate_npcs()?;
+        self.validate_spawns()?;
+        self.validate_quests()?;
+        self.validate_dialogues()?;
+        self.validate_shops()?;
+        self.validate_loot()?;
+        self.validate_death()?;
+        self.validate_crafting()?;
+        self.validate_factions()
+    }
+
+    /// Takes a location out of the world along with everything in it. Players must
+    /// have been moved out first.
+    pub(crate) fn remove_location(&mut self, id: &str) {
+        let npcs = self
+            .npcs_in_location(id)
+            .map(|npc| npc.id)
+            .collect::<Vec<_>>();
+        for npc in npcs {
+            self.remove_npc(npc);
+        }
+        if let Some(location) = self.locations.remove(id) {
+            for item in location.items {
+                self.destroy_item(item);
+            }
+        }
+    }
+
+    /// Writes an area back to the file it was loaded from.
+    pub(crate) fn save_area(&self, area: &str) -> Result<(), String> {
+        let area = &self.areas[area];
+        write_ron(&area.path, &area.source)
+    }
+
+    /// The area whose file defines an item template.
+    fn item_area(&self, id: &str) -> Option<String> {
+        self.areas
+            .values()
+            .find(|area| area.source.items.iter().any(|t| t.id == id))
+            .map(|area| area.id.clone())
+    }
+
+    /// The area whose file defines an NPC template.
+    fn npc_area(&self, id: &str) -> Option<String> {
+        self.areas
+            .values()
+            .find(|area| area.source.npcs.iter().any(|t| t.id == id))
+            .map(|area| area.id.clone())
+    }
+}
+
+/// Turns a name into something usable in an id, e.g. "Old Mill" into "old_mill".
+fn slug(name: &str) -> String {
+    name.split(|c: char| !c.is_ascii_alphanumeric())
+        .filter(|word| !word.is_empty())
+        .map(|word| word.to_lowercase())
+        .collect::<Vec<_>>()
+        .join("_")
+}
+
+/// Keywords for a new template, taken from its name.
+fn keywords(name: &str) -> Vec<String> {
+    name.split_whitespace()
+        .map(|word| word.to_lowercase())
+        .filter(|word| !matches!(word.as_str(), "a" | "an" | "the" | "of"))
+        .collect()
+}
+
+fn number<T: FromStr>(field: &str, value: &str) -> Result<T, String> {
+    value
+        .parse()
+        .map_err(|_| format!("{} must be a number.", capitalize(field)))
+}
+
+fn text(field: &str, words: &[&str]) -> Result<String, String> {
+    if words.is_empty() {
+        return Err(format!("What should the {field} be?"));
+    }
+    Ok(words.join(" "))
+}
+
+impl Shared {
+    fn require_builder(&self, addr: &SocketAddr) -> Result<(), String> {
+        if self.session(addr)?.character.role < Role::Builder {
+            return Err("Only builders can do that.".to_string());
+        }
+        Ok(())
+    }
+
+    /// Makes `edit` to an area's file, applies it to the world and saves it. `edit`
+    /// returns what to tell the builder.
+    fn edit_area(
+        &mut self,
+        addr: &SocketAddr,
+        area: &str,
+        edit: impl FnOnce(&mut AreaDef) -> Result<String, String>,
+    ) -> Result<(), String> {
+        if self.instances.contains_key(area) {
+            return Err(
+                "This is a copy of an instanced area. Edit the original instead.".to_string(),
+            );
+        }
+        let before = self.world.areas[area].source.clone();
+        let mut def = before.clone();
+        let msg = edit(&mut def)?;
+        self.replace_area(def.clone())?;
+
+        let undo = &mut self.peers.get_mut(addr).unwrap().undo;
+        undo.push(Snapshot {
+            area: area.to_string(),
+            before,
+            after: def,
+        });
+        if undo.len() > UNDO_LIMIT {
+            undo.remove(0);
+        }
+        self.send_to(addr, Response::game_msg(&msg));
+        Ok(())
+    }
+
+    /// Swaps an area's file for `def`. Nothing changes if it doesn't fit.
+    fn replace_area(&mut self, def: AreaDef) -> Result<(), String> {
+        let area = def.id.clone();
+        let kept = def.locations.iter().map(|l| &l.id).collect::<BTreeSet<_>>();
+        let start = self.world.config.start_location.clone();
+        let stranded = self
+            .peers
+            .iter()
+            .filter(|(_, session)| {
+                self.world.locations[&session.location].area == area
+                    && !kept.contains(&session.location)
+            })
+            .map(|(addr, _)| *addr)
+            .collect::<Vec<_>>();
+        if !stranded.is_empty()
+            && !kept.contains(&start)
+            && self.world.locations[&start].area == area
+        {
+            return Err("That would leave nowhere to start.".to_string());
+        }
+
+        self.world.apply_area(def)?;
+        for addr in stranded {
+            self.end_fights(crate::combat::Combatant::Player(addr));
+            self.peers.get_mut(&addr).unwrap().location = start.clone();
+            self.send_to(
+                &addr,
+                Response::game_msg("The world shifts around you, and you find yourself elsewhere."),
+            );
+            let _ = self.look(&addr, &[]);
+        }
+
+        self.send_maps_in_area(&area);
+        self.world
+            .save_area(&area)
+            .map_err(|e| format!("The edit was made but couldn't be saved: {e}"))
+    }
+
+    /// `dig <direction> <name>`
+    pub fn dig(&mut self, addr: &SocketAddr, args: &[&str]) -> Result<(), String> {
+        self.require_builder(addr)?;
+        let (dir, name) = args.split_first().ok_or("Dig which way?")?;
+        let dir = Direction::parse(dir).ok_or(format!("{dir} is not a direction."))?;
+        let name = text("name", name)?;
+        let location = &self.world.locations[&self.session(addr)?.location];
+        let (area, from) = (location.area.clone(), location.id.clone());
+        if self.world.locations[&from].exits.contains_key(&dir) {
+            return Err(format!("There's already an exit {}.", dir.name()));
+        }
+
+        let base = format!("{area}:{}", slug(&name));
+        let mut id = base.clone();
+        let mut n = 2;
+        while self.world.locations.contains_key(&id) {
+            id = format!("{base}_{n}");
+            n += 1;
+        }
+
+        let outdoors = self.world.locations[&from].outdoors;
+        self.edit_area(addr, &area, |def| {
+            let here = def
+                .locations
+                .iter_mut()
+                .find(|l| l.id == from)
+                .ok_or("This location isn't in its area's file.")?;
+            here.exits.insert(dir, exit_to(&id));
+            def.locations.push(LocationDef {
+                id: id.clone(),
+                name: name.clone(),
+                description: "Nothing has been built here yet.".to_string(),
+                exits: [(dir.opposite(), exit_to(&from))].into(),
+                outdoors,
+                ..Default::default()
+            });
+            Ok(format!("You dig {} to {name} ({id}).", dir.name()))
+        })
+    }
+
+    /// `redit [name|desc|exit|outdoors|dark|landmark|item|npc] ...`
+    pub fn redit(&mut self, addr: &SocketAddr, args: &[&str]) -> Result<(), String> {
+        self.require_builder(addr)?;
+        let location = &self.world.locations[&self.session(addr)?.location];
+        let (area, id) = (location.area.clone(), location.id.clone());
+        let Some((field, rest)) = args.split_first() else {
+            let def = self.world.areas[&area]
+                .source
+                .locations
+                .iter()
+                .find(|l| l.id == id)
+                .ok_or("This location isn't in its area's file.")?;
+            self.send_to(addr, Response::game_msg(&describe_location_def(def)));
+            return Ok(());
+        };
+
+        let field = field.to_lowercase();
+        let value = match field.as_str() {
+            "name" | "desc" | "description" => text(&field, rest)?,
+            _ => String::new(),
+        };
+        let exit = match field.as_str() {
+            "exit" => {
+                let (dir, to) = rest.split_first().ok_or("Which exit?")?;
+                let dir = Direction::parse(dir).ok_or(format!("{dir} is not a direction."))?;
+                let to = to
+                    .first()
+                    .ok_or("Where should it lead, or none?")?
+                    .to_string();
+                Some((dir, to))
+            }
+            _ => None,
+        };
+        let spawn = match field.as_str() {
+            "item" | "npc" => {
+                let (action, template) = rest
+                    .first()
+                    .zip(rest.get(1))
+                    .ok_or(format!("Usage: redit {field} add|remove <template>"))?;
+                let known = if field == "item" {
+                    self.world.item_templates.contains_key(*template)
+                } else {
+                    self.world.npc_templates.contains_key(*template)
+                };
+                if !known {
+                    return Err(format!("There's no {field} template {template}."));
+                }
+                Some((action.to_lowercase(), template.to_string()))
+            }
+            _ => None,
+        };
+
+        self.edit_area(addr, &area, |def| {
+            let location = def
+                .locations
+                .iter_mut()
+                .find(|l| l.id == id)
+                .ok_or("This location isn't in its area's file.")?;
+            match field.as_str() {
+                "name" => location.name = value,
+                "desc" | "description" => location.description = value,
+                "outdoors" => location.outdoors = !location.outdoors,
+                "dark" => location.dark = !location.dark,
+                "landmark" => location.landmark = !location.landmark,
+                "exit" => {
+                    let (dir, to) = exit.unwrap();
+                    if to == "none" {
+                        location
+                            .exits
+                            .remove(&dir)
+                            .ok_or(format!("There's no exit {}.", dir.name()))?;
+                    } else {
+                        location.exits.insert(dir, exit_to(&to));
+                    }
+                }
+                "item" | "npc" => {
+                    let (action, template) = spawn.unwrap();
+                    let list = if field == "item" {
+                        &mut location.items
+                    } else {
+                        &mut location.npcs
+                    };
+                    match action.as_str() {
+                        "add" => list.push(template),
+                        "remove" => {
+                            let i = list
+                                .iter()
+                                .position(|t| *t == template)
+                                .ok_or(format!("This location has no {template}."))?;
+                            list.remove(i);
+                        }
+                        _ => return Err(format!("Usage: redit {field} add|remove <template>")),
+                    }
+                }
+                _ => return Err(format!("Can't edit a location's {field}.")),
+            }
+            Ok(format!("Location {id} updated."))
+        })
+    }
+
+    /// `oedit <id>`, `oedit create <id> <name>`, `oedit <id> load` or
+    /// `oedit <id> <field> <value>`
+    pub fn oedit(&mut self, addr: &SocketAddr, args: &[&str]) -> Result<(), String> {
+        self.require_builder(addr)?;
+        match args {
+            [] => Err("Edit which item?".to_string()),
+            ["create", id, name @ ..] => {
+                let name = text("name", name)?;
+                if self.world.item_templates.contains_key(*id) {
+                    return Err(format!("Item {id} already exists."));
+                }
+                let area = self.world.locations[&self.session(addr)?.location]
+                    .area
+                    .clone();
+                self.edit_area(addr, &area, |def| {
+                    def.items.push(ItemTemplate {
+                        id: id.to_string(),
+                        keywords: keywords(&name),
+                        name,
+                        ..Default::default()
+                    });
+                    Ok(format!("Item {id} created in {area}."))
+                })
+            }
+            [id, "load"] => {
+                let item = self.world.spawn_item(id)?;
+                let name = self.world.item_name(item).to_string();
+                self.peers.get_mut(addr).unwrap().inventory.push(item);
+                self.send_to(addr, Response::game_msg(&format!("You conjure {name}.")));
+                self.send_inventory(addr)
+            }
+            [id] => {
+                let template = self
+                    .world
+                    .item_templates
+                    .get(*id)
+                    .ok_or(format!("There's no item {id}."))?;
+                self.send_to(addr, Response::game_msg(&describe_item_template(template)));
+                Ok(())
+            }
+            [id, field, value @ ..] => {
+                let area = self
+                    .world
+                    .item_area(id)
+                    .ok_or(format!("There's no item {id}."))?;
+                let field = field.to_lowercase();
+                self.edit_area(addr, &area, |def| {
+                    let template = def.items.iter_mut().find(|t| t.id == *id).unwrap();
+                    match field.as_str() {
+                        "name" => template.name = text(&field, value)?,
+                        "desc" | "description" => template.description = text(&field, value)?,
+                        "keywords" => template.keywords = keywords(&text(&field, value)?),
+                        "weight" => template.weight = number(&field, &text(&field, value)?)?,
+                        "value" => template.value = number(&field, &text(&field, value)?)?,
+                        "attack" => template.attack = number(&field, &text(&field, value)?)?,
+                        "defense" => template.defense = number(&field, &text(&field, value)?)?,
+                        "light" => template.light = !template.light,
+                        "slot" => {
+                            let slot = text(&field, value)?;
+                            template.slot = match slot.as_str() {
+                                "none" => None,
+                                slot => Some(
+                                    ron::from_str::<WearSlot>(&capitalize(slot))
+                                        .map_err(|_| format!("{slot} is not a wear slot."))?,
+                                ),
+                            };
+                        }
+                        _ => return Err(format!("Can't edit an item's {field}.")),
+                    }
+                    Ok(format!("Item {id} updated."))
+                })
+            }
+        }
+    }
+
+    /// `medit <id>`, `medit create <id> <name>`, `medit <id> load` or
+    /// `medit <id> <field> <value>`
+    pub fn medit(&mut self, addr: &SocketAddr, args: &[&str]) -> Result<(), String> {
+        self.require_builder(addr)?;
+        match args {
+            [] => Err("Edit which NPC?".to_string()),
+            ["create", id, name @ ..] => {
+                let name = text("name", name)?;
+                if self.world.npc_templates.contains_key(*id) {
+                    return Err(format!("NPC {id} already exists."));
+                }
+                let area = self.world.locations[&self.session(addr)?.location]
+                    .area
+                    .clone();
+                self.edit_area(addr, &area, |def| {
+                    def.npcs.push(NpcTemplate {
+                        id: id.to_string(),
+                        keywords: keywords(&name),
+                        long: format!("{} is here.", capitalize(&name)),
+                        name,
+                        stats: NpcStats {
+                            level: 1,
+                            max_hp: 10,
+                            ..Default::default()
+                        },
+                        ..Default::default()
+                    });
+                    Ok(format!("NPC {id} created in {area}."))
+                })
+            }
+            [id, "load"] => {
+                let location = self.session(addr)?.location.clone();
+                let npc = self.world.spawn_npc(id, &location)?;
+                let name = self.world.npc_template(npc).name.clone();
+                self.broadcast_location(&location, &format!("{} appears.", capitalize(&name)), &[]);
+                Ok(())
+            }
+            [id] => {
+                let template = self
+                    .world
+                    .npc_templates
+                    .get(*id)
+                    .ok_or(format!("There's no NPC {id}."))?;
+                self.send_to(addr, Response::game_msg(&describe_npc_template(template)));
+                Ok(())
+            }
+            [id, field, value @ ..] => {
+                let area = self
+                    .world
+                    .npc_area(id)
+                    .ok_or(format!("There's no NPC {id}."))?;
+                let field = field.to_lowercase();
+                self.edit_area(addr, &area, |def| {
+                    let template = def.npcs.iter_mut().find(|t| t.id == *id).unwrap();
+                    match field.as_str() {
+                        "name" => template.name = text(&field, value)?,
+                        "long" => template.long = text(&field, value)?,
+                        "desc" | "description" => template.description = text(&field, value)?,
+                        "keywords" => template.keywords = keywords(&text(&field, value)?),
+                        "pronouns" => {
+                            template.pronouns = Pronouns::parse(&text(&field, value)?)
+                                .ok_or("Pronouns can be 'they', 'he', 'she' or 'it'.")?
+                        }
+                        "faction" => {
+                            template.faction = match text(&field, value)?.as_str() {
+                                "none" => None,
+                                faction => Some(faction.to_string()),
+                            }
+                        }
+                        "level" => {
+                            let level = number(&field, &text(&field, value)?)?;
+                            if level < 1 {
+                                return Err("An NPC's level must be at least 1.".to_string());
+                            }
+                            template.stats.level = level;
+                        }
+                        "hp" => {
+                            let hp = number(&field, &text(&field, value)?)?;
+                            if hp <= 0 {
+                                return Err("An NPC's hp must be positive.".to_string());
+                            }
+                            template.stats.max_hp = hp;
+                        }
+                        "attack" => template.stats.attack = number(&field, &text(&field, value)?)?,
+                        "defense" => {
+                            template.stats.defense = number(&field, &text(&field, value)?)?
+                        }
+                        _ => return Err(format!("Can't edit an NPC's {field}.")),
+                    }
+                    Ok(format!("NPC {id} updated."))
+                })
+            }
+        }
+    }
+
+    /// `undo`: takes back the builder's last edit.
+    pub fn undo(&mut self, addr: &SocketAddr) -> Result<(), String> {
+        self.require_builder(addr)?;
+        let snapshot = self
+            .peers
+            .get_mut(addr)
+            .unwrap()
+            .undo
+            .pop()
+            .ok_or("You have no edits to undo.")?;
+        let current = self.world.areas[&snapshot.area].source.clone();
+        let name = self.world.areas[&snapshot.area].name.clone();
+        if !same_area(&current, &snapshot.after) {
+            self.peers.get_mut(addr).unwrap().undo.clear();
+            return Err(format!(
+                "{name} has been changed since your last edit, so it can't be undone."
+            ));
+        }
+        self.replace_area(snapshot.before)?;
+        self.send_to(
+            addr,
+            Response::game_msg(&format!("Undid your last edit to {name}.")),
+        );
+        Ok(())
+    }
+}
+
+/// Whether two versions of an area file would be saved the same.
+fn same_area(a: &AreaDef, b: &AreaDef) -> bool {
+    matches!((ron::to_string(a), ron::to_string(b)), (Ok(a), Ok(b)) if a == b)
+}
+
+fn exit_to(to: &str) -> Exit {
+    Exit {
+        to: to.to_string(),
+        door: None,
+        hidden: None,
+    }
+}
+
+fn describe_location_def(def: &LocationDef) -> String {
+    let exits = def
+        .exits
+        .iter()
+        .map(|(dir, exit)| format!("{} -> {}", dir.name(), exit.to))
+        .collect::<Vec<_>>();
+    let mut flags = vec![];
+    if def.outdoors {
+        flags.push("outdoors");
+    }
+    if def.dark {
+        flags.push("dark");
+    }
+    if def.landmark {
+        flags.push("landmark");
+    }
+    format!(
+        "Location {}\nName: {}\nDescription: {}\nFlags: {}\nExits: {}\nItems: {}\nNPCs: {}",
+        def.id,
+        def.name,
+        def.description,
+        list(&flags),
+        list(&exits),
+        list(&def.items),
+        list(&def.npcs),
+    )
+}
+
+fn describe_item_template(t: &ItemTemplate) -> String {
+    let slot = t.slot.map_or("none".to_string(), |slot| slot.to_string());
+    format!(
+        "Item {}\nName: {}\nKeywords: {}\nDescription: {}\nWeight: {}  Value: {}\nSlot: {slot}  Attack: {}  Defense: {}  Light: {}",
+        t.id,
+        t.name,
+        t.keywords.join(" "),
+        t.description,
+        t.weight,
+        t.value,
+        t.attack,
+        t.defense,
+        if t.light { "yes" } else { "no" },
+    )
+}
+
+fn describe_npc_template(t: &NpcTemplate) -> String {
+    format!(
+        "NPC {}\nName: {}\nKeywords: {}\nLong: {}\nDescription: {}\nLevel: {}  HP: {}  Attack: {}  Defense: {}",
+        t.id,
+        t.name,
+        t.keywords.join(" "),
+        t.long,
+        t.description,
+        t.stats.level,
+        t.stats.max_hp,
+        t.stats.attack,
+        t.stats.defense,
+    )
+}
+
+fn list<T: AsRef<str>>(items: &[T]) -> String {
+    if items.is_empty() {
+        return "none".to_string();
+    }
+    items
+        .iter()
+        .map(|i| i.as_ref())
+        .collect::<Vec<_>>()
+        .join(", ")
+}
@@ -0,0 +1,450 @@
+use std::collections::{BTreeSet, HashMap};
+use std::net::SocketAddr;
+
+use model::{ChatMessage, GameUpdate, PartyMemberView, PartyView, Response};
+use serde::{Deserialize, Serialize};
+
+use crate::world::Direction;
+use crate::Shared;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct PartyId(pub u64);
+
+/// Who gets items dropped by enemies the party kills.
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum LootRule {
+    /// Drops are left on the floor for anyone to take.
+    #[default]
+    FreeForAll,
+    /// Members present take turns receiving drops.
+    RoundRobin,
+    /// Drops go to the leader if they're present.
+    Leader,
+}
+
+impl LootRule {
+    fn parse(s: &str) -> Option<LootRule> {
+        match s.to_lowercase().as_str() {
+            "free" | "freeforall" => Some(LootRule::FreeForAll),
+            "roundrobin" | "round" => Some(LootRule::RoundRobin),
+            "leader" => Some(LootRule::Leader),
+            _ => None,
+        }
+    }
+
+    fn name(self) -> &'static str {
+        match self {
+            LootRule::FreeForAll => "free for all",
+            LootRule::RoundRobin => "round robin",
+            LootRule::Leader => "leader takes all",
+        }
+    }
+}
+
+pub struct Party {
+    pub id: PartyId,
+    pub leader: SocketAddr,
+    /// Everyone in the party, leader first.
+    pub members: Vec<SocketAddr>,
+    /// Members who move when the leader does.
+    pub followers: BTreeSet<SocketAddr>,
+    /// Players invited who haven't answered yet.
+    pub invites: BTreeSet<SocketAddr>,
+    pub loot: LootRule,
+    /// Index into `members` of whoever gets the next round robin drop.
+    pub next_looter: usize,
+}
+
+impl Shared {
+    pub(crate) fn party_of(&self, addr: &SocketAddr) -> Option<PartyId> {
+        self.parties
+            .values()
+            .find(|party| party.members.contains(addr))
+            .map(|party| party.id)
+    }
+
+    fn own_party(&self, addr: &SocketAddr) -> Result<PartyId, String> {
+        self.party_of(addr)
+            .ok_or("You aren't in a group.".to_string())
+    }
+
+    fn led_party(&self, addr: &SocketAddr) -> Result<PartyId, String> {
+        let id = self.own_party(addr)?;
+        if self.parties[&id].leader != *addr {
+            return Err("Only the group leader can do that.".to_string());
+        }
+        Ok(id)
+    }
+
+    /// Party members in the same location as `addr`, including `addr`.
+    pub(crate) fn party_members_here(&self, addr: &SocketAddr) -> Vec<SocketAddr> {
+        let Some(id) = self.party_of(addr) else {
+            return vec![*addr];
+        };
+        let location = &self.peers[addr].location;
+        self.parties[&id]
+            .members
+            .iter()
+            .filter(|member| {
+                self.peers
+                    .get(member)
+                    .is_some_and(|s| &s.location == location)
+            })
+            .copied()
+            .collect()
+    }
+
+    /// Who receives an item dropped by something `killer` killed, or `None` to leave it
+    /// on the floor.
+    pub(crate) fn loot_recipient(&mut self, killer: &SocketAddr) -> Option<SocketAddr> {
+        let id = self.party_of(killer)?;
+        let here = self.party_members_here(killer);
+        let party = self.parties.get_mut(&id).unwrap();
+        match party.loot {
+            LootRule::FreeForAll => None,
+            LootRule::Leader => here.contains(&party.leader).then_some(party.leader),
+            LootRule::RoundRobin => {
+                for _ in 0..party.members.len() {
+                    let member = party.members[party.next_looter % party.members.len()];
+                    party.next_looter = (party.next_looter + 1) % party.members.len();
+                    if here.contains(&member) {
+                        return Some(member);
+                    }
+                }
+                None
+            }
+        }
+    }
+
+    fn party_message(&self, id: PartyId, msg: &str) {
+        for member in &self.parties[&id].members {
+            self.send_to(member, Response::game_msg(msg));
+        }
+    }
+
+    /// Sends every member the party's current state.
+    pub(crate) fn send_party(&self, id: PartyId) {
+        let party = &self.parties[&id];
+        let members = party
+            .members
+            .iter()
+            .filter_map(|member| {
+                let session = self.peers.get(member)?;
+                let character = &session.character;
+                Some(PartyMemberView {
+                    name: session.name.clone(),
+                    level: character.level,
+                    hp: character.hp,
+                    max_hp: character.max_hp,
+                    mana: character.mana,
+                    max_mana: character.max_mana,
+                    following: party.followers.contains(member),
+                })
+            })
+            .collect();
+        let view = PartyView {
+            leader: self.peers[&party.leader].name.clone(),
+            members,
+        };
+
+        for member in &party.members {
+            self.send_to(
+                member,
+                Response::Game(GameUpdate::Party(Some(view.clone()))),
+            );
+        }
+    }
+
+    /// Keeps party members' vitals panels up to date.
+    pub(crate) fn send_party_vitals(&self, addr: &SocketAddr) {
+        if let Some(id) = self.party_of(addr) {
+            self.send_party(id);
+        }
+    }
+
+    /// `group [list]`, `group invite|kick <player>`, `group accept [leader]`,
+    /// `group leave|disband|follow`, `group loot <free|roundrobin|leader>` or
+    /// `group say <message>`
+    pub fn group(&mut self, addr: &SocketAddr, args: &[&str]) -> Result<(), String> {
+        let rest = args.get(1..).unwrap_or_default();
+        match args.first().copied() {
+            None | Some("list") => self.group_list(addr),
+            Some("invite") => self.group_invite(addr, rest.first().ok_or("Invite whom?")?),
+            Some("accept") | Some("join") => self.group_accept(addr, rest.first().copied()),
+            Some("leave") => self.leave_party(addr),
+            Some("kick") => self.group_kick(addr, rest.first().ok_or("Kick whom?")?),
+            Some("disband") => self.group_disband(addr),
+            Some("follow") => self.group_follow(addr),
+            Some("loot") => self.group_loot(addr, rest.first().ok_or("Which loot rule?")?),
+            Some("say") | Some("tell") => self.party_tell(addr, &rest.join(" ")),
+            Some(other) => Err(format!("Unknown group command: {other}")),
+        }
+    }
+
+    fn group_list(&self, addr: &SocketAddr) -> Result<(), String> {
+        let party = &self.parties[&self.own_party(addr)?];
+        let mut msg = format!("Your group ({}):", party.loot.name());
+        for member in &party.members {
+            let session = &self.peers[member];
+            let c = &session.character;
+            let role = if *member == party.leader {
+                " (leader)"
+            } else {
+                ""
+            };
+            msg.push_str(&format!(
+                "\n  {}{role}  level {}  hp {}/{}",
+                session.name, c.level, c.hp, c.max_hp
+            ));
+        }
+        self.send_to(addr, Response::game_msg(&msg));
+        Ok(())
+    }
+
+    fn group_invite(&mut self, addr: &SocketAddr, target: &str) -> Result<(), String> {
+        let session = self.session(addr)?;
+        let name = session.name.clone();
+        let other = self
+            .find_user_in_location(&session.location, target)
+            .ok_or(format!("You don't see {target} here."))?;
+        if other == *addr {
+            return Err("You can't invite yourself.".to_string());
+        }
+        let other_name = self.peers[&other].name.clone();
+        if self.party_of(&other).is_some() {
+            return Err(format!("{other_name} is already in a group."));
+        }
+
+        let id = match self.party_of(addr) {
+            Some(_) => self.led_party(addr)?,
+            None => {
+                let id = PartyId(self.next_party_id);
+                self.next_party_id += 1;
+                self.parties.insert(
+                    id,
+                    Party {
+                        id,
+                        leader: *addr,
+                        members: vec![*addr],
+                        followers: BTreeSet::new(),
+                        invites: BTreeSet::new(),
+                        loot: LootRule::default(),
+                        next_looter: 0,
+                    },
+                );
+                id
+            }
+        };
+
+        self.parties.get_mut(&id).unwrap().invites.insert(other);
+        self.send_to(
+            &other,
+            Response::game_msg(&format!(
+                "{name} invites you to join their group. Type 'group accept' to join."
+            )),
+        );
+        self.send_to(
+            addr,
+            Response::game_msg(&format!("You invite {other_name} to your group.")),
+        );
+        Ok(())
+    }
+
+    fn group_accept(&mut self, addr: &SocketAddr, leader: Option<&str>) -> Result<(), String> {
+        if self.party_of(addr).is_some() {
+            return Err("You are already in a group.".to_string());
+        }
+
+        let mut invites = self
+            .parties
+            .values()
+            .filter(|party| party.invites.contains(addr))
+            .filter(|party| {
+                leader.is_none_or(|leader| {
+                    self.peers[&party.leader]
+                        .name
+                        .to_lowercase()
+                        .starts_with(&leader.to_lowercase())
+                })
+            })
+            .map(|party| party.id)
+            .collect::<Vec<_>>();
+        invites.sort();
+        let id = *invites.last().ok_or("Nobody has invited you to a group.")?;
+
+        for party in self.parties.values_mut() {
+            party.invites.remove(addr);
+        }
+        let party = self.parties.get_mut(&id).unwrap();
+        party.members.push(*addr);
+        party.followers.insert(*addr);
+
+        let name = self.session(addr)?.name.clone();
+        self.party_message(id, &format!("{name} joins the group."));
+        self.send_party(id);
+        Ok(())
+    }
+
+    /// Takes `addr` out of their party, handing over leadership if needed. Also used
+    /// when a member disconnects.
+    pub(crate) fn leave_party(&mut self, addr: &SocketAddr) -> Result<(), String> {
+        for party in self.parties.values_mut() {
+            party.invites.remove(addr);
+        }
+        let id = self.own_party(addr)?;
+        let name = self.session(addr)?.name.clone();
+
+        self.party_message(id, &format!("{name} leaves the group."));
+        self.send_to(addr, Response::Game(GameUpdate::Party(None)));
+
+        let party = self.parties.get_mut(&id).unwrap();
+        party.members.retain(|member| member != addr);
+        party.followers.remove(addr);
+        if party.members.len() < 2 {
+            return self.disband(id, "The group breaks up.");
+        }
+
+        if party.leader == *addr {
+            let leader = party.members[0];
+            party.leader = leader;
+            party.followers.remove(&leader);
+            let leader_name = self.peers[&leader].name.clone();
+            self.party_message(id, &format!("{leader_name} now leads the group."));
+        }
+        self.send_party(id);
+        Ok(())
+    }
+
+    fn disband(&mut self, id: PartyId, msg: &str) -> Result<(), String> {
+        self.party_message(id, msg);
+        let party = self.parties.remove(&id).ok_or("No such group")?;
+        for member in &party.members {
+            self.send_to(member, Response::Game(GameUpdate::Party(None)));
+        }
+        Ok(())
+    }
+
+    fn group_kick(&mut self, addr: &SocketAddr, target: &str) -> Result<(), String> {
+        let id = self.led_party(addr)?;
+        let target = target.to_lowercase();
+        let member = self.parties[&id]
+            .members
+            .iter()
+            .copied()
+            .find(|member| {
+                member != addr && self.peers[member].name.to_lowercase().starts_with(&target)
+            })
+            .ok_or(format!("{target} isn't in your group."))?;
+
+        let name = self.peers[&member].name.clone();
+        self.send_to(
+            &member,
+            Response::game_msg("You have been removed from the group."),
+        );
+        self.leave_party(&member)?;
+        if self.parties.contains_key(&id) {
+            self.party_message(id, &format!("{name} has been removed from the group."));
+        }
+        Ok(())
+    }
+
+    fn group_disband(&mut self, addr: &SocketAddr) -> Result<(), String> {
+        let id = self.led_party(addr)?;
+        let name = self.session(addr)?.name.clone();
+        self.disband(id, &format!("{name} disbands the group."))
+    }
+
+    fn group_follow(&mut self, addr: &SocketAddr) -> Result<(), String> {
+        let id = self.own_party(addr)?;
+        let party = self.parties.get_mut(&id).unwrap();
+        if party.leader == *addr {
+            return Err("You lead the group; the others follow you.".to_string());
+        }
+
+        let msg = if party.followers.remove(addr) {
+            "You stop following the leader."
+        } else {
+            party.followers.insert(*addr);
+            "You now follow the leader."
+        };
+        self.send_to(addr, Response::game_msg(msg));
+        self.send_party(id);
+        Ok(())
+    }
+
+    fn group_loot(&mut self, addr: &SocketAddr, rule: &str) -> Result<(), String> {
+        let id = self.led_party(addr)?;
+        let rule = LootRule::parse(rule)
+            .ok_or("Loot rules are 'free', 'roundrobin' and 'leader'.".to_string())?;
+        self.parties.get_mut(&id).unwrap().loot = rule;
+        self.party_message(id, &format!("Loot is now {}.", rule.name()));
+        Ok(())
+    }
+
+    /// `gtell <message>`: talks to the whole group wherever they are.
+    pub fn party_tell(&self, addr: &SocketAddr, msg: &str) -> Result<(), String> {
+        if msg.trim().is_empty() {
+            return Err("Tell your group what?".to_string());
+        }
+        let id = self.own_party(addr)?;
+        let from = self.session(addr)?.name.clone();
+        let res = Response::Chat(ChatMessage::Party {
+            from,
+            msg: msg.to_string(),
+        });
+        for member in &self.parties[&id].members {
+            self.send_to(member, res.clone());
+        }
+        Ok(())
+    }
+
+    /// Moves everyone following `leader` who was standing with them.
+    pub(crate) fn move_followers(&mut self, leader: &SocketAddr, from: &str, dir: Direction) {
+        let Some(id) = self.party_of(leader) else {
+            return;
+        };
+        let party = &self.parties[&id];
+        if party.leader != *leader {
+            return;
+        }
+
+        let followers = party
+            .followers
+            .iter()
+            .copied()
+            .filter(|member| self.peers.get(member).is_some_and(|s| s.location == from))
+            .collect::<Vec<_>>();
+        let leader_name = self.peers[leader].name.clone();
+        for follower in followers {
+            self.send_to(
+                &follower,
+                Response::game_msg(&format!("You follow {leader_name} {}.", dir.name())),
+            );
+            if let Err(e) = self.move_user(&follower, dir) {
+                self.send_to(&follower, Response::game_msg(&e));
+            }
+        }
+    }
+
+    /// Splits experience for a kill between the killer's party members who were present.
+    pub(crate) fn share_experience(&mut self, killer: &SocketAddr, victim_level: u32) {
+        let members = self.party_members_here(killer);
+        let rules = &self.world.combat;
+        let bonus = 1.0 + rules.group_experience_bonus * (members.len() - 1) as f64;
+
+        let mut rewards = HashMap::new();
+        for member in &members {
+            let level = self.peers[member].character.level;
+            let experience = rules.experience_for(level, victim_level) as f64 * bonus;
+            rewards.insert(*member, (experience / members.len() as f64).round() as u64);
+        }
+
+        for (member, experience) in rewards {
+            self.send_to(
+                &member,
+                Response::game_msg(&format!("You receive {experience} experience.")),
+            );
+            self.gain_experience(&member, experience);
+        }
+    }
+}
@@ -0,0 +1,117 @@
+use std::net::SocketAddr;
+
+use model::Response;
+use serde::{Deserialize, Serialize};
+
+use crate::combat::Combatant;
+use crate::world::World;
+use crate::Shared;
+
+/// The fighting rules of an area.
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum Zone {
+    /// Players may only fight each other if both have turned PvP on.
+    #[default]
+    Normal,
+    /// Nobody can start a fight here.
+    Safe,
+    /// Players may fight each other whether they've turned PvP on or not.
+    Pvp,
+    /// Like `Pvp`, but players who die here lose nothing.
+    Arena,
+}
+
+impl Zone {
+    pub fn name(self) -> &'static str {
+        match self {
+            Zone::Normal => "normal",
+            Zone::Safe => "safe",
+            Zone::Pvp => "PvP",
+            Zone::Arena => "arena",
+        }
+    }
+}
+
+impl World {
+    pub fn zone(&self, location: &str) -> Zone {
+        self.areas[&self.locations[location].area].source.zone
+    }
+}
+
+impl Shared {
+    /// Checks that `attacker` may start a fight with `target`.
+    pub(crate) fn check_attack(
+        &self,
+        attacker: Combatant,
+        target: Combatant,
+    ) -> Result<(), String> {
+        let zone = self.world.zone(&self.combatant_location(attacker));
+        if zone == Zone::Safe {
+            return Err("You can't fight here.".to_string());
+        }
+        let (Combatant::Player(attacker), Combatant::Player(target)) = (attacker, target) else {
+            return Ok(());
+        };
+        if matches!(zone, Zone::Pvp | Zone::Arena) {
+            return Ok(());
+        }
+        if !self.session(&attacker)?.character.pvp {
+            return Err("You haven't turned PvP on.".to_string());
+        }
+        let target = self.session(&target)?;
+        if !target.character.pvp {
+            return Err(format!(
+                "{} hasn't agreed to fight other players.",
+                target.name
+            ));
+        }
+        Ok(())
+    }
+
+    /// `pvp [on|off]`
+    pub fn pvp(&mut self, addr: &SocketAddr, args: &[&str]) -> Result<(), String> {
+        let session = self.session(addr)?;
+        let zone = self.world.zone(&session.location);
+        let Some(choice) = args.first() else {
+            let status = if session.character.pvp { "on" } else { "off" };
+            let msg = format!("Your PvP is {status}. This is a {} area.", zone.name());
+            self.send_to(addr, Response::game_msg(&msg));
+            return Ok(());
+        };
+
+        let pvp = match choice.to_lowercase().as_str() {
+            "on" => true,
+            "off" => false,
+            _ => return Err("Turn PvP 'on' or 'off'?".to_string()),
+        };
+        if pvp == session.character.pvp {
+            return Err(format!("Your PvP is already {choice}."));
+        }
+        if self.is_fighting(Combatant::Player(*addr)) {
+            return Err("Not while you're fighting!".to_string());
+        }
+        if session.pvp_cooldown > 0 {
+            return Err(format!(
+                "You can change your PvP setting again in {} ticks.",
+                session.pvp_cooldown
+            ));
+        }
+
+        let cooldown = self.world.combat.pvp_cooldown_ticks;
+        let session = self.peers.get_mut(addr).unwrap();
+        session.character.pvp = pvp;
+        session.pvp_cooldown = cooldown;
+        let msg = match pvp {
+            true => "You will now fight other players who have PvP on.",
+            false => "You will no longer fight other players outside PvP areas.",
+        };
+        self.send_to(addr, Response::game_msg(msg));
+        Ok(())
+    }
+
+    pub(crate) fn pvp_tick(&mut self) {
+        for session in self.peers.values_mut() {
+            session.pvp_cooldown = session.pvp_cooldown.saturating_sub(1);
+        }
+    }
+}
@@ -0,0 +1,670 @@
+use std::collections::{BTreeMap, BTreeSet};
+use std::net::SocketAddr;
+
+use model::{GameUpdate, ObjectiveView, QuestStatus, QuestView, Response};
+use serde::{Deserialize, Serialize};
+
+use crate::instance::base_location;
+use crate::item::ItemId;
+use crate::npc::{capitalize, NpcId};
+use crate::world::{LocationId, World};
+use crate::{Shared, UserSession};
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub enum Objective {
+    /// Kill `count` NPCs made from the `npc` template.
+    Kill {
+        npc: String,
+        count: u32,
+    },
+    /// Be carrying `count` items made from the `item` template. They are handed over
+    /// when the quest is turned in.
+    Fetch {
+        item: String,
+        count: u32,
+    },
+    Reach {
+        location: LocationId,
+    },
+    /// `talk` to an NPC made from the `npc` template.
+    Talk {
+        npc: String,
+    },
+}
+
+impl Objective {
+    pub fn required(&self) -> u32 {
+        match self {
+            Objective::Kill { count, .. } | Objective::Fetch { count, .. } => *count,
+            Objective::Reach { .. } | Objective::Talk { .. } => 1,
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, Default)]
+pub struct QuestRewards {
+    #[serde(default)]
+    pub experience: u64,
+    #[serde(default)]
+    pub gold: u64,
+    /// Item templates given to the player.
+    #[serde(default)]
+    pub items: Vec<String>,
+    /// Reputation changes, by faction.
+    #[serde(default)]
+    pub reputation: BTreeMap<String, i32>,
+}
+
+/// A quest as written in an area file.
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct QuestDef {
+    pub id: String,
+    pub name: String,
+    #[serde(default)]
+    pub description: String,
+    /// The NPC template that hands out the quest and takes it back when it's done.
+    pub giver: String,
+    /// Quests that must be turned in before this one is offered.
+    #[serde(default)]
+    pub prerequisites: Vec<String>,
+    #[serde(default)]
+    pub min_level: u32,
+    pub objectives: Vec<Objective>,
+    #[serde(default)]
+    pub rewards: QuestRewards,
+}
+
+/// A character's quest progress, kept in their save file.
+#[derive(Serialize, Deserialize, Clone, Debug, Default)]
+pub struct QuestLog {
+    /// Progress towards each objective of the quests being worked on.
+    #[serde(default)]
+    pub active: BTreeMap<String, Vec<u32>>,
+    #[serde(default)]
+    pub completed: BTreeSet<String>,
+}
+
+impl World {
+    /// Brings a saved quest log in line with the quests that exist now, dropping any
+    /// that are gone and fitting progress to each quest's objectives.
+    pub fn restore_quests(&self, mut log: QuestLog) -> QuestLog {
+        log.active.retain(|id, counts| match self.quests.get(id) {
+            Some(quest) => {
+                counts.resize(quest.objectives.len(), 0);
+                true
+            }
+            None => {
+                eprintln!("Dropping saved quest {id}");
+                false
+            }
+        });
+        log.completed.retain(|id| self.quests.contains_key(id));
+        log
+    }
+
+    /// Checks that quests only refer to things that exist.
+    pub(crate) fn validate_quests(&self) -> Result<(), String> {
+        for quest in self.quests.values() {
+            let check = |exists: bool, kind: &str, id: &str| {
+                if exists {
+                    Ok(())
+                } else {
+                    Err(format!("Quest {} refers to unknown {kind} {id}", quest.id))
+                }
+            };
+
+            check(
+                self.npc_templates.contains_key(&quest.giver),
+                "NPC",
+                &quest.giver,
+            )?;
+            for id in &quest.prerequisites {
+                check(self.quests.contains_key(id), "quest", id)?;
+            }
+            for item in &quest.rewards.items {
+                check(self.item_templates.contains_key(item), "item", item)?;
+            }
+            for objective in &quest.objectives {
+                match objective {
+                    Objective::Kill { npc, .. } | Objective::Talk { npc } => {
+                        check(self.npc_templates.contains_key(npc), "NPC", npc)?
+                    }
+                    Objective::Fetch { item, .. } => {
+                        check(self.item_templates.contains_key(item), "item", item)?
+                    }
+                    Objective::Reach { location } => {
+                        check(self.locations.contains_key(location), "location", location)?
+                    }
+                }
+            }
+        }
+        Ok(())
+    }
+
+    pub fn describe_objective(&self, objective: &Objective) -> String {
+        match objective {
+            Objective::Kill { npc, .. } => format!("Kill {}", self.npc_templates[npc].name),
+            Objective::Fetch { item, .. } => {
+                format!("Bring {}", self.item_templates[item].name)
+            }
+            Objective::Reach { location } => format!("Visit {}", self.locations[location].name),
+            Objective::Talk { npc } => {
+                format!("Speak to {}", self.npc_templates[npc].name)
+            }
+        }
+    }
+
+    /// Every item carried by a player, including the contents of containers.
+    fn carried_items(&self, session: &UserSession) -> Vec<ItemId> {
+        let mut items = session.inventory.clone();
+        let mut i = 0;
+        while i < items.len() {
+            items.extend(self.items[&items[i]].contents.iter().copied());
+            i += 1;
+        }
+        items
+    }
+
+    fn objective_progress(&self, session: &UserSession, objective: &Objective, count: u32) -> u32 {
+        let current = match objective {
+            Objective::Fetch { item, .. } => self
+                .carried_items(session)
+                .iter()
+                .filter(|id| &self.items[id].template == item)
+                .count() as u32,
+            _ => count,
+        };
+        current.min(objective.required())
+    }
+
+    fn quest_complete(&self, session: &UserSession, id: &str) -> bool {
+        let Some(counts) = session.quests.active.get(id) else {
+            return false;
+        };
+        self.quests[id]
+            .objectives
+            .iter()
+            .zip(counts)
+            .all(|(objective, count)| {
+                self.objective_progress(session, objective, *count) >= objective.required()
+            })
+    }
+
+    fn quest_view(&self, session: &UserSession, id: &str, status: QuestStatus) -> QuestView {
+        let quest = &self.quests[id];
+        let counts = session.quests.active.get(id);
+        let objectives = quest
+            .objectives
+            .iter()
+            .enumerate()
+            .map(|(i, objective)| {
+                let count = counts.map_or(0, |counts| counts[i]);
+                ObjectiveView {
+                    description: self.describe_objective(objective),
+                    current: match status {
+                        QuestStatus::TurnedIn => objective.required(),
+                        _ => self.objective_progress(session, objective, count),
+                    },
+                    required: objective.required(),
+                }
+            })
+            .collect();
+
+        QuestView {
+            id: quest.id.clone(),
+            name: quest.name.clone(),
+            status,
+            objectives,
+        }
+    }
+
+    /// Whether `session` could accept `id` right now, ignoring where they are.
+    pub(crate) fn can_accept(&self, session: &UserSession, id: &str) -> Result<(), String> {
+        let quest = &self.quests[id];
+        if session.quests.completed.contains(id) {
+            return Err(format!("You have already finished {}.", quest.name));
+        }
+        if session.quests.active.contains_key(id) {
+            return Err(format!("You are already on {}.", quest.name));
+        }
+        if session.character.level < quest.min_level {
+            return Err(format!(
+                "You must be level {} to take on {}.",
+                quest.min_level, quest.name
+            ));
+        }
+        if let Some(missing) = quest
+            .prerequisites
+            .iter()
+            .find(|prereq| !session.quests.completed.contains(*prereq))
+        {
+            return Err(format!(
+                "You must finish {} before taking on {}.",
+                self.quests[missing].name, quest.name
+            ));
+        }
+        Ok(())
+    }
+
+    /// Finds a quest by id or by a word in its name.
+    fn find_quest<'a>(
+        &self,
+        ids: impl Iterator<Item = &'a String>,
+        keyword: &str,
+    ) -> Option<String> {
+        let keyword = keyword.to_lowercase();
+        ids.into_iter()
+            .find(|id| {
+                **id == keyword
+                    || self.quests[*id]
+                        .name
+                        .to_lowercase()
+                        .split_whitespace()
+                        .any(|word| word.starts_with(&keyword))
+            })
+            .cloned()
+    }
+}
+
+impl Shared {
+    fn quest_status(&self, session: &UserSession, id: &str) -> QuestStatus {
+        if self.world.quest_complete(session, id) {
+            QuestStatus::Complete
+        } else {
+            QuestStatus::Active
+        }
+    }
+
+    fn send_quest(&self, addr: &SocketAddr, id: &str, status: QuestStatus) {
+        let Ok(session) = self.session(addr) else {
+            return;
+        };
+        let view = self.world.quest_view(session, id, status);
+        self.send_to(addr, Response::Game(GameUpdate::QuestUpdated(view)));
+    }
+
+    /// Sends every active quest, e.g. after logging in.
+    pub(crate) fn send_quest_log(&self, addr: &SocketAddr) {
+        let Ok(session) = self.session(addr) else {
+            return;
+        };
+        for id in session.quests.active.keys() {
+            self.send_quest(addr, id, self.quest_status(session, id));
+        }
+    }
+
+    /// Resends quests with fetch objectives, whose progress depends on what's carried.
+    pub(crate) fn send_fetch_quests(&self, addr: &SocketAddr) {
+        let Ok(session) = self.session(addr) else {
+            return;
+        };
+        for id in session.quests.active.keys() {
+            let fetches = self.world.quests[id]
+                .objectives
+                .iter()
+                .any(|objective| matches!(objective, Objective::Fetch { .. }));
+            if fetches {
+                self.send_quest(addr, id, self.quest_status(session, id));
+            }
+        }
+    }
+
+    /// Counts progress towards every active objective matching `pred`. Returns whether
+    /// any quest changed.
+    fn advance_objectives(&mut self, addr: &SocketAddr, pred: impl Fn(&Objective) -> bool) -> bool {
+        let Some(session) = self.peers.get_mut(addr) else {
+            return false;
+        };
+
+        let mut updated = vec![];
+        for (id, counts) in session.quests.active.iter_mut() {
+            let mut changed = false;
+            for (objective, count) in self.world.quests[id].objectives.iter().zip(counts) {
+                if pred(objective) && *count < objective.required() {
+                    *count += 1;
+                    changed = true;
+                }
+            }
+            if changed {
+                updated.push(id.clone());
+            }
+        }
+
+        let advanced = !updated.is_empty();
+        for id in updated {
+            let session = &self.peers[addr];
+            let status = self.quest_status(session, &id);
+            let quest = &self.world.quests[&id];
+            let msg = match status {
+                QuestStatus::Complete => format!(
+                    "You have done everything asked of you for {}. Return to {}.",
+                    quest.name, self.world.npc_templates[&quest.giver].name
+                ),
+                _ => format!("Quest updated: {}", quest.name),
+            };
+            self.send_to(addr, Response::game_msg(&msg));
+            self.send_quest(addr, &id, status);
+        }
+        advanced
+    }
+
+    pub(crate) fn quest_kill(&mut self, addr: &SocketAddr, npc_template: &str) {
+        let _ = self.advance_objectives(
+            addr,
+            |objective| matches!(objective, Objective::Kill { npc, .. } if npc == npc_template),
+        );
+    }
+
+    pub(crate) fn quest_reach(&mut self, addr: &SocketAddr) {
+        let Ok(session) = self.session(addr) else {
+            return;
+        };
+        let here = base_location(&session.location).to_string();
+        let _ = self.advance_objectives(
+            addr,
+            |objective| matches!(objective, Objective::Reach { location } if *location == here),
+        );
+    }
+
+    /// `talk <npc>`: counts towards talk objectives, hands in finished quests, then
+    /// either starts the NPC's dialogue tree or mentions any new work it has.
+    pub fn talk(&mut self, addr: &SocketAddr, args: &[&str]) -> Result<(), String> {
+        let target = args.first().ok_or("Talk to whom?")?;
+        let location = self.session(addr)?.location.clone();
+        let npc = self
+            .world
+            .find_npc(&location, target)
+            .ok_or(format!("You don't see {target} here."))?;
+        let template = self.world.npcs[&npc].template.clone();
+
+        let talked = self.advance_objectives(
+            addr,
+            |objective| matches!(objective, Objective::Talk { npc } if *npc == template),
+        );
+
+        let session = self.session(addr)?;
+        let finished = session
+            .quests
+            .active
+            .keys()
+            .filter(|id| self.world.quests[*id].giver == template)
+            .filter(|id| self.world.quest_complete(session, id))
+            .cloned()
+            .collect::<Vec<_>>();
+        for id in &finished {
+            // an earlier turn-in may have used up items this one needed
+            if let Err(e) = self.turn_in_quest(addr, npc, id) {
+                self.send_to(addr, Response::game_msg(&e));
+            }
+        }
+
+        if self.world.npc_template(npc).dialogue_tree.is_some() {
+            return self.start_dialogue(addr, npc);
+        }
+
+        let session = self.session(addr)?;
+        let mut offers = self
+            .world
+            .quests
+            .values()
+            .filter(|quest| quest.giver == template)
+            .filter(|quest| self.world.can_accept(session, &quest.id).is_ok())
+            .collect::<Vec<_>>();
+        offers.sort_by_key(|quest| &quest.id);
+
+        let name = capitalize(&self.world.npc_template(npc).name);
+        if offers.is_empty() {
+            if !talked && finished.is_empty() {
+                self.send_to(
+                    addr,
+                    Response::game_msg(&format!("{name} has nothing more to say to you.")),
+                );
+            }
+            return Ok(());
+        }
+
+        let mut msg = format!("{name} has work for you:");
+        for quest in offers {
+            msg.push_str(&format!("\n  {} - {}", quest.name, quest.description));
+        }
+        msg.push_str("\nUse 'quest accept <name>' to take one on.");
+        self.send_to(addr, Response::game_msg(&msg));
+        Ok(())
+    }
+
+    fn turn_in_quest(&mut self, addr: &SocketAddr, giver: NpcId, id: &str) -> Result<(), String> {
+        let quest = self.world.quests[id].clone();
+        if !self.world.quest_complete(self.session(addr)?, id) {
+            return Err(format!(
+                "You no longer have everything {} needs.",
+                quest.name
+            ));
+        }
+
+        // hand over fetched items, looking in containers too
+        for objective in &quest.objectives {
+            let Objective::Fetch { item, count } = objective else {
+                continue;
+            };
+            for _ in 0..*count {
+                let session = self.session(addr)?;
+                let Some(found) = self
+                    .world
+                    .carried_items(session)
+                    .into_iter()
+                    .find(|i| &self.world.items[i].template == item)
+                else {
+                    break;
+                };
+                self.peers
+                    .get_mut(addr)
+                    .unwrap()
+                    .inventory
+                    .retain(|i| *i != found);
+                for item in self.world.items.values_mut() {
+                    item.contents.retain(|i| *i != found);
+                }
+                self.world.destroy_item(found);
+            }
+        }
+
+        let session = self.session(addr)?;
+        let view = self.world.quest_view(session, id, QuestStatus::TurnedIn);
+        let player = session.name.clone();
+
+        let mut rewards = vec![];
+        for template in &quest.rewards.items {
+            let item = self.world.spawn_item(template)?;
+            rewards.push(self.world.item_name(item).to_string());
+            self.peers.get_mut(addr).unwrap().inventory.push(item);
+        }
+        if quest.rewards.gold > 0 {
+            rewards.push(format!("{} gold", quest.rewards.gold));
+        }
+
+        let session = self.peers.get_mut(addr).unwrap();
+        session.character.gold += quest.rewards.gold;
+        session.quests.active.remove(id);
+        session.quests.completed.insert(id.to_string());
+
+        self.npc_say(giver, &format!("Well done, {player}!"));
+        let mut msg = format!("Quest complete: {}", quest.name);
+        if !rewards.is_empty() {
+            msg.push_str(&format!("\nYou receive {}.", rewards.join(", ")));
+        }
+        self.send_to(addr, Response::game_msg(&msg));
+        self.send_to(addr, Response::Game(GameUpdate::QuestUpdated(view)));
+        self.send_inventory(addr)?;
+
+        if quest.rewards.experience > 0 {
+            self.send_to(
+                addr,
+                Response::game_msg(&format!(
+                    "You receive {} experience.",
+                    quest.rewards.experience
+                )),
+            );
+            self.gain_experience(addr, quest.rewards.experience);
+        }
+        for (faction, amount) in &quest.rewards.reputation {
+            self.change_reputation(addr, faction, *amount);
+        }
+        self.save_character(addr)
+    }
+
+    /// `quest [list]`, `quest info <quest>`, `quest accept <quest>` or `quest abandon <quest>`
+    pub fn quest(&mut self, addr: &SocketAddr, args: &[&str]) -> Result<(), String> {
+        match args.first().copied() {
+            None | Some("list") => self.quest_list(addr),
+            Some("info") => self.quest_info(addr, args.get(1).ok_or("Which quest?")?),
+            Some("accept") => self.quest_accept(addr, args.get(1).ok_or("Accept which quest?")?),
+            Some("abandon") => self.quest_abandon(addr, args.get(1).ok_or("Abandon which quest?")?),
+            Some(other) => Err(format!("Unknown quest command: {other}")),
+        }
+    }
+
+    fn quest_list(&self, addr: &SocketAddr) -> Result<(), String> {
+        let session = self.session(addr)?;
+        let mut msg = String::from("Quests:");
+        if session.quests.active.is_empty() {
+            msg.push_str("\n  You aren't on any quests.");
+        }
+        for id in session.quests.active.keys() {
+            let done = match self.quest_status(session, id) {
+                QuestStatus::Complete => " (complete)",
+                _ => "",
+            };
+            msg.push_str(&format!("\n  {}{done}", self.world.quests[id].name));
+        }
+        msg.push_str(&format!(
+            "\nYou have finished {} quest(s).",
+            session.quests.completed.len()
+        ));
+        self.send_to(addr, Response::game_msg(&msg));
+        Ok(())
+    }
+
+    fn quest_info(&self, addr: &SocketAddr, keyword: &str) -> Result<(), String> {
+        let session = self.session(addr)?;
+        let id = self
+            .world
+            .find_quest(
+                session
+                    .quests
+                    .active
+                    .keys()
+                    .chain(&session.quests.completed),
+                keyword,
+            )
+            .ok_or(format!("You don't know of a quest called {keyword}."))?;
+        let quest = &self.world.quests[&id];
+
+        let status = if session.quests.completed.contains(&id) {
+            QuestStatus::TurnedIn
+        } else {
+            self.quest_status(session, &id)
+        };
+        let view = self.world.quest_view(session, &id, status);
+
+        let mut msg = format!("{}\n{}", quest.name, quest.description);
+        msg.push_str(&format!(
+            "\nGiven by {}.",
+            self.world.npc_templates[&quest.giver].name
+        ));
+        for objective in &view.objectives {
+            msg.push_str(&format!(
+                "\n  [{}/{}] {}",
+                objective.current, objective.required, objective.description
+            ));
+        }
+        let mut rewards = quest
+            .rewards
+            .items
+            .iter()
+            .map(|t| self.world.item_templates[t].name.clone())
+            .collect::<Vec<_>>();
+        if quest.rewards.gold > 0 {
+            rewards.push(format!("{} gold", quest.rewards.gold));
+        }
+        if quest.rewards.experience > 0 {
+            rewards.push(format!("{} experience", quest.rewards.experience));
+        }
+        for (faction, amount) in &quest.rewards.reputation {
+            rewards.push(format!(
+                "{amount:+} reputation with {}",
+                self.world
+                    .faction(faction)
+                    .map_or(faction.as_str(), |f| &f.name)
+            ));
+        }
+        if !rewards.is_empty() {
+            msg.push_str(&format!("\nRewards: {}.", rewards.join(", ")));
+        }
+
+        self.send_to(addr, Response::game_msg(&msg));
+        Ok(())
+    }
+
+    fn quest_accept(&mut self, addr: &SocketAddr, keyword: &str) -> Result<(), String> {
+        let session = self.session(addr)?;
+        let location = session.location.clone();
+        let givers = self
+            .world
+            .npcs_in_location(&location)
+            .map(|npc| npc.template.clone())
+            .collect::<Vec<_>>();
+        let offered = self
+            .world
+            .quests
+            .values()
+            .filter(|quest| givers.contains(&quest.giver))
+            .map(|quest| &quest.id)
+            .collect::<Vec<_>>();
+
+        let id = self
+            .world
+            .find_quest(offered.into_iter(), keyword)
+            .ok_or(format!("Nobody here is offering {keyword}."))?;
+        self.world.can_accept(session, &id)?;
+        self.start_quest(addr, &id)
+    }
+
+    /// Adds a quest to the player's log, checking any objectives they've already met.
+    pub(crate) fn start_quest(&mut self, addr: &SocketAddr, id: &str) -> Result<(), String> {
+        let quest = &self.world.quests[id];
+        let counts = vec![0; quest.objectives.len()];
+        let name = quest.name.clone();
+        self.peers
+            .get_mut(addr)
+            .ok_or("No session for address")?
+            .quests
+            .active
+            .insert(id.to_string(), counts);
+
+        self.send_to(
+            addr,
+            Response::game_msg(&format!("You have started the quest: {name}")),
+        );
+        let session = self.session(addr)?;
+        self.send_quest(addr, id, self.quest_status(session, id));
+        self.quest_reach(addr);
+        Ok(())
+    }
+
+    fn quest_abandon(&mut self, addr: &SocketAddr, keyword: &str) -> Result<(), String> {
+        let session = self.session(addr)?;
+        let id = self
+            .world
+            .find_quest(session.quests.active.keys(), keyword)
+            .ok_or(format!("You aren't on a quest called {keyword}."))?;
+        let view = self.world.quest_view(session, &id, QuestStatus::Abandoned);
+
+        self.peers.get_mut(addr).unwrap().quests.active.remove(&id);
+        self.send_to(
+            addr,
+            Response::game_msg(&format!("You abandon {}.", view.name)),
+        );
+        self.send_to(addr, Response::Game(GameUpdate::QuestUpdated(view)));
+        Ok(())
+    }
+}
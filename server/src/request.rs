@@ -1,13 +1,11 @@
+use crate::world::Direction;
 use crate::Shared;
 use futures::SinkExt;
 use std::net::SocketAddr;
 use std::sync::Arc;
 use tokio::{net::TcpStream, sync::Mutex};
 use tokio_util::codec::LengthDelimitedCodec;
-use tokio_util::{
-    bytes::{Bytes, BytesMut},
-    codec::Framed,
-};
+use tokio_util::{bytes::Bytes, codec::Framed};
 
 use std::error::Error;
 
@@ -26,61 +24,132 @@ pub async fn handle_request(
 
     match req {
         model::UserAction::Chat(msg) => match msg {
-            model::ChatMessage::Private { from, msg } => todo!(),
-            model::ChatMessage::Public {
-                room_name,
-                from,
-                msg,
-            } => {
-                state.broadcast(addr, &msg, Some(&from), &room_name).await;
+            model::ChatMessage::Private { .. } => todo!(),
+            model::ChatMessage::Public { room_name, msg, .. } => {
+                state.broadcast(addr, msg, Some(username), room_name).await;
             }
+            model::ChatMessage::Party { msg, .. } => state.party_tell(addr, msg)?,
             model::ChatMessage::Username(_) => todo!(),
+            model::ChatMessage::Speech { .. } => {
+                return Err("Speak with a command instead.".into());
+            }
         },
+        model::UserAction::CreateCharacter { .. } => {
+            return Err("You already have a character.".into());
+        }
+        model::UserAction::Command(line) => {
+            handle_command(&mut state, stream, addr, line).await?;
+        }
     }
-    /*
-    if let Some(command_msg) = msg.strip_prefix("/") {
-        // command
-        let tokens = command_msg.split_whitespace().collect::<Vec<_>>();
-        let (cmd, args) = tokens.split_first().ok_or("Expected a command.")?;
 
-        match *cmd {
-            "who" => {
-                get_users(&state, stream, current_room).await;
-            }
-            "rooms" | "rs" => {
-                get_rooms(&state, stream, current_room).await;
-            }
-            "pm" => {
-                let to = args.get(0).ok_or("Error with pm: Expected a recepient.")?;
-                let full_message = &args[1..].join(" ");
+    Ok(())
+}
+
+async fn handle_command(
+    state: &mut Shared,
+    stream: &mut Stream,
+    addr: &SocketAddr,
+    line: &str,
+) -> Result<(), Box<dyn Error + Send + Sync>> {
+    let current_room = "main";
+    let tokens = line.split_whitespace().collect::<Vec<_>>();
+    let (cmd, args) = tokens.split_first().ok_or("Expected a command.")?;
 
-                match state.private_message(&full_message, addr, to).await {
-                    Ok(_) => {}
-                    Err(_) => {
-                        let res = Response::server_msg(
-                            &format!("Couldn't send PM to {to}"),
-                            current_room,
-                        );
-                        send_response(stream, res).await;
-                    }
-                }
+    // doing anything else stops a player walking somewhere
+    if !matches!(*cmd, "travel" | "walk") {
+        state.stop_walking(addr, "You stop travelling.");
+    }
+
+    match *cmd {
+        "who" => {
+            get_users(state, stream, current_room).await;
+        }
+        "rooms" | "rs" => {
+            get_rooms(state, stream, current_room).await;
+        }
+        "pm" => {
+            let to = args.first().ok_or("Error with pm: Expected a recepient.")?;
+            let full_message = &args[1..].join(" ");
+
+            if state.private_message(full_message, addr, to).await.is_err() {
+                let res = Response::server_msg(&format!("Couldn't send PM to {to}"), current_room);
+                send_response(stream, res).await;
             }
-            _ => {}
         }
-    } else {
-        // public message
-        state
-            .broadcast(addr, &msg, Some(username), current_room)
-            .await;
+        "look" | "l" => state.look(addr, args)?,
+        "say" | "'" => state.say(addr, args)?,
+        "emote" | "pose" | ":" => state.emote(addr, args)?,
+        "whisper" => state.whisper(addr, args)?,
+        "shout" => state.shout(addr, args)?,
+        "yell" => state.yell(addr, args)?,
+        "socials" => state.list_socials(addr)?,
+        "pronouns" => state.pronouns(addr, args)?,
+        "pvp" => state.pvp(addr, args)?,
+        "travel" => state.travel(addr, args)?,
+        "walk" => state.walk(addr, args)?,
+        "go" => {
+            let dir = args.first().ok_or("Go where?")?;
+            let dir = Direction::parse(dir).ok_or(format!("{dir} is not a direction."))?;
+            state.move_user(addr, dir)?;
+        }
+        "get" | "take" => state.get_item(addr, args)?,
+        "drop" => state.drop_item(addr, args)?,
+        "give" => state.give_item(addr, args)?,
+        "put" => state.put_item(addr, args)?,
+        "inventory" | "inv" | "i" => state.show_inventory(addr)?,
+        "wear" | "wield" => state.wear_item(addr, args)?,
+        "remove" => state.remove_item(addr, args)?,
+        "use" => state.use_item(addr, args)?,
+        "open" => state.open_door(addr, args)?,
+        "close" => state.close_door(addr, args)?,
+        "lock" => state.lock_door(addr, args)?,
+        "unlock" => state.unlock_door(addr, args)?,
+        "pick" => state.pick_lock(addr, args)?,
+        "search" => state.search(addr)?,
+        "talk" => state.talk(addr, args)?,
+        "list" => state.list_stock(addr)?,
+        "buy" => state.buy_item(addr, args)?,
+        "sell" => state.sell_item(addr, args)?,
+        "appraise" | "value" => state.appraise_item(addr, args)?,
+        "trade" => state.trade(addr, args)?,
+        "group" | "party" => state.group(addr, args)?,
+        "gtell" | "gt" => state.party_tell(addr, &args.join(" "))?,
+        "quest" | "quests" => state.quest(addr, args)?,
+        "kill" | "attack" | "k" => state.attack(addr, args)?,
+        "flee" => state.flee(addr)?,
+        "cast" | "c" => state.cast(addr, args)?,
+        "skills" | "spells" => state.list_skills(addr)?,
+        "score" | "sc" => state.score(addr)?,
+        "craft" => state.craft(addr, args)?,
+        "recipes" => state.list_recipes(addr)?,
+        "reputation" | "rep" => state.show_reputation(addr)?,
+        "time" | "weather" => state.time(addr)?,
+        "map" => state.show_map(addr)?,
+        "dig" => state.dig(addr, args)?,
+        "redit" => state.redit(addr, args)?,
+        "oedit" => state.oedit(addr, args)?,
+        "medit" => state.medit(addr, args)?,
+        "undo" => state.undo(addr)?,
+        "save" => {
+            state.save_character(addr)?;
+            state.send_to(addr, Response::game_msg("Saved."));
+        }
+        _ => match (Direction::parse(cmd), cmd.parse::<usize>()) {
+            (Some(dir), _) => state.move_user(addr, dir)?,
+            (None, Ok(choice)) => state.choose_dialogue(addr, choice)?,
+            (None, Err(_)) if state.world.socials.contains_key(*cmd) => {
+                state.social(addr, cmd, args)?
+            }
+            (None, Err(_)) => return Err(format!("Unknown command: {cmd}").into()),
+        },
     }
-        */
 
     Ok(())
 }
 
 async fn get_rooms(state: &Shared, stream: &mut Stream, current_room: &str) {
     let mut room_list = String::from("Joinable rooms:\n");
-    for (room_name, _) in &state.rooms {
+    for room_name in state.rooms.keys() {
         room_list.push_str(room_name);
         room_list.push('\n');
     }
@@ -96,7 +165,11 @@ async fn get_users(state: &Shared, stream: &mut Stream, current_room: &str) {
     send_response(stream, Response::server_msg(&user_list, current_room)).await;
 }
 
+/// Sends a response to the client. A failed send means the client has gone, which the
+/// read side of the connection notices, so the error is only logged.
 pub async fn send_response(stream: &mut Stream, res: Response) {
     let res_bytes: Vec<u8> = res.into();
-    stream.send(Bytes::from(res_bytes)).await.unwrap();
+    if let Err(e) = stream.send(Bytes::from(res_bytes)).await {
+        eprintln!("Failed to send response: {e}");
+    }
 }
@@ -0,0 +1,204 @@
+use std::collections::{BTreeMap, HashMap};
+
+use serde::{Deserialize, Serialize};
+
+use crate::npc::NpcId;
+use crate::world::{LocationDef, LocationId, World};
+use crate::Shared;
+
+fn one() -> u32 {
+    1
+}
+
+/// Something an area puts back whenever it resets, as written in an area file.
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub enum SpawnRule {
+    /// Keeps up to `max` NPCs made by this rule alive, each spawning in `location` and
+    /// wearing `equipment`.
+    Npc {
+        npc: String,
+        location: LocationId,
+        #[serde(default = "one")]
+        max: u32,
+        #[serde(default)]
+        equipment: Vec<String>,
+    },
+    /// Keeps up to `max` of an item lying on the floor of `location`.
+    Item {
+        item: String,
+        location: LocationId,
+        #[serde(default = "one")]
+        max: u32,
+    },
+}
+
+/// Which rule spawned an NPC. Rules are told apart by what they spawn and where, so
+/// editing an area's other rules doesn't change which one an NPC belongs to.
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+pub struct SpawnId {
+    pub area: String,
+    pub location: LocationId,
+    pub npc: String,
+}
+
+/// The rules a location's `items` and `npcs` lists stand for.
+pub(crate) fn location_spawns(def: &LocationDef) -> Vec<SpawnRule> {
+    fn count(templates: &[String]) -> BTreeMap<&String, u32> {
+        let mut counts = BTreeMap::new();
+        for template in templates {
+            *counts.entry(template).or_default() += 1;
+        }
+        counts
+    }
+
+    let npcs = count(&def.npcs)
+        .into_iter()
+        .map(|(npc, max)| SpawnRule::Npc {
+            npc: npc.clone(),
+            location: def.id.clone(),
+            max,
+            equipment: vec![],
+        });
+    let items = count(&def.items)
+        .into_iter()
+        .map(|(item, max)| SpawnRule::Item {
+            item: item.clone(),
+            location: def.id.clone(),
+            max,
+        });
+    npcs.chain(items).collect()
+}
+
+impl World {
+    /// Checks that spawn rules only refer to things that exist.
+    pub(crate) fn validate_spawns(&self) -> Result<(), String> {
+        for area in self.areas.values() {
+            for rule in &area.spawns {
+                let (location, templates, npc) = match rule {
+                    SpawnRule::Npc {
+                        npc,
+                        location,
+                        equipment,
+                        ..
+                    } => (location, equipment.as_slice(), Some(npc)),
+                    SpawnRule::Item { item, location, .. } => {
+                        (location, std::slice::from_ref(item), None)
+                    }
+                };
+                if !self.locations.contains_key(location) {
+                    return Err(format!(
+                        "Area {} spawns into unknown location {location}",
+                        area.id
+                    ));
+                }
+                if let Some(npc) = npc.filter(|npc| !self.npc_templates.contains_key(*npc)) {
+                    return Err(format!("Area {} spawns unknown NPC {npc}", area.id));
+                }
+                if let Some(item) = templates
+                    .iter()
+                    .find(|item| !self.item_templates.contains_key(*item))
+                {
+                    return Err(format!("Area {} spawns unknown item {item}", area.id));
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Puts an area back the way its file has it: doors reset, hidden exits hide again
+    /// and anything killed or taken respawns. Respawned chests are filled afresh.
+    pub fn reset_area(&mut self, area: &str) -> Result<(), String> {
+        self.reset_doors(area);
+
+        let spawns = self.areas[area].spawns.clone();
+        // how many living NPCs earlier rules for the same NPC and place have counted
+        let mut claimed = HashMap::new();
+        for rule in spawns {
+            match rule {
+                SpawnRule::Npc {
+                    npc,
+                    location,
+                    max,
+                    equipment,
+                } => {
+                    let spawn = SpawnId {
+                        area: area.to_string(),
+                        location: location.clone(),
+                        npc: npc.clone(),
+                    };
+                    let alive = self
+                        .npcs
+                        .values()
+                        .filter(|n| n.spawn.as_ref() == Some(&spawn))
+                        .count() as u32;
+                    let claimed = claimed.entry(spawn.clone()).or_insert(0);
+                    let alive = alive.saturating_sub(*claimed).min(max);
+                    *claimed += max;
+                    for _ in alive..max {
+                        let id = self.spawn_npc(&npc, &location)?;
+                        self.equip_npc(id, &equipment)?;
+                        self.npcs.get_mut(&id).unwrap().spawn = Some(spawn.clone());
+                    }
+                }
+                SpawnRule::Item {
+                    item,
+                    location,
+                    max,
+                } => {
+                    let lying = self.locations[&location]
+                        .items
+                        .iter()
+                        .filter(|id| self.items[*id].template == item)
+                        .count() as u32;
+                    for _ in lying..max {
+                        let id = self.spawn_item(&item)?;
+                        let loot = self.template(id).loot.clone();
+                        self.items.get_mut(&id).unwrap().loot = loot;
+                        self.locations.get_mut(&location).unwrap().items.push(id);
+                    }
+                }
+            }
+        }
+        Ok(())
+    }
+
+    fn equip_npc(&mut self, id: NpcId, equipment: &[String]) -> Result<(), String> {
+        for template in equipment {
+            let item = self.spawn_item(template)?;
+            self.npcs.get_mut(&id).unwrap().equipment.push(item);
+        }
+        Ok(())
+    }
+}
+
+impl Shared {
+    /// Resets any area whose `reset_ticks` have passed. Areas that only reset while
+    /// empty wait until the last player leaves.
+    pub(crate) fn reset_tick(&mut self) {
+        let mut due = vec![];
+        for area in self.world.areas.values_mut() {
+            let Some(ticks) = area.reset_ticks else {
+                continue;
+            };
+            area.reset_timer = (area.reset_timer + 1).min(ticks);
+            if area.reset_timer >= ticks {
+                due.push(area.id.clone());
+            }
+        }
+
+        for area in due {
+            let occupied = self
+                .peers
+                .values()
+                .any(|session| self.world.locations[&session.location].area == area);
+            if occupied && self.world.areas[&area].reset_when_empty {
+                continue;
+            }
+
+            self.world.areas.get_mut(&area).unwrap().reset_timer = 0;
+            if let Err(e) = self.world.reset_area(&area) {
+                eprintln!("Failed to reset {area}: {e}");
+            }
+        }
+    }
+}
@@ -0,0 +1,613 @@
+use std::collections::HashMap;
+use std::error::Error;
+use std::fs;
+use std::net::SocketAddr;
+use std::path::Path;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use model::{Response, SpeechKind};
+use rand::rngs::StdRng;
+use rand::{Rng, SeedableRng};
+use rhai::{Dynamic, Engine, ImmutableString, Map, Scope, AST};
+use serde::Deserialize;
+
+use crate::character::Role;
+use crate::combat::Combatant;
+use crate::item::{ItemFlag, ItemId};
+use crate::npc::{capitalize, NpcId};
+use crate::world::LocationId;
+use crate::Shared;
+
+/// How deeply hooks may trigger other hooks, e.g. a script moving a player into a
+/// location whose own `on_enter` moves them again.
+const MAX_SCRIPT_DEPTH: u32 = 4;
+
+#[derive(Deserialize, Clone, Debug)]
+pub struct ScriptLimits {
+    /// Rhai operations a single hook may run before being stopped.
+    pub max_operations: u64,
+    /// Wall clock time a single hook may run before being stopped.
+    pub max_time_ms: u64,
+}
+
+/// The entity a script is attached to.
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+pub enum ScriptOwner {
+    Location(LocationId),
+    Npc(NpcId),
+    Item(ItemId),
+}
+
+/// A change to the world requested by a script, applied once the hook returns.
+#[derive(Clone, Debug)]
+pub enum Effect {
+    Send { player: String, msg: String },
+    Broadcast { location: String, msg: String },
+    Say { msg: String },
+    Emote { msg: String },
+    MovePlayer { player: String, location: String },
+    MoveSelf { location: String },
+    GiveItem { player: String, template: String },
+    SpawnItem { template: String },
+    Heal { player: String, amount: i64 },
+    DestroySelf,
+    SetProp { key: String, value: Dynamic },
+}
+
+/// Compiled scripts from `data/scripts` and the sandboxed engine that runs them.
+pub struct Scripts {
+    engine: Engine,
+    scripts: HashMap<String, AST>,
+    effects: Arc<Mutex<Vec<Effect>>>,
+    deadline: Arc<Mutex<Instant>>,
+    /// Used by `random`, reseeded from the game's RNG before each hook so runs repeat.
+    rng: Arc<Mutex<StdRng>>,
+    limits: ScriptLimits,
+    /// Properties set by scripts on the entities they're attached to.
+    props: HashMap<ScriptOwner, Map>,
+}
+
+impl Scripts {
+    pub fn load(dir: &Path, limits: ScriptLimits) -> Result<Scripts, Box<dyn Error>> {
+        let effects = Arc::new(Mutex::new(vec![]));
+        let deadline = Arc::new(Mutex::new(Instant::now()));
+        let rng = Arc::new(Mutex::new(StdRng::seed_from_u64(0)));
+        let engine = build_engine(&effects, &deadline, &rng, &limits);
+
+        let mut scripts = HashMap::new();
+        if dir.exists() {
+            for entry in fs::read_dir(dir)? {
+                let path = entry?.path();
+                if path.extension().is_some_and(|ext| ext == "rhai") {
+                    let name = path.file_stem().unwrap().to_string_lossy().to_string();
+                    let ast = engine
+                        .compile_file(path.clone())
+                        .map_err(|e| format!("{}: {e}", path.display()))?;
+                    scripts.insert(name, ast);
+                }
+            }
+        }
+
+        Ok(Scripts {
+            engine,
+            scripts,
+            effects,
+            deadline,
+            rng,
+            limits,
+            props: HashMap::new(),
+        })
+    }
+
+    pub fn contains(&self, name: &str) -> bool {
+        self.scripts.contains_key(name)
+    }
+
+    pub fn props(&self, owner: &ScriptOwner) -> Map {
+        self.props.get(owner).cloned().unwrap_or_default()
+    }
+
+    /// Calls `hook` in `script` if it defines it, with `random` seeded from `seed`.
+    /// Nothing in the world changes until the returned effects are applied.
+    pub fn run(
+        &mut self,
+        script: &str,
+        hook: &str,
+        ctx: Map,
+        seed: u64,
+    ) -> Result<Vec<Effect>, String> {
+        let ast = self
+            .scripts
+            .get(script)
+            .ok_or(format!("Unknown script {script}"))?;
+        if !ast.iter_functions().any(|f| f.name == hook) {
+            return Ok(vec![]);
+        }
+
+        *self.deadline.lock().unwrap() =
+            Instant::now() + Duration::from_millis(self.limits.max_time_ms);
+        self.effects.lock().unwrap().clear();
+        *self.rng.lock().unwrap() = StdRng::seed_from_u64(seed);
+
+        let result = self
+            .engine
+            .call_fn::<Dynamic>(&mut Scope::new(), ast, hook, (ctx,));
+        let effects = std::mem::take(&mut *self.effects.lock().unwrap());
+
+        result.map(|_| effects).map_err(|e| e.to_string())
+    }
+}
+
+fn build_engine(
+    effects: &Arc<Mutex<Vec<Effect>>>,
+    deadline: &Arc<Mutex<Instant>>,
+    rng: &Arc<Mutex<StdRng>>,
+    limits: &ScriptLimits,
+) -> Engine {
+    let mut engine = Engine::new();
+    engine.set_max_operations(limits.max_operations);
+    engine.set_max_call_levels(32);
+    engine.set_max_expr_depths(64, 32);
+    engine.set_max_string_size(4096);
+    engine.set_max_array_size(1024);
+    engine.set_max_map_size(1024);
+    // scripts can't pull in other files
+    engine.set_module_resolver(rhai::module_resolvers::DummyModuleResolver::new());
+
+    let deadline = Arc::clone(deadline);
+    engine.on_progress(move |_| {
+        if Instant::now() > *deadline.lock().unwrap() {
+            Some("Script ran out of time".into())
+        } else {
+            None
+        }
+    });
+
+    let rng = Arc::clone(rng);
+    engine.register_fn("random", move |max: i64| {
+        if max <= 0 {
+            0
+        } else {
+            rng.lock().unwrap().gen_range(0..max)
+        }
+    });
+
+    let push = |effects: &Arc<Mutex<Vec<Effect>>>| {
+        let effects = Arc::clone(effects);
+        move |effect: Effect| effects.lock().unwrap().push(effect)
+    };
+
+    let p = push(effects);
+    engine.register_fn(
+        "send",
+        move |player: ImmutableString, msg: ImmutableString| {
+            p(Effect::Send {
+                player: player.to_string(),
+                msg: msg.to_string(),
+            })
+        },
+    );
+    let p = push(effects);
+    engine.register_fn(
+        "broadcast",
+        move |location: ImmutableString, msg: ImmutableString| {
+            p(Effect::Broadcast {
+                location: location.to_string(),
+                msg: msg.to_string(),
+            })
+        },
+    );
+    let p = push(effects);
+    engine.register_fn("say", move |msg: ImmutableString| {
+        p(Effect::Say {
+            msg: msg.to_string(),
+        })
+    });
+    let p = push(effects);
+    engine.register_fn("emote", move |msg: ImmutableString| {
+        p(Effect::Emote {
+            msg: msg.to_string(),
+        })
+    });
+    let p = push(effects);
+    engine.register_fn(
+        "move_player",
+        move |player: ImmutableString, location: ImmutableString| {
+            p(Effect::MovePlayer {
+                player: player.to_string(),
+                location: location.to_string(),
+            })
+        },
+    );
+    let p = push(effects);
+    engine.register_fn("move_self", move |location: ImmutableString| {
+        p(Effect::MoveSelf {
+            location: location.to_string(),
+        })
+    });
+    let p = push(effects);
+    engine.register_fn(
+        "give_item",
+        move |player: ImmutableString, template: ImmutableString| {
+            p(Effect::GiveItem {
+                player: player.to_string(),
+                template: template.to_string(),
+            })
+        },
+    );
+    let p = push(effects);
+    engine.register_fn("spawn_item", move |template: ImmutableString| {
+        p(Effect::SpawnItem {
+            template: template.to_string(),
+        })
+    });
+    let p = push(effects);
+    engine.register_fn("heal", move |player: ImmutableString, amount: i64| {
+        p(Effect::Heal {
+            player: player.to_string(),
+            amount,
+        })
+    });
+    let p = push(effects);
+    engine.register_fn("destroy_self", move || p(Effect::DestroySelf));
+    let p = push(effects);
+    engine.register_fn("set_prop", move |key: ImmutableString, value: Dynamic| {
+        p(Effect::SetProp {
+            key: key.to_string(),
+            value,
+        })
+    });
+
+    engine
+}
+
+impl Shared {
+    fn owner_location(&self, owner: &ScriptOwner) -> Option<LocationId> {
+        match owner {
+            ScriptOwner::Location(id) => Some(id.clone()),
+            ScriptOwner::Npc(id) => self.world.npcs.get(id).map(|npc| npc.location.clone()),
+            ScriptOwner::Item(id) => self.item_location(*id),
+        }
+    }
+
+    fn owner_name(&self, owner: &ScriptOwner) -> String {
+        match owner {
+            ScriptOwner::Location(id) => self.world.locations[id].name.clone(),
+            ScriptOwner::Npc(id) => self.world.npc_template(*id).name.clone(),
+            ScriptOwner::Item(id) => self.world.item_name(*id).to_string(),
+        }
+    }
+
+    /// The location an item is in, following it into inventories and containers.
+    fn item_location(&self, id: ItemId) -> Option<LocationId> {
+        let holds = |ids: &[ItemId]| {
+            ids.iter()
+                .any(|i| *i == id || self.world.items[i].contents.contains(&id))
+        };
+
+        self.world
+            .locations
+            .values()
+            .find(|location| holds(&location.items))
+            .map(|location| location.id.clone())
+            .or_else(|| {
+                self.peers
+                    .values()
+                    .find(|session| holds(&session.inventory) || holds(&session.equipment_ids()))
+                    .map(|session| session.location.clone())
+            })
+    }
+
+    fn script_context(&self, owner: &ScriptOwner, player: Option<&SocketAddr>) -> Map {
+        let mut ctx = Map::new();
+        ctx.insert("self".into(), self.owner_name(owner).into());
+        ctx.insert(
+            "location".into(),
+            self.owner_location(owner).unwrap_or_default().into(),
+        );
+        ctx.insert("props".into(), self.world.scripts.props(owner).into());
+
+        if let Some(session) = player.and_then(|addr| self.peers.get(addr)) {
+            ctx.insert("player".into(), session.name.clone().into());
+            ctx.insert(
+                "player_level".into(),
+                (session.character.level as i64).into(),
+            );
+            ctx.insert("player_hp".into(), (session.character.hp as i64).into());
+            let items = session
+                .inventory
+                .iter()
+                .chain(session.equipment.values())
+                .map(|id| Dynamic::from(self.world.items[id].template.clone()))
+                .collect::<Vec<_>>();
+            ctx.insert("player_items".into(), items.into());
+        }
+
+        ctx
+    }
+
+    /// Runs `hook` for `owner`, reporting failures to online admins instead of failing.
+    pub(crate) fn run_hook(
+        &mut self,
+        owner: ScriptOwner,
+        script: &str,
+        hook: &str,
+        player: Option<&SocketAddr>,
+        extra: Map,
+    ) {
+        if self.script_depth >= MAX_SCRIPT_DEPTH {
+            self.report_script_error(script, hook, "hooks nested too deeply");
+            return;
+        }
+
+        let mut ctx = self.script_context(&owner, player);
+        ctx.extend(extra);
+
+        let seed = self.rng.gen();
+        match self.world.scripts.run(script, hook, ctx, seed) {
+            Ok(effects) => {
+                self.script_depth += 1;
+                for effect in effects {
+                    if let Err(e) = self.apply_effect(&owner, effect) {
+                        self.report_script_error(script, hook, &e);
+                    }
+                }
+                self.script_depth -= 1;
+            }
+            Err(e) => self.report_script_error(script, hook, &e),
+        }
+    }
+
+    fn report_script_error(&self, script: &str, hook: &str, error: &str) {
+        let msg = format!("[script] {script}::{hook} failed: {error}");
+        eprintln!("{msg}");
+
+        for session in self.peers.values() {
+            if session.character.role >= Role::Admin {
+                let _ = session.send.send(Response::game_msg(&msg));
+            }
+        }
+    }
+
+    fn find_player(&self, name: &str) -> Result<SocketAddr, String> {
+        self.peers
+            .iter()
+            .find(|(_, session)| session.name.eq_ignore_ascii_case(name))
+            .map(|(addr, _)| *addr)
+            .ok_or(format!("No player called {name}"))
+    }
+
+    fn apply_effect(&mut self, owner: &ScriptOwner, effect: Effect) -> Result<(), String> {
+        let here = self.owner_location(owner);
+
+        match effect {
+            Effect::Send { player, msg } => {
+                let addr = self.find_player(&player)?;
+                self.send_to(&addr, Response::game_msg(&msg));
+            }
+            Effect::Broadcast { location, msg } => {
+                if !self.world.locations.contains_key(&location) {
+                    return Err(format!("No location called {location}"));
+                }
+                self.broadcast_location(&location, &msg, &[]);
+            }
+            Effect::Say { msg } => {
+                let here = here.ok_or("Nowhere to speak")?;
+                let name = capitalize(&self.owner_name(owner));
+                self.broadcast_speech(
+                    &here,
+                    SpeechKind::Say,
+                    &format!("{name} says, '{msg}'"),
+                    &[],
+                );
+            }
+            Effect::Emote { msg } => {
+                let here = here.ok_or("Nowhere to emote")?;
+                let name = capitalize(&self.owner_name(owner));
+                self.broadcast_speech(&here, SpeechKind::Emote, &format!("{name} {msg}"), &[]);
+            }
+            Effect::MovePlayer { player, location } => {
+                let addr = self.find_player(&player)?;
+                self.teleport(&addr, &location)?;
+            }
+            Effect::MoveSelf { location } => {
+                let ScriptOwner::Npc(id) = owner else {
+                    return Err("Only NPCs can move themselves".to_string());
+                };
+                if !self.world.locations.contains_key(&location) {
+                    return Err(format!("No location called {location}"));
+                }
+                self.world.npcs.get_mut(id).ok_or("NPC is gone")?.location = location;
+            }
+            Effect::GiveItem { player, template } => {
+                let addr = self.find_player(&player)?;
+                let id = self.world.spawn_item(&template)?;
+                self.peers.get_mut(&addr).unwrap().inventory.push(id);
+                self.send_inventory(&addr)?;
+            }
+            Effect::SpawnItem { template } => {
+                let here = here.ok_or("Nowhere to spawn items")?;
+                let id = self.world.spawn_item(&template)?;
+                self.world.locations.get_mut(&here).unwrap().items.push(id);
+            }
+            Effect::Heal { player, amount } => {
+                let addr = self.find_player(&player)?;
+                let character = &mut self.peers.get_mut(&addr).unwrap().character;
+                let amount = amount.clamp(i32::MIN.into(), i32::MAX.into()) as i32;
+                character.hp = character
+                    .hp
+                    .saturating_add(amount)
+                    .clamp(0, character.max_hp);
+                self.send_vitals(&addr);
+            }
+            Effect::DestroySelf => match owner {
+                ScriptOwner::Item(id) => {
+                    let mut holders = vec![];
+                    for (addr, session) in self.peers.iter_mut() {
+                        let held = session.inventory.len() + session.equipment.len();
+                        session.inventory.retain(|i| i != id);
+                        session.equipment.retain(|_, i| i != id);
+                        if session.inventory.len() + session.equipment.len() != held {
+                            holders.push(*addr);
+                        }
+                    }
+                    for location in self.world.locations.values_mut() {
+                        location.items.retain(|i| i != id);
+                    }
+                    for item in self.world.items.values_mut() {
+                        item.contents.retain(|i| i != id);
+                    }
+                    self.world.destroy_item(*id);
+                    for addr in holders {
+                        self.send_inventory(&addr)?;
+                    }
+                }
+                ScriptOwner::Npc(id) => {
+                    self.end_fights(Combatant::Npc(*id));
+                    self.affects.remove(&Combatant::Npc(*id));
+                    self.world.remove_npc(*id);
+                }
+                ScriptOwner::Location(_) => return Err("Locations can't be destroyed".to_string()),
+            },
+            Effect::SetProp { key, value } => {
+                self.world
+                    .scripts
+                    .props
+                    .entry(owner.clone())
+                    .or_default()
+                    .insert(key.into(), value);
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Moves a player straight to `location` without using an exit.
+    pub(crate) fn teleport(&mut self, addr: &SocketAddr, location: &str) -> Result<(), String> {
+        if !self.world.locations.contains_key(location) {
+            return Err(format!("No location called {location}"));
+        }
+
+        let session = self.session(addr)?;
+        let from = session.location.clone();
+        let name = session.name.clone();
+        let location = &self.instance_destination(addr, &from, location)?;
+
+        self.broadcast_location(&from, &format!("{name} disappears."), &[*addr]);
+        self.peers.get_mut(addr).unwrap().location = location.to_string();
+        self.broadcast_location(location, &format!("{name} appears."), &[*addr]);
+        self.look(addr, &[])?;
+        self.quest_reach(addr);
+        self.fire_enter(addr);
+        Ok(())
+    }
+
+    /// Scripts attached to a location and the NPCs in it.
+    fn location_scripts(&self, location: &str) -> Vec<(ScriptOwner, String)> {
+        let mut scripts = vec![];
+        if let Some(script) = &self.world.locations[location].script {
+            scripts.push((ScriptOwner::Location(location.to_string()), script.clone()));
+        }
+
+        let mut npcs = self.world.npcs_in_location(location).collect::<Vec<_>>();
+        npcs.sort_by_key(|npc| npc.id);
+        for npc in npcs {
+            if let Some(script) = &self.world.npc_template(npc.id).script {
+                scripts.push((ScriptOwner::Npc(npc.id), script.clone()));
+            }
+        }
+
+        scripts
+    }
+
+    /// Fires `on_enter` after a player arrives somewhere.
+    pub(crate) fn fire_enter(&mut self, addr: &SocketAddr) {
+        let Ok(session) = self.session(addr) else {
+            return;
+        };
+        for (owner, script) in self.location_scripts(&session.location.clone()) {
+            self.run_hook(owner, &script, "on_enter", Some(addr), Map::new());
+        }
+    }
+
+    /// Fires `on_say` after a player says something.
+    pub(crate) fn fire_say(&mut self, addr: &SocketAddr, msg: &str) {
+        let Ok(session) = self.session(addr) else {
+            return;
+        };
+        let mut extra = Map::new();
+        extra.insert("message".into(), msg.into());
+        for (owner, script) in self.location_scripts(&session.location.clone()) {
+            self.run_hook(owner, &script, "on_say", Some(addr), extra.clone());
+        }
+    }
+
+    /// Fires `on_tick` for every scripted location and NPC.
+    pub(crate) fn script_tick(&mut self) {
+        let mut locations = self
+            .world
+            .locations
+            .values()
+            .filter(|location| location.script.is_some())
+            .map(|location| location.id.clone())
+            .collect::<Vec<_>>();
+        locations.sort();
+        for location in locations {
+            let script = self.world.locations[&location].script.clone().unwrap();
+            self.run_hook(
+                ScriptOwner::Location(location),
+                &script,
+                "on_tick",
+                None,
+                Map::new(),
+            );
+        }
+
+        let mut npcs = self
+            .world
+            .npcs
+            .values()
+            .filter_map(|npc| Some((npc.id, self.world.npc_template(npc.id).script.clone()?)))
+            .collect::<Vec<_>>();
+        npcs.sort_by_key(|(id, _)| *id);
+        for (id, script) in npcs {
+            if self.world.npcs.contains_key(&id) {
+                self.run_hook(ScriptOwner::Npc(id), &script, "on_tick", None, Map::new());
+            }
+        }
+    }
+
+    /// `use <item>`
+    pub fn use_item(&mut self, addr: &SocketAddr, args: &[&str]) -> Result<(), String> {
+        let item_kw = args.first().ok_or("Use what?")?;
+        let session = self.session(addr)?;
+        let floor = &self.world.locations[&session.location].items;
+        let id = self
+            .world
+            .find_item(&session.inventory, item_kw)
+            .or_else(|| self.world.find_item(&session.equipment_ids(), item_kw))
+            .or_else(|| {
+                self.world
+                    .find_item(floor, item_kw)
+                    .filter(|id| self.world.template(*id).has_flag(ItemFlag::NoTake))
+            })
+            .ok_or(format!("You don't have a {item_kw}."))?;
+
+        let Some(script) = self.world.template(id).script.clone() else {
+            return Err(format!(
+                "You can't think of a way to use {}.",
+                self.world.item_name(id)
+            ));
+        };
+
+        self.run_hook(
+            ScriptOwner::Item(id),
+            &script,
+            "on_use",
+            Some(addr),
+            Map::new(),
+        );
+        Ok(())
+    }
+}
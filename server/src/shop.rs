@@ -0,0 +1,426 @@
+use std::collections::BTreeMap;
+use std::fs::OpenOptions;
+use std::io::Write;
+use std::net::SocketAddr;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use model::Response;
+use serde::{Deserialize, Serialize};
+
+use crate::character::Character;
+use crate::faction::Attitude;
+use crate::item::{ItemFlag, ItemId};
+use crate::npc::{capitalize, NpcId};
+use crate::world::World;
+use crate::Shared;
+
+/// Prices and restocking, loaded from `data/economy.ron`.
+#[derive(Deserialize, Clone, Debug)]
+pub struct EconomyRules {
+    /// Gold given to newly created characters.
+    pub starting_gold: u64,
+    /// What players pay, as a multiple of an item's value.
+    pub buy_multiplier: f64,
+    /// What shops pay players, as a multiple of an item's value.
+    pub sell_multiplier: f64,
+    /// Game ticks between each shop restocking one of every item it's short of.
+    pub restock_ticks: u32,
+    /// Where every purchase and sale is recorded, relative to the data directory.
+    pub transaction_log: String,
+}
+
+impl EconomyRules {
+    /// What a player pays for an item worth `value`, with `markup` on top.
+    pub fn buy_price(&self, value: u32, markup: f64) -> u64 {
+        let price = value as f64 * self.buy_multiplier * markup;
+        (price.ceil() as u64).max(1)
+    }
+
+    /// What a shop pays for an item worth `value`, with `markup` taken off.
+    pub fn sell_price(&self, value: u32, markup: f64) -> u64 {
+        let price = value as f64 * self.sell_multiplier / markup;
+        price.floor() as u64
+    }
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct StockEntry {
+    /// Item template.
+    pub item: String,
+    /// How many the shop keeps on hand after restocking.
+    pub max: u32,
+}
+
+fn default_markup() -> f64 {
+    1.0
+}
+
+/// What a shopkeeper NPC sells, as written in an area file.
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct ShopDef {
+    pub stock: Vec<StockEntry>,
+    /// Multiplies the prices this shop charges and divides what it pays.
+    #[serde(default = "default_markup")]
+    pub markup: f64,
+}
+
+impl World {
+    /// The stock a newly spawned shopkeeper starts with.
+    pub(crate) fn initial_stock(&self, template: &str) -> BTreeMap<String, u32> {
+        self.npc_templates[template]
+            .shop
+            .iter()
+            .flat_map(|shop| &shop.stock)
+            .map(|entry| (entry.item.clone(), entry.max))
+            .collect()
+    }
+
+    /// Checks that shops only stock items that exist, and never pay more for an item
+    /// than they charge for it.
+    pub(crate) fn validate_shops(&self) -> Result<(), String> {
+        let economy = &self.economy;
+        if !(0.0..=economy.buy_multiplier).contains(&economy.sell_multiplier) {
+            return Err("The sell multiplier must be between 0 and the buy multiplier".to_string());
+        }
+        for template in self.npc_templates.values() {
+            let Some(shop) = &template.shop else {
+                continue;
+            };
+            if !shop.markup.is_finite() || shop.markup < 1.0 {
+                return Err(format!(
+                    "Shop {} must have a markup of at least 1",
+                    template.id
+                ));
+            }
+            if let Some(entry) = shop
+                .stock
+                .iter()
+                .find(|entry| !self.item_templates.contains_key(&entry.item))
+            {
+                return Err(format!(
+                    "Shop {} stocks unknown item {}",
+                    template.id, entry.item
+                ));
+            }
+        }
+        Ok(())
+    }
+
+    fn shop(&self, id: NpcId) -> &ShopDef {
+        self.npc_template(id).shop.as_ref().unwrap()
+    }
+
+    /// What a player pays a shop for an item made from `template`.
+    pub fn buy_price(&self, shop: NpcId, template: &str, buyer: &Character) -> u64 {
+        let markup = self.shop(shop).markup * self.price_multiplier(shop, buyer);
+        self.economy
+            .buy_price(self.item_templates[template].value, markup)
+    }
+
+    /// What a shop pays a player for an item made from `template`.
+    pub fn sell_price(&self, shop: NpcId, template: &str, seller: &Character) -> u64 {
+        // good standing gets players discounts, never more for what they sell
+        let markup = self.shop(shop).markup * self.price_multiplier(shop, seller).max(1.0);
+        self.economy
+            .sell_price(self.item_templates[template].value, markup)
+    }
+
+    fn find_shopkeeper(&self, location: &str) -> Option<NpcId> {
+        let mut shops = self
+            .npcs_in_location(location)
+            .filter(|npc| self.npc_template(npc.id).shop.is_some())
+            .map(|npc| npc.id)
+            .collect::<Vec<_>>();
+        shops.sort();
+        shops.first().copied()
+    }
+}
+
+impl Shared {
+    fn shopkeeper_here(&self, addr: &SocketAddr) -> Result<NpcId, String> {
+        let session = self.session(addr)?;
+        let shop = self
+            .world
+            .find_shopkeeper(&session.location)
+            .ok_or("There is no shop here.".to_string())?;
+        if self.world.attitude(shop, &session.character) == Attitude::Hostile {
+            return Err(format!(
+                "{} won't deal with you.",
+                capitalize(&self.world.npc_template(shop).name)
+            ));
+        }
+        Ok(shop)
+    }
+
+    /// Records a purchase or sale in the transaction log.
+    fn log_transaction(&self, addr: &SocketAddr, action: &str, shop: NpcId, item: &str, gold: u64) {
+        let Ok(session) = self.session(addr) else {
+            return;
+        };
+        let time = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map_or(0, |d| d.as_secs());
+        let line = format!(
+            "{time}\t{}\t{action}\t{item}\t{gold}\t{}\t{}\n",
+            session.name, self.world.npcs[&shop].template, session.character.gold,
+        );
+
+        let path = self
+            .world
+            .data_dir
+            .join(&self.world.economy.transaction_log);
+        let result = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&path)
+            .and_then(|mut file| file.write_all(line.as_bytes()));
+        if let Err(e) = result {
+            eprintln!("Failed to write {}: {e}", path.display());
+        }
+    }
+
+    /// `list`
+    pub fn list_stock(&self, addr: &SocketAddr) -> Result<(), String> {
+        let shop = self.shopkeeper_here(addr)?;
+        let npc = &self.world.npcs[&shop];
+        let name = capitalize(&self.world.npc_template(shop).name);
+        let character = &self.session(addr)?.character;
+
+        let mut msg = format!("{name} has for sale:");
+        let mut empty = true;
+        for (item, count) in npc.stock.iter().filter(|(_, count)| **count > 0) {
+            empty = false;
+            msg.push_str(&format!(
+                "\n  {:>5} gold  {} ({count})",
+                self.world.buy_price(shop, item, character),
+                self.world.item_templates[item].name,
+            ));
+        }
+        if empty {
+            msg.push_str("\n  Nothing at the moment.");
+        }
+        msg.push_str(&format!("\nYou have {} gold.", character.gold));
+
+        self.send_to(addr, Response::game_msg(&msg));
+        Ok(())
+    }
+
+    /// `buy <item>`
+    pub fn buy_item(&mut self, addr: &SocketAddr, args: &[&str]) -> Result<(), String> {
+        let item_kw = args.first().ok_or("Buy what?")?;
+        let shop = self.shopkeeper_here(addr)?;
+        let shop_name = capitalize(&self.world.npc_template(shop).name);
+
+        let template = self.world.npcs[&shop]
+            .stock
+            .iter()
+            .find(|(item, count)| **count > 0 && self.world.item_templates[*item].matches(item_kw))
+            .map(|(item, _)| item.clone())
+            .ok_or(format!("{shop_name} doesn't sell {item_kw}."))?;
+
+        let session = self.session(addr)?;
+        let price = self.world.buy_price(shop, &template, &session.character);
+        if session.character.gold < price {
+            return Err(format!("You can't afford that. It costs {price} gold."));
+        }
+        self.check_capacity(addr, self.world.item_templates[&template].weight)?;
+
+        let id = self.world.spawn_item(&template)?;
+        *self
+            .world
+            .npcs
+            .get_mut(&shop)
+            .unwrap()
+            .stock
+            .get_mut(&template)
+            .unwrap() -= 1;
+        let session = self.peers.get_mut(addr).unwrap();
+        session.character.gold -= price;
+        session.inventory.push(id);
+        let name = session.name.clone();
+        let location = session.location.clone();
+
+        let item_name = self.world.item_name(id).to_string();
+        self.send_to(
+            addr,
+            Response::game_msg(&format!("You buy {item_name} for {price} gold.")),
+        );
+        self.broadcast_location(
+            &location,
+            &format!(
+                "{name} buys {item_name} from {}.",
+                self.world.npc_template(shop).name
+            ),
+            &[*addr],
+        );
+        self.log_transaction(addr, "buy", shop, &template, price);
+        self.send_inventory(addr)
+    }
+
+    /// Finds an item in the player's inventory and works out what the shop here would pay.
+    fn shop_offer(&self, addr: &SocketAddr, keyword: &str) -> Result<(NpcId, ItemId, u64), String> {
+        let shop = self.shopkeeper_here(addr)?;
+        let session = self.session(addr)?;
+        let id = self
+            .world
+            .find_item(&session.inventory, keyword)
+            .ok_or(format!("You don't have a {keyword}."))?;
+
+        let shop_name = capitalize(&self.world.npc_template(shop).name);
+        let item = &self.world.items[&id];
+        if !item.contents.is_empty() {
+            return Err(format!(
+                "You'll want to empty {} first.",
+                self.world.item_name(id)
+            ));
+        }
+        let price = self
+            .world
+            .sell_price(shop, &item.template, &session.character);
+        if price == 0 || self.world.template(id).has_flag(ItemFlag::NoDrop) {
+            return Err(format!(
+                "{shop_name} isn't interested in {}.",
+                self.world.item_name(id)
+            ));
+        }
+        Ok((shop, id, price))
+    }
+
+    /// `appraise <item>`
+    pub fn appraise_item(&self, addr: &SocketAddr, args: &[&str]) -> Result<(), String> {
+        let item_kw = args.first().ok_or("Appraise what?")?;
+        let (shop, id, price) = self.shop_offer(addr, item_kw)?;
+        self.npc_say(
+            shop,
+            &format!(
+                "I'd give you {price} gold for {}.",
+                self.world.item_name(id)
+            ),
+        );
+        Ok(())
+    }
+
+    /// `sell <item>`
+    pub fn sell_item(&mut self, addr: &SocketAddr, args: &[&str]) -> Result<(), String> {
+        let item_kw = args.first().ok_or("Sell what?")?;
+        let (shop, id, price) = self.shop_offer(addr, item_kw)?;
+
+        let template = self.world.items[&id].template.clone();
+        let item_name = self.world.item_name(id).to_string();
+        let session = self.peers.get_mut(addr).unwrap();
+        session.inventory.retain(|i| *i != id);
+        session.character.gold += price;
+        let name = session.name.clone();
+        let location = session.location.clone();
+
+        self.world.destroy_item(id);
+        // the shop resells anything it buys
+        *self
+            .world
+            .npcs
+            .get_mut(&shop)
+            .unwrap()
+            .stock
+            .entry(template.clone())
+            .or_default() += 1;
+
+        self.send_to(
+            addr,
+            Response::game_msg(&format!("You sell {item_name} for {price} gold.")),
+        );
+        self.broadcast_location(
+            &location,
+            &format!(
+                "{name} sells {item_name} to {}.",
+                self.world.npc_template(shop).name
+            ),
+            &[*addr],
+        );
+        self.log_transaction(addr, "sell", shop, &template, price);
+        self.send_inventory(addr)
+    }
+
+    /// Tops up every shop's stock once every `restock_ticks` game ticks.
+    pub(crate) fn shop_tick(&mut self) {
+        self.restock_timer += 1;
+        if self.restock_timer < self.world.economy.restock_ticks {
+            return;
+        }
+        self.restock_timer = 0;
+
+        let shops = self
+            .world
+            .npcs
+            .values()
+            .filter_map(|npc| Some((npc.id, self.world.npc_template(npc.id).shop.clone()?)))
+            .collect::<Vec<_>>();
+        for (id, shop) in shops {
+            let stock = &mut self.world.npcs.get_mut(&id).unwrap().stock;
+            for entry in shop.stock {
+                let count = stock.entry(entry.item).or_default();
+                if *count < entry.max {
+                    *count += 1;
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn economy(buy_multiplier: f64, sell_multiplier: f64) -> EconomyRules {
+        EconomyRules {
+            starting_gold: 0,
+            buy_multiplier,
+            sell_multiplier,
+            restock_ticks: 1,
+            transaction_log: String::new(),
+        }
+    }
+
+    #[test]
+    fn selling_never_pays_more_than_buying_costs() {
+        for (buy, sell) in [(1.0, 0.5), (1.0, 1.0), (2.0, 0.1)] {
+            let economy = economy(buy, sell);
+            for markup in [1.0, 1.2, 1.5, 3.0] {
+                for value in 0..500 {
+                    assert!(
+                        economy.sell_price(value, markup) <= economy.buy_price(value, markup),
+                        "value {value}, markup {markup}, multipliers {buy}/{sell}"
+                    );
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn standing_discounts_never_beat_selling() {
+        let economy = economy(1.0, 0.5);
+        // the lowest price validation allows with these multipliers
+        for standing in [0.5_f64, 0.9, 1.0, 1.5] {
+            for value in 0..500 {
+                assert!(
+                    economy.sell_price(value, standing.max(1.0))
+                        <= economy.buy_price(value, standing),
+                    "value {value}, standing {standing}"
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn markup_below_one_lets_players_print_money() {
+        let economy = economy(1.0, 0.5);
+        assert!(economy.sell_price(100, 0.5) > economy.buy_price(100, 0.5));
+    }
+
+    #[test]
+    fn prices_round_in_the_shops_favour() {
+        let economy = economy(1.0, 0.5);
+        assert_eq!(economy.buy_price(3, 1.2), 4);
+        assert_eq!(economy.sell_price(3, 1.2), 1);
+        assert_eq!(economy.buy_price(0, 1.0), 1);
+    }
+}
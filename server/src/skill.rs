@@ -0,0 +1,515 @@
+use std::collections::{BTreeMap, HashSet};
+use std::net::SocketAddr;
+
+use model::{AffectView, GameUpdate, Response, SkillView, SkillsView};
+use rand::Rng;
+use serde::Deserialize;
+
+use crate::character::{Attribute, Character};
+use crate::combat::Combatant;
+use crate::npc::capitalize;
+use crate::world::World;
+use crate::Shared;
+
+/// Who a skill can be used on.
+#[derive(Deserialize, Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Targeting {
+    /// Always the user.
+    Caster,
+    /// The user or another player in the same location, defaulting to the user.
+    Ally,
+    /// An NPC or player to fight, defaulting to whoever the user is fighting.
+    Enemy,
+}
+
+/// A buff or debuff that wears off after a number of game ticks.
+#[derive(Deserialize, Clone, Debug)]
+pub struct AffectDef {
+    pub name: String,
+    pub duration: u32,
+    #[serde(default)]
+    pub attack: i32,
+    #[serde(default)]
+    pub defense: i32,
+    /// Hit points gained each tick, or lost if negative.
+    #[serde(default)]
+    pub hp_per_tick: i32,
+}
+
+impl AffectDef {
+    fn harmful(&self) -> bool {
+        self.attack < 0 || self.defense < 0 || self.hp_per_tick < 0
+    }
+}
+
+#[derive(Deserialize, Clone, Debug)]
+pub enum SkillEffect {
+    /// Rolls `min..=max`, plus the user's modifier for `bonus`.
+    Damage {
+        min: i32,
+        max: i32,
+        #[serde(default)]
+        bonus: Option<Attribute>,
+    },
+    Heal {
+        min: i32,
+        max: i32,
+        #[serde(default)]
+        bonus: Option<Attribute>,
+    },
+    Affect(AffectDef),
+}
+
+/// A skill or spell, loaded from `data/skills.ron`.
+#[derive(Deserialize, Clone, Debug)]
+pub struct SkillDef {
+    pub id: String,
+    pub name: String,
+    #[serde(default)]
+    pub description: String,
+    /// The level at which each class learns it.
+    pub classes: BTreeMap<String, u32>,
+    #[serde(default)]
+    pub mana: i32,
+    #[serde(default)]
+    pub stamina: i32,
+    /// Game ticks before it can be used again.
+    pub cooldown: u32,
+    pub target: Targeting,
+    pub effects: Vec<SkillEffect>,
+}
+
+impl SkillDef {
+    /// Whether `keyword` names the skill. Each of its words has to start the next word
+    /// of the name, e.g. "sh ba" for "Shield Bash".
+    pub fn matches(&self, keyword: &str) -> bool {
+        let keyword = keyword.to_lowercase();
+        let words = keyword.split_whitespace().collect::<Vec<_>>();
+        let name = self.name.to_lowercase();
+        let name = name.split_whitespace().collect::<Vec<_>>();
+        !words.is_empty()
+            && (self.id.starts_with(&keyword)
+                || name.windows(words.len()).any(|run| {
+                    run.iter()
+                        .zip(&words)
+                        .all(|(name, word)| name.starts_with(word))
+                }))
+    }
+
+    fn known_by(&self, character: &Character) -> bool {
+        self.classes
+            .get(&character.class)
+            .is_some_and(|level| *level <= character.level)
+    }
+
+    fn cost(&self) -> String {
+        match (self.mana, self.stamina) {
+            (0, 0) => "free".to_string(),
+            (mana, 0) => format!("{mana} mana"),
+            (0, stamina) => format!("{stamina} stamina"),
+            (mana, stamina) => format!("{mana} mana, {stamina} stamina"),
+        }
+    }
+}
+
+/// A buff or debuff currently on a combatant.
+#[derive(Clone, Debug)]
+pub struct Affect {
+    pub def: AffectDef,
+    /// Ticks until it wears off.
+    pub remaining: u32,
+    /// Credited with kills from damage over time.
+    pub caster: Combatant,
+}
+
+impl World {
+    /// Checks that skills are learned by classes that exist and have sensible rolls.
+    pub(crate) fn validate_skills(&self) -> Result<(), String> {
+        let mut ids = HashSet::new();
+        for skill in &self.skills {
+            if !ids.insert(&skill.id) {
+                return Err(format!("Duplicate skill {}", skill.id));
+            }
+            if let Some(class) = skill
+                .classes
+                .keys()
+                .find(|class| self.character_rules.class(class).is_none())
+            {
+                return Err(format!(
+                    "Skill {} is learned by unknown class {class}",
+                    skill.id
+                ));
+            }
+            for effect in &skill.effects {
+                match effect {
+                    SkillEffect::Damage { min, max, .. } | SkillEffect::Heal { min, max, .. }
+                        if min > max =>
+                    {
+                        return Err(format!("Skill {} has an empty roll", skill.id));
+                    }
+                    SkillEffect::Affect(affect) if affect.duration == 0 => {
+                        return Err(format!("Skill {} has an affect that never lasts", skill.id));
+                    }
+                    _ => {}
+                }
+            }
+        }
+        Ok(())
+    }
+
+    pub fn known_skills<'a>(
+        &'a self,
+        character: &'a Character,
+    ) -> impl Iterator<Item = &'a SkillDef> + 'a {
+        self.skills
+            .iter()
+            .filter(move |skill| skill.known_by(character))
+    }
+}
+
+impl Shared {
+    /// Attack and defense bonuses from everything affecting `c`.
+    pub(crate) fn affect_bonuses(&self, c: Combatant) -> (i32, i32) {
+        self.affects.get(&c).map_or((0, 0), |affects| {
+            affects.iter().fold((0, 0), |(attack, defense), affect| {
+                (attack + affect.def.attack, defense + affect.def.defense)
+            })
+        })
+    }
+
+    /// Sends the player their skills' cooldowns and what's affecting them.
+    pub(crate) fn send_skills(&self, addr: &SocketAddr) {
+        let Ok(session) = self.session(addr) else {
+            return;
+        };
+        let skills = self
+            .world
+            .known_skills(&session.character)
+            .map(|skill| SkillView {
+                name: skill.name.clone(),
+                cost: skill.cost(),
+                cooldown: session.cooldowns.get(&skill.id).copied().unwrap_or(0),
+                max_cooldown: skill.cooldown,
+            })
+            .collect();
+        let affects = self
+            .affects
+            .get(&Combatant::Player(*addr))
+            .into_iter()
+            .flatten()
+            .map(|affect| AffectView {
+                name: affect.def.name.clone(),
+                remaining: affect.remaining,
+                harmful: affect.def.harmful(),
+            })
+            .collect();
+        self.send_to(
+            addr,
+            Response::Game(GameUpdate::Skills(SkillsView { skills, affects })),
+        );
+    }
+
+    /// Tells a player about skills they learned by going from `old_level` to their
+    /// current level.
+    pub(crate) fn announce_new_skills(&self, addr: &SocketAddr, old_level: u32) {
+        let Ok(session) = self.session(addr) else {
+            return;
+        };
+        let character = &session.character;
+        for skill in self.world.known_skills(character) {
+            if skill.classes[&character.class] > old_level {
+                self.send_to(
+                    addr,
+                    Response::game_msg(&format!("You have learned {}!", skill.name)),
+                );
+            }
+        }
+        self.send_skills(addr);
+    }
+
+    /// `skills`
+    pub fn list_skills(&self, addr: &SocketAddr) -> Result<(), String> {
+        let session = self.session(addr)?;
+        let mut msg = String::from("Your skills:");
+        let mut any = false;
+        for skill in self.world.known_skills(&session.character) {
+            any = true;
+            let ready = match session.cooldowns.get(&skill.id) {
+                Some(ticks) => format!("ready in {ticks}"),
+                None => "ready".to_string(),
+            };
+            msg.push_str(&format!(
+                "\n  {:<16} {:<20} {ready}",
+                skill.name,
+                skill.cost()
+            ));
+        }
+        if !any {
+            msg.push_str("\n  None yet.");
+        }
+
+        if let Some(affects) = self.affects.get(&Combatant::Player(*addr)) {
+            msg.push_str("\nAffected by:");
+            for affect in affects {
+                msg.push_str(&format!(
+                    "\n  {} ({} more ticks)",
+                    affect.def.name, affect.remaining
+                ));
+            }
+        }
+
+        self.send_to(addr, Response::game_msg(&msg));
+        Ok(())
+    }
+
+    /// `cast <skill> [target]`
+    pub fn cast(&mut self, addr: &SocketAddr, args: &[&str]) -> Result<(), String> {
+        if args.is_empty() {
+            return Err("Cast what?".to_string());
+        }
+        let session = self.session(addr)?;
+        // the skill is the most leading words that name one, e.g. `cast shield bash wolf`
+        let (skill, target_kw) = (1..=args.len())
+            .rev()
+            .find_map(|words| {
+                let skill_kw = args[..words].join(" ");
+                self.world
+                    .known_skills(&session.character)
+                    .find(|skill| skill.matches(&skill_kw))
+                    .map(|skill| (skill.clone(), args.get(words).copied()))
+            })
+            .ok_or(format!("You don't know {}.", args[0]))?;
+
+        if let Some(ticks) = session.cooldowns.get(&skill.id) {
+            return Err(format!("{} will be ready in {ticks} ticks.", skill.name));
+        }
+        let character = &session.character;
+        if character.mana < skill.mana {
+            return Err("You don't have enough mana.".to_string());
+        }
+        if character.stamina < skill.stamina {
+            return Err("You are too tired.".to_string());
+        }
+
+        let caster = Combatant::Player(*addr);
+        let location = session.location.clone();
+        let target = match (skill.target, target_kw) {
+            (Targeting::Caster, _) | (Targeting::Ally, None) => caster,
+            (Targeting::Ally, Some(kw)) => Combatant::Player(
+                self.find_user_in_location(&location, kw)
+                    .ok_or(format!("You don't see {kw} here."))?,
+            ),
+            (Targeting::Enemy, Some(kw)) => self
+                .find_combatant(&location, kw)
+                .ok_or(format!("You don't see {kw} here."))?,
+            (Targeting::Enemy, None) => self
+                .fights
+                .get(&caster)
+                .copied()
+                .ok_or(format!("Cast {} on whom?", skill.name))?,
+        };
+        if skill.target == Targeting::Enemy {
+            if target == caster {
+                return Err("You can't attack yourself.".to_string());
+            }
+            self.check_attack(caster, target)?;
+        }
+
+        let session = self.peers.get_mut(addr).unwrap();
+        session.character.mana -= skill.mana;
+        session.character.stamina -= skill.stamina;
+        if skill.cooldown > 0 {
+            session.cooldowns.insert(skill.id.clone(), skill.cooldown);
+        }
+        let name = session.name.clone();
+
+        if target == caster {
+            self.send_to(
+                addr,
+                Response::game_msg(&format!("You cast {}.", skill.name)),
+            );
+            self.broadcast_location(
+                &location,
+                &format!("{name} casts {}.", skill.name),
+                &[*addr],
+            );
+        } else {
+            let target_name = self.combatant_name(target);
+            self.send_to(
+                addr,
+                Response::game_msg(&format!("You cast {} on {target_name}.", skill.name)),
+            );
+            let mut except = vec![*addr];
+            if let Combatant::Player(other) = target {
+                self.send_to(
+                    &other,
+                    Response::game_msg(&format!("{name} casts {} on you.", skill.name)),
+                );
+                except.push(other);
+            }
+            self.broadcast_location(
+                &location,
+                &format!("{name} casts {} on {target_name}.", skill.name),
+                &except,
+            );
+        }
+
+        if skill.target == Targeting::Enemy && !self.is_fighting(caster) {
+            self.start_fight(caster, target);
+        }
+
+        let modifier = |bonus: &Option<Attribute>, shared: &Shared| {
+            bonus.map_or(0, |attribute| {
+                shared.peers[addr].character.attributes.modifier(attribute)
+            })
+        };
+        for effect in &skill.effects {
+            match effect {
+                SkillEffect::Damage { min, max, bonus } => {
+                    let amount = (self.rng.gen_range(*min..=*max) + modifier(bonus, self)).max(1);
+                    self.damage(target, amount);
+                    self.affect_message(
+                        target,
+                        &format!("You take {amount} damage."),
+                        &format!("{} takes {amount} damage.", self.combatant_name(target)),
+                    );
+                    if self.combatant_hp(target).0 <= 0 {
+                        self.kill(target, Some(caster));
+                        break;
+                    }
+                }
+                SkillEffect::Heal { min, max, bonus } => {
+                    let amount = (self.rng.gen_range(*min..=*max) + modifier(bonus, self)).max(1);
+                    let amount = self.heal(target, amount);
+                    self.affect_message(
+                        target,
+                        &format!("You are healed for {amount}."),
+                        &format!("{} is healed for {amount}.", self.combatant_name(target)),
+                    );
+                }
+                SkillEffect::Affect(def) => self.add_affect(target, def, caster),
+            }
+        }
+
+        self.send_vitals(addr);
+        self.send_skills(addr);
+        Ok(())
+    }
+
+    /// Sends `you` to `subject` if they're a player and `others` to everyone else there.
+    fn affect_message(&self, subject: Combatant, you: &str, others: &str) {
+        let location = self.combatant_location(subject);
+        let except = match subject {
+            Combatant::Player(addr) => {
+                self.send_to(&addr, Response::game_msg(you));
+                vec![addr]
+            }
+            Combatant::Npc(_) => vec![],
+        };
+        self.broadcast_location(&location, &capitalize(others), &except);
+    }
+
+    /// Puts a buff or debuff on `target`, replacing any with the same name.
+    fn add_affect(&mut self, target: Combatant, def: &AffectDef, caster: Combatant) {
+        let affects = self.affects.entry(target).or_default();
+        affects.retain(|affect| affect.def.name != def.name);
+        affects.push(Affect {
+            def: def.clone(),
+            remaining: def.duration,
+            caster,
+        });
+
+        self.affect_message(
+            target,
+            &format!("You are affected by {}.", def.name),
+            &format!(
+                "{} is affected by {}.",
+                self.combatant_name(target),
+                def.name
+            ),
+        );
+        if let Combatant::Player(addr) = target {
+            self.send_skills(&addr);
+        }
+    }
+
+    /// Counts down cooldowns and buffs, applying damage and healing over time.
+    pub(crate) fn skill_tick(&mut self) {
+        let mut changed = HashSet::new();
+        for (addr, session) in self.peers.iter_mut() {
+            if session.cooldowns.is_empty() {
+                continue;
+            }
+            session.cooldowns.retain(|_, ticks| {
+                *ticks = ticks.saturating_sub(1);
+                *ticks > 0
+            });
+            changed.insert(*addr);
+        }
+
+        let mut affected = self.affects.keys().copied().collect::<Vec<_>>();
+        affected.sort();
+        for c in affected {
+            if !self.combatant_exists(c) {
+                self.affects.remove(&c);
+                continue;
+            }
+
+            let mut killer = None;
+            for affect in self.affects[&c].clone() {
+                match affect.def.hp_per_tick {
+                    0 => {}
+                    amount if amount > 0 => {
+                        self.heal(c, amount);
+                    }
+                    amount => {
+                        self.damage(c, -amount);
+                        self.affect_message(
+                            c,
+                            &format!("You take {} damage from {}.", -amount, affect.def.name),
+                            &format!(
+                                "{} takes {} damage from {}.",
+                                self.combatant_name(c),
+                                -amount,
+                                affect.def.name
+                            ),
+                        );
+                        killer = Some(affect.caster);
+                    }
+                }
+            }
+            if self.combatant_hp(c).0 <= 0 {
+                let killer = killer.filter(|k| *k != c && self.combatant_exists(*k));
+                self.kill(c, killer);
+                continue;
+            }
+
+            let affects = self.affects.get_mut(&c).unwrap();
+            let mut expired = vec![];
+            affects.retain_mut(|affect| {
+                affect.remaining = affect.remaining.saturating_sub(1);
+                if affect.remaining == 0 {
+                    expired.push(affect.def.name.clone());
+                }
+                affect.remaining > 0
+            });
+            if affects.is_empty() {
+                self.affects.remove(&c);
+            }
+
+            if let Combatant::Player(addr) = c {
+                for name in expired {
+                    self.send_to(
+                        &addr,
+                        Response::game_msg(&format!("{} wears off.", capitalize(&name))),
+                    );
+                }
+                changed.insert(addr);
+            }
+        }
+
+        for addr in changed {
+            self.send_skills(&addr);
+        }
+    }
+}
@@ -0,0 +1,273 @@
+use std::net::SocketAddr;
+
+use model::{Response, SpeechKind};
+use serde::{Deserialize, Serialize};
+
+use crate::npc::capitalize;
+use crate::world::World;
+use crate::Shared;
+
+/// How a character or NPC is referred to in socials.
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum Pronouns {
+    #[default]
+    They,
+    He,
+    She,
+    It,
+}
+
+impl Pronouns {
+    pub fn parse(s: &str) -> Option<Pronouns> {
+        match s.to_lowercase().as_str() {
+            "they" | "them" => Some(Pronouns::They),
+            "he" | "him" => Some(Pronouns::He),
+            "she" | "her" => Some(Pronouns::She),
+            "it" => Some(Pronouns::It),
+            _ => None,
+        }
+    }
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Pronouns::They => "they/them",
+            Pronouns::He => "he/him",
+            Pronouns::She => "she/her",
+            Pronouns::It => "it/its",
+        }
+    }
+
+    pub fn subject(self) -> &'static str {
+        match self {
+            Pronouns::They => "they",
+            Pronouns::He => "he",
+            Pronouns::She => "she",
+            Pronouns::It => "it",
+        }
+    }
+
+    pub fn object(self) -> &'static str {
+        match self {
+            Pronouns::They => "them",
+            Pronouns::He => "him",
+            Pronouns::She => "her",
+            Pronouns::It => "it",
+        }
+    }
+
+    pub fn possessive(self) -> &'static str {
+        match self {
+            Pronouns::They => "their",
+            Pronouns::He => "his",
+            Pronouns::She => "her",
+            Pronouns::It => "its",
+        }
+    }
+}
+
+/// What a social shows when it's done without a target.
+#[derive(Deserialize, Clone, Debug)]
+pub struct SocialMessages {
+    pub actor: String,
+    pub others: String,
+}
+
+/// What a social shows when it's done to someone.
+#[derive(Deserialize, Clone, Debug)]
+pub struct TargetedMessages {
+    pub actor: String,
+    pub target: String,
+    pub others: String,
+}
+
+/// A social from `socials.ron`. Its messages can use `$n` for the actor's name and
+/// `$e`, `$m` and `$s` for their subject, object and possessive pronouns, with the
+/// upper case letters standing for the target instead.
+#[derive(Deserialize, Clone, Debug)]
+pub struct SocialDef {
+    pub id: String,
+    pub alone: SocialMessages,
+    /// Without these the social can't be done to anyone.
+    #[serde(default)]
+    pub targeted: Option<TargetedMessages>,
+}
+
+/// Someone named in a social.
+struct Person {
+    name: String,
+    pronouns: Pronouns,
+}
+
+/// Fills in a social message. Lines always start with a capital letter.
+fn fill(template: &str, actor: &Person, target: Option<&Person>) -> String {
+    let mut line = String::new();
+    let mut chars = template.chars();
+    while let Some(c) = chars.next() {
+        if c != '$' {
+            line.push(c);
+            continue;
+        }
+        let Some(token) = chars.next() else {
+            break;
+        };
+        let person = match token.is_uppercase() {
+            true => target.unwrap_or(actor),
+            false => actor,
+        };
+        match token.to_ascii_lowercase() {
+            'n' => line.push_str(&person.name),
+            'e' => line.push_str(person.pronouns.subject()),
+            'm' => line.push_str(person.pronouns.object()),
+            's' => line.push_str(person.pronouns.possessive()),
+            _ => line.push(token),
+        }
+    }
+    capitalize(&line)
+}
+
+/// Checks a social message only uses tokens that exist, and target ones only when
+/// there is a target.
+fn check_tokens(social: &str, template: &str, targeted: bool) -> Result<(), String> {
+    let mut chars = template.chars();
+    while let Some(c) = chars.next() {
+        if c != '$' {
+            continue;
+        }
+        match chars.next() {
+            Some('n' | 'e' | 'm' | 's' | '$') => {}
+            Some('N' | 'E' | 'M' | 'S') if targeted => {}
+            Some(token) => {
+                return Err(format!("Social {social} can't use ${token} there"));
+            }
+            None => return Err(format!("Social {social} has a message ending in $")),
+        }
+    }
+    Ok(())
+}
+
+impl World {
+    pub(crate) fn validate_socials(&self) -> Result<(), String> {
+        for social in self.socials.values() {
+            check_tokens(&social.id, &social.alone.actor, false)?;
+            check_tokens(&social.id, &social.alone.others, false)?;
+            if let Some(targeted) = &social.targeted {
+                check_tokens(&social.id, &targeted.actor, true)?;
+                check_tokens(&social.id, &targeted.target, true)?;
+                check_tokens(&social.id, &targeted.others, true)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+impl Shared {
+    /// `<social> [target]`, e.g. `wave` or `wave Alice`.
+    pub fn social(&self, addr: &SocketAddr, id: &str, args: &[&str]) -> Result<(), String> {
+        let social = &self.world.socials[id];
+        let session = self.session(addr)?;
+        let actor = Person {
+            name: session.name.clone(),
+            pronouns: session.character.pronouns,
+        };
+        let location = &session.location;
+
+        let target = match args.first() {
+            None => None,
+            Some(keyword) => match self.find_user_in_location(location, keyword) {
+                Some(other) if other == *addr => None,
+                Some(other) => {
+                    let other_session = self.session(&other)?;
+                    let person = Person {
+                        name: other_session.name.clone(),
+                        pronouns: other_session.character.pronouns,
+                    };
+                    Some((Some(other), person))
+                }
+                None => {
+                    let npc = self
+                        .world
+                        .find_npc(location, keyword)
+                        .ok_or(format!("You don't see {keyword} here."))?;
+                    let template = self.world.npc_template(npc);
+                    let person = Person {
+                        name: template.name.clone(),
+                        pronouns: template.pronouns,
+                    };
+                    Some((None, person))
+                }
+            },
+        };
+
+        let Some((other, target)) = target else {
+            self.send_to(
+                addr,
+                Response::speech(SpeechKind::Emote, &fill(&social.alone.actor, &actor, None)),
+            );
+            self.broadcast_speech(
+                location,
+                SpeechKind::Emote,
+                &fill(&social.alone.others, &actor, None),
+                &[*addr],
+            );
+            return Ok(());
+        };
+
+        let targeted = social
+            .targeted
+            .as_ref()
+            .ok_or(format!("You can't {id} at anyone."))?;
+        self.send_to(
+            addr,
+            Response::speech(
+                SpeechKind::Emote,
+                &fill(&targeted.actor, &actor, Some(&target)),
+            ),
+        );
+        let mut except = vec![*addr];
+        if let Some(other) = other {
+            self.send_to(
+                &other,
+                Response::speech(
+                    SpeechKind::Emote,
+                    &fill(&targeted.target, &actor, Some(&target)),
+                ),
+            );
+            except.push(other);
+        }
+        self.broadcast_speech(
+            location,
+            SpeechKind::Emote,
+            &fill(&targeted.others, &actor, Some(&target)),
+            &except,
+        );
+        Ok(())
+    }
+
+    /// `socials`
+    pub fn list_socials(&self, addr: &SocketAddr) -> Result<(), String> {
+        let mut ids = self.world.socials.keys().cloned().collect::<Vec<_>>();
+        ids.sort();
+        let msg = format!("Socials: {}", ids.join(", "));
+        self.send_to(addr, Response::game_msg(&msg));
+        Ok(())
+    }
+
+    /// `pronouns [they|he|she|it]`
+    pub fn pronouns(&mut self, addr: &SocketAddr, args: &[&str]) -> Result<(), String> {
+        let Some(choice) = args.first() else {
+            let pronouns = self.session(addr)?.character.pronouns;
+            let msg = format!(
+                "Your pronouns are {}. Use 'pronouns they', 'he', 'she' or 'it' to change them.",
+                pronouns.name()
+            );
+            self.send_to(addr, Response::game_msg(&msg));
+            return Ok(());
+        };
+        let pronouns = Pronouns::parse(choice)
+            .ok_or("Pronouns can be 'they', 'he', 'she' or 'it'.".to_string())?;
+        self.peers.get_mut(addr).unwrap().character.pronouns = pronouns;
+        let msg = format!("Your pronouns are now {}.", pronouns.name());
+        self.send_to(addr, Response::game_msg(&msg));
+        Ok(())
+    }
+}
@@ -0,0 +1,198 @@
+use std::collections::BTreeMap;
+use std::net::SocketAddr;
+
+use model::{Response, SpeechKind};
+
+use crate::world::{Direction, LocationId};
+use crate::Shared;
+
+impl Direction {
+    /// Where a sound coming through an exit this way seems to come from.
+    fn heard_from(self) -> &'static str {
+        match self {
+            Direction::North => "from the north",
+            Direction::East => "from the east",
+            Direction::South => "from the south",
+            Direction::West => "from the west",
+            Direction::Up => "from above",
+            Direction::Down => "from below",
+        }
+    }
+}
+
+/// Turns `emote` text into a line about `name`, e.g. "Ann waves." or "Ann's eyes narrow."
+fn emote_line(name: &str, action: &str) -> String {
+    let mut line = if action.starts_with('\'') || action.starts_with(',') {
+        format!("{name}{action}")
+    } else {
+        format!("{name} {action}")
+    };
+    if !line.ends_with(['.', '!', '?']) {
+        line.push('.');
+    }
+    line
+}
+
+impl Shared {
+    /// Sends speech to everyone in `location` except the users in `except`.
+    pub(crate) fn broadcast_speech(
+        &self,
+        location: &str,
+        kind: SpeechKind,
+        msg: &str,
+        except: &[SocketAddr],
+    ) {
+        let res = Response::speech(kind, msg);
+        for (addr, _) in self.users_in_location(location) {
+            if !except.contains(addr) {
+                self.send_to(addr, res.clone());
+            }
+        }
+    }
+
+    /// `say <message>`
+    pub fn say(&mut self, addr: &SocketAddr, args: &[&str]) -> Result<(), String> {
+        if args.is_empty() {
+            return Err("Say what?".to_string());
+        }
+        let msg = args.join(" ");
+        let session = self.session(addr)?;
+        let location = session.location.clone();
+        let name = session.name.clone();
+
+        self.send_to(
+            addr,
+            Response::speech(SpeechKind::Say, &format!("You say, '{msg}'")),
+        );
+        self.broadcast_speech(
+            &location,
+            SpeechKind::Say,
+            &format!("{name} says, '{msg}'"),
+            &[*addr],
+        );
+        self.fire_say(addr, &msg);
+        Ok(())
+    }
+
+    /// `emote <action>`: shows everyone here "<name> <action>".
+    pub fn emote(&self, addr: &SocketAddr, args: &[&str]) -> Result<(), String> {
+        if args.is_empty() {
+            return Err("Emote what?".to_string());
+        }
+        let session = self.session(addr)?;
+        let line = emote_line(&session.name, &args.join(" "));
+        self.broadcast_speech(&session.location, SpeechKind::Emote, &line, &[]);
+        Ok(())
+    }
+
+    /// `whisper <player> <message>`: only they hear it, but everyone here sees it.
+    pub fn whisper(&self, addr: &SocketAddr, args: &[&str]) -> Result<(), String> {
+        let (target, words) = args.split_first().ok_or("Whisper to whom?")?;
+        if words.is_empty() {
+            return Err("Whisper what?".to_string());
+        }
+        let msg = words.join(" ");
+        let session = self.session(addr)?;
+        let other = self
+            .find_user_in_location(&session.location, target)
+            .ok_or(format!("You don't see {target} here."))?;
+        if other == *addr {
+            return Err("You can't whisper to yourself.".to_string());
+        }
+        let other_name = &self.session(&other)?.name;
+
+        self.send_to(
+            addr,
+            Response::speech(
+                SpeechKind::Whisper,
+                &format!("You whisper to {other_name}, '{msg}'"),
+            ),
+        );
+        self.send_to(
+            &other,
+            Response::speech(
+                SpeechKind::Whisper,
+                &format!("{} whispers to you, '{msg}'", session.name),
+            ),
+        );
+        self.broadcast_speech(
+            &session.location,
+            SpeechKind::Whisper,
+            &format!("{} whispers something to {other_name}.", session.name),
+            &[*addr, other],
+        );
+        Ok(())
+    }
+
+    /// `shout <message>`: heard by everyone in the same area.
+    pub fn shout(&self, addr: &SocketAddr, args: &[&str]) -> Result<(), String> {
+        if args.is_empty() {
+            return Err("Shout what?".to_string());
+        }
+        let msg = args.join(" ");
+        let session = self.session(addr)?;
+        let area = &self.world.locations[&session.location].area;
+
+        let heard = Response::speech(
+            SpeechKind::Shout,
+            &format!("{} shouts, '{msg}'", session.name),
+        );
+        for (other, listener) in &self.peers {
+            if other != addr && self.world.locations[&listener.location].area == *area {
+                self.send_to(other, heard.clone());
+            }
+        }
+        self.send_to(
+            addr,
+            Response::speech(SpeechKind::Shout, &format!("You shout, '{msg}'")),
+        );
+        Ok(())
+    }
+
+    /// `yell <message>`: heard here and through every exit anyone can see.
+    pub fn yell(&self, addr: &SocketAddr, args: &[&str]) -> Result<(), String> {
+        if args.is_empty() {
+            return Err("Yell what?".to_string());
+        }
+        let msg = args.join(" ");
+        let session = self.session(addr)?;
+        let here = &self.world.locations[&session.location];
+
+        // each neighbour hears it once, from the way that leads back here
+        let mut nearby = BTreeMap::<LocationId, Option<Direction>>::new();
+        for (dir, exit) in &here.exits {
+            if here.hidden.contains(dir) || exit.to == here.id {
+                continue;
+            }
+            let from = self
+                .world
+                .reverse_exit(&here.id, *dir)
+                .map(|(_, back)| back);
+            let entry = nearby.entry(exit.to.clone()).or_insert(from);
+            if entry.is_none() {
+                *entry = from;
+            }
+        }
+
+        self.send_to(
+            addr,
+            Response::speech(SpeechKind::Yell, &format!("You yell, '{msg}'")),
+        );
+        self.broadcast_speech(
+            &here.id,
+            SpeechKind::Yell,
+            &format!("{} yells, '{msg}'", session.name),
+            &[*addr],
+        );
+        for (location, from) in nearby {
+            let from = from.map_or("from somewhere nearby", Direction::heard_from);
+            self.broadcast_speech(
+                &location,
+                SpeechKind::Yell,
+                &format!("You hear {} yell {from}, '{msg}'", session.name),
+                &[],
+            );
+        }
+        Ok(())
+    }
+}
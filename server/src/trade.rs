@@ -0,0 +1,417 @@
+use std::net::SocketAddr;
+
+use model::{GameUpdate, Response, TradeOfferView, TradeView};
+
+use crate::combat::Combatant;
+use crate::item::{ItemFlag, ItemId};
+use crate::Shared;
+
+/// What one player is putting into a trade.
+#[derive(Debug)]
+pub struct TradeSide {
+    pub addr: SocketAddr,
+    pub items: Vec<ItemId>,
+    /// What was inside the offered items when they were offered, so emptying a bag
+    /// afterwards can't slip past the other player.
+    pub contents: Vec<ItemId>,
+    pub gold: u64,
+    pub confirmed: bool,
+}
+
+impl TradeSide {
+    fn new(addr: SocketAddr) -> TradeSide {
+        TradeSide {
+            addr,
+            items: vec![],
+            contents: vec![],
+            gold: 0,
+            confirmed: false,
+        }
+    }
+}
+
+/// A trade between two players. Until the second player agrees it is only an invitation.
+#[derive(Debug)]
+pub struct Trade {
+    pub sides: [TradeSide; 2],
+    pub accepted: bool,
+}
+
+impl Trade {
+    fn involves(&self, addr: &SocketAddr) -> bool {
+        self.sides.iter().any(|side| side.addr == *addr)
+    }
+
+    /// The index of `addr`'s side.
+    fn side_of(&self, addr: &SocketAddr) -> usize {
+        if self.sides[0].addr == *addr {
+            0
+        } else {
+            1
+        }
+    }
+}
+
+impl Shared {
+    fn trade_index(&self, addr: &SocketAddr) -> Option<usize> {
+        self.trades
+            .iter()
+            .position(|trade| trade.accepted && trade.involves(addr))
+    }
+
+    fn active_trade(&self, addr: &SocketAddr) -> Result<usize, String> {
+        self.trade_index(addr)
+            .ok_or("You aren't trading with anyone.".to_string())
+    }
+
+    fn offer_view(&self, side: &TradeSide) -> TradeOfferView {
+        TradeOfferView {
+            items: side
+                .items
+                .iter()
+                .map(|id| self.world.item_view(*id))
+                .collect(),
+            gold: side.gold,
+            confirmed: side.confirmed,
+        }
+    }
+
+    /// Sends both players the current state of a trade.
+    fn send_trade(&self, index: usize) {
+        let trade = &self.trades[index];
+        for (mine, theirs) in [(0, 1), (1, 0)] {
+            let view = TradeView {
+                partner: self.peers[&trade.sides[theirs].addr].name.clone(),
+                yours: self.offer_view(&trade.sides[mine]),
+                theirs: self.offer_view(&trade.sides[theirs]),
+            };
+            self.send_to(
+                &trade.sides[mine].addr,
+                Response::Game(GameUpdate::Trade(Some(view))),
+            );
+        }
+    }
+
+    /// Ends a trade without exchanging anything, telling both players why.
+    fn close_trade(&mut self, index: usize, reason: &str) {
+        let trade = self.trades.remove(index);
+        for side in &trade.sides {
+            self.send_to(&side.addr, Response::game_msg(reason));
+            if trade.accepted {
+                self.send_to(&side.addr, Response::Game(GameUpdate::Trade(None)));
+            }
+        }
+    }
+
+    /// Cancels any trade or invitation involving `addr`, e.g. when they disconnect.
+    pub(crate) fn cancel_trades(&mut self, addr: &SocketAddr) {
+        while let Some(index) = self.trades.iter().position(|trade| trade.involves(addr)) {
+            let name = self.peers[addr].name.clone();
+            self.close_trade(index, &format!("The trade with {name} is off."));
+        }
+    }
+
+    /// `trade <player>`, `trade offer <item>|<amount> gold`, `trade remove <item>|gold`,
+    /// `trade confirm` or `trade cancel`
+    pub fn trade(&mut self, addr: &SocketAddr, args: &[&str]) -> Result<(), String> {
+        match args.first().copied() {
+            None => match self.trade_index(addr) {
+                Some(index) => {
+                    self.send_trade(index);
+                    Ok(())
+                }
+                None => Err("Trade with whom?".to_string()),
+            },
+            Some("offer") => self.trade_offer(addr, &args[1..]),
+            Some("remove") => self.trade_remove(addr, &args[1..]),
+            Some("confirm") | Some("accept") => self.trade_confirm(addr),
+            Some("cancel") => {
+                let index = self
+                    .trades
+                    .iter()
+                    .position(|trade| trade.involves(addr))
+                    .ok_or("You aren't trading with anyone.")?;
+                let name = self.session(addr)?.name.clone();
+                self.close_trade(index, &format!("{name} calls off the trade."));
+                Ok(())
+            }
+            Some(target) => self.trade_invite(addr, target),
+        }
+    }
+
+    fn trade_invite(&mut self, addr: &SocketAddr, target: &str) -> Result<(), String> {
+        if self
+            .trades
+            .iter()
+            .any(|trade| trade.accepted && trade.involves(addr))
+        {
+            return Err("You are already trading.".to_string());
+        }
+        if self.is_fighting(Combatant::Player(*addr)) {
+            return Err("You're a little busy for that!".to_string());
+        }
+
+        let session = self.session(addr)?;
+        let name = session.name.clone();
+        let other = self
+            .find_user_in_location(&session.location, target)
+            .ok_or(format!("You don't see {target} here."))?;
+        if other == *addr {
+            return Err("You can't trade with yourself.".to_string());
+        }
+        let other_name = self.peers[&other].name.clone();
+        if self.trade_index(&other).is_some() {
+            return Err(format!("{other_name} is busy trading with someone else."));
+        }
+
+        let invited = self.trades.iter().any(|trade| {
+            !trade.accepted && trade.sides[0].addr == other && trade.sides[1].addr == *addr
+        });
+        // only one outstanding invitation at a time
+        self.trades
+            .retain(|trade| trade.accepted || trade.sides[0].addr != *addr);
+
+        if invited {
+            let index = self
+                .trades
+                .iter()
+                .position(|trade| trade.sides[0].addr == other && trade.sides[1].addr == *addr)
+                .unwrap();
+            self.trades[index].accepted = true;
+            self.send_to(
+                &other,
+                Response::game_msg(&format!("{name} agrees to trade with you.")),
+            );
+            self.send_to(
+                addr,
+                Response::game_msg(&format!("You begin trading with {other_name}.")),
+            );
+            self.send_trade(index);
+            return Ok(());
+        }
+
+        self.trades.push(Trade {
+            sides: [TradeSide::new(*addr), TradeSide::new(other)],
+            accepted: false,
+        });
+        self.send_to(
+            &other,
+            Response::game_msg(&format!(
+                "{name} wants to trade with you. Type 'trade {name}' to agree."
+            )),
+        );
+        self.send_to(
+            addr,
+            Response::game_msg(&format!("You offer to trade with {other_name}.")),
+        );
+        Ok(())
+    }
+
+    /// Any change to the offers means both players have to confirm again.
+    fn reset_confirmations(&mut self, index: usize) {
+        for side in &mut self.trades[index].sides {
+            side.confirmed = false;
+        }
+    }
+
+    /// Everything currently inside the items on one side of a trade, sorted.
+    fn offered_contents(&self, side: &TradeSide) -> Vec<ItemId> {
+        let mut contents = side
+            .items
+            .iter()
+            .flat_map(|id| self.world.nested_contents(*id))
+            .collect::<Vec<_>>();
+        contents.sort();
+        contents
+    }
+
+    /// Notes what's inside each side's offered items. Returns the name of anyone
+    /// whose offer changed since it was last noted.
+    fn update_contents(&mut self, index: usize) -> Option<String> {
+        let mut changed = None;
+        for side in 0..2 {
+            let contents = self.offered_contents(&self.trades[index].sides[side]);
+            let offer = &mut self.trades[index].sides[side];
+            if offer.contents != contents {
+                offer.contents = contents;
+                changed = Some(self.peers[&offer.addr].name.clone());
+            }
+        }
+        changed
+    }
+
+    fn trade_offer(&mut self, addr: &SocketAddr, args: &[&str]) -> Result<(), String> {
+        let index = self.active_trade(addr)?;
+        let side = self.trades[index].side_of(addr);
+        let session = self.session(addr)?;
+
+        match args {
+            [amount, "gold"] | [amount, "coins"] => {
+                let amount = amount
+                    .parse::<u64>()
+                    .map_err(|_| format!("{amount} isn't an amount of gold."))?;
+                let offered = self.trades[index].sides[side]
+                    .gold
+                    .checked_add(amount)
+                    .ok_or("You don't have that much gold.")?;
+                if offered > session.character.gold {
+                    return Err("You don't have that much gold.".to_string());
+                }
+                self.trades[index].sides[side].gold = offered;
+            }
+            [item_kw, ..] => {
+                let offered = &self.trades[index].sides[side].items;
+                let available = session
+                    .inventory
+                    .iter()
+                    .copied()
+                    .filter(|id| !offered.contains(id))
+                    .collect::<Vec<_>>();
+                let id = self
+                    .world
+                    .find_item(&available, item_kw)
+                    .ok_or(format!("You don't have a {item_kw} to offer."))?;
+                if std::iter::once(id)
+                    .chain(self.world.nested_contents(id))
+                    .any(|id| self.world.template(id).has_flag(ItemFlag::NoDrop))
+                {
+                    return Err("You can't let go of it!".to_string());
+                }
+                self.trades[index].sides[side].items.push(id);
+            }
+            [] => return Err("Offer what?".to_string()),
+        }
+
+        self.update_contents(index);
+        self.reset_confirmations(index);
+        self.send_trade(index);
+        Ok(())
+    }
+
+    fn trade_remove(&mut self, addr: &SocketAddr, args: &[&str]) -> Result<(), String> {
+        let index = self.active_trade(addr)?;
+        let side = self.trades[index].side_of(addr);
+        let offer = &mut self.trades[index].sides[side];
+
+        match args.first().copied() {
+            Some("gold") | Some("coins") => offer.gold = 0,
+            Some(item_kw) => {
+                let id = self
+                    .world
+                    .find_item(&offer.items, item_kw)
+                    .ok_or(format!("You aren't offering a {item_kw}."))?;
+                offer.items.retain(|i| *i != id);
+            }
+            None => return Err("Remove what?".to_string()),
+        }
+
+        self.update_contents(index);
+        self.reset_confirmations(index);
+        self.send_trade(index);
+        Ok(())
+    }
+
+    fn trade_confirm(&mut self, addr: &SocketAddr) -> Result<(), String> {
+        let index = self.active_trade(addr)?;
+        let side = self.trades[index].side_of(addr);
+        self.trades[index].sides[side].confirmed = true;
+
+        let trade = &self.trades[index];
+        if !trade.sides.iter().all(|side| side.confirmed) {
+            let name = self.session(addr)?.name.clone();
+            let other = trade.sides[1 - side].addr;
+            self.send_to(
+                &other,
+                Response::game_msg(&format!("{name} confirms the trade.")),
+            );
+            self.send_trade(index);
+            return Ok(());
+        }
+
+        if let Some(name) = self.update_contents(index) {
+            self.reset_confirmations(index);
+            for side in &self.trades[index].sides {
+                self.send_to(
+                    &side.addr,
+                    Response::game_msg(&format!(
+                        "{name}'s offer has changed. Look it over and confirm again."
+                    )),
+                );
+            }
+            self.send_trade(index);
+            return Ok(());
+        }
+        if let Err(reason) = self.check_trade(index) {
+            self.close_trade(index, &format!("The trade falls through: {reason}"));
+            return Ok(());
+        }
+        self.complete_trade(index)
+    }
+
+    /// Makes sure a trade can go ahead in full before anything changes hands.
+    fn check_trade(&self, index: usize) -> Result<(), String> {
+        let trade = &self.trades[index];
+        let [a, b] = &trade.sides;
+        let (Ok(first), Ok(second)) = (self.session(&a.addr), self.session(&b.addr)) else {
+            return Err("someone has left.".to_string());
+        };
+        if first.location != second.location {
+            return Err("you are no longer together.".to_string());
+        }
+
+        for (side, other) in [(a, b), (b, a)] {
+            let session = &self.peers[&side.addr];
+            if side.items.iter().any(|id| !session.inventory.contains(id)) {
+                return Err(format!(
+                    "{} no longer has everything offered.",
+                    session.name
+                ));
+            }
+            if side.gold > session.character.gold {
+                return Err(format!("{} can't cover the gold.", session.name));
+            }
+
+            // what they'll carry once their own items leave and the other side's arrive
+            let given = side
+                .items
+                .iter()
+                .map(|id| self.world.item_weight(*id))
+                .sum::<u32>();
+            let received = other
+                .items
+                .iter()
+                .map(|id| self.world.item_weight(*id))
+                .sum::<u32>();
+            let weight = self.carried_weight(&side.addr)? - given + received;
+            if weight > session.carry_capacity() {
+                return Err(format!("{} can't carry that much.", session.name));
+            }
+        }
+        Ok(())
+    }
+
+    /// Swaps everything offered. Only called once `check_trade` has passed, so every
+    /// step here succeeds.
+    fn complete_trade(&mut self, index: usize) -> Result<(), String> {
+        let trade = self.trades.remove(index);
+        let [a, b] = &trade.sides;
+
+        for (from, to) in [(a, b), (b, a)] {
+            let giver = self.peers.get_mut(&from.addr).unwrap();
+            giver.inventory.retain(|id| !from.items.contains(id));
+            giver.character.gold -= from.gold;
+
+            let receiver = self.peers.get_mut(&to.addr).unwrap();
+            receiver.inventory.extend(from.items.iter().copied());
+            receiver.character.gold += from.gold;
+        }
+
+        for side in &trade.sides {
+            self.send_to(&side.addr, Response::game_msg("The trade is done."));
+            self.send_to(&side.addr, Response::Game(GameUpdate::Trade(None)));
+            self.send_inventory(&side.addr)?;
+            self.save_character(&side.addr)?;
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,186 @@
+use std::collections::{HashMap, VecDeque};
+use std::net::SocketAddr;
+
+use model::Response;
+
+use crate::combat::Combatant;
+use crate::world::{Direction, Exit, Location, LocationId, World};
+use crate::Shared;
+
+impl World {
+    /// The shortest list of moves from `from` to `to` using only exits `passable`
+    /// allows, or `None` if there's no way.
+    pub fn find_path(
+        &self,
+        from: &str,
+        to: &str,
+        passable: impl Fn(&Location, Direction, &Exit) -> bool,
+    ) -> Option<Vec<Direction>> {
+        let mut came_from = HashMap::<LocationId, (LocationId, Direction)>::new();
+        let mut queue = VecDeque::from([from.to_string()]);
+
+        while let Some(id) = queue.pop_front() {
+            if id == to {
+                let mut path = vec![];
+                let mut at = id;
+                while let Some((prev, dir)) = came_from.get(&at) {
+                    path.push(*dir);
+                    at = prev.clone();
+                }
+                path.reverse();
+                return Some(path);
+            }
+            let here = &self.locations[&id];
+            for (dir, exit) in &here.exits {
+                if exit.to == from || came_from.contains_key(&exit.to) {
+                    continue;
+                }
+                if passable(here, *dir, exit) {
+                    came_from.insert(exit.to.clone(), (id.clone(), *dir));
+                    queue.push_back(exit.to.clone());
+                }
+            }
+        }
+        None
+    }
+
+    /// Finds a landmark whose name contains `name`, ignoring case.
+    fn find_landmark(&self, name: &str) -> Option<&Location> {
+        let name = name.to_lowercase();
+        let mut landmarks = self
+            .locations
+            .values()
+            .filter(|location| location.landmark)
+            .collect::<Vec<_>>();
+        landmarks.sort_by(|a, b| a.id.cmp(&b.id));
+        landmarks
+            .into_iter()
+            .find(|location| location.name.to_lowercase().contains(&name))
+    }
+}
+
+impl Shared {
+    /// The way a player can walk from where they are to `to`. Hidden exits, locked
+    /// doors, guards and areas closed to travel are avoided. Closed doors are fine, as
+    /// they're opened on the way.
+    fn path_for(&self, addr: &SocketAddr, to: &str) -> Option<Vec<Direction>> {
+        let start = &self.session(addr).ok()?.location;
+        let start_area = &self.world.locations[start].area;
+        self.world.find_path(start, to, |here, dir, exit| {
+            if here.hidden.contains(&dir)
+                || here.doors.get(&dir).is_some_and(|door| door.locked)
+                || self.blocking_guard(&here.id, dir).is_some()
+            {
+                return false;
+            }
+            let area = &self.world.locations[&exit.to].area;
+            let source = &self.world.areas[area].source;
+            area == start_area || !(source.no_travel || source.instanced)
+        })
+    }
+
+    /// Sets off walking to `to`, a step each tick.
+    fn start_walk(&mut self, addr: &SocketAddr, to: LocationId) -> Result<(), String> {
+        let session = self.session(addr)?;
+        if session.location == to {
+            return Err("You're already there.".to_string());
+        }
+        if self.is_fighting(Combatant::Player(*addr)) {
+            return Err("You're fighting for your life! Try to flee.".to_string());
+        }
+        self.path_for(addr, &to)
+            .ok_or("You can't find a way there.".to_string())?;
+
+        let msg = format!("You set off towards {}.", self.world.locations[&to].name);
+        self.peers.get_mut(addr).unwrap().walk = Some(to);
+        self.send_to(addr, Response::game_msg(&msg));
+        Ok(())
+    }
+
+    /// Stops a player walking, telling them if they were.
+    pub(crate) fn stop_walking(&mut self, addr: &SocketAddr, msg: &str) {
+        if let Some(session) = self.peers.get_mut(addr) {
+            if session.walk.take().is_some() {
+                self.send_to(addr, Response::game_msg(msg));
+            }
+        }
+    }
+
+    /// `travel [landmark]`
+    pub fn travel(&mut self, addr: &SocketAddr, args: &[&str]) -> Result<(), String> {
+        if args.is_empty() {
+            let mut landmarks = self
+                .world
+                .locations
+                .values()
+                .filter(|location| location.landmark)
+                .map(|location| location.name.clone())
+                .collect::<Vec<_>>();
+            landmarks.sort();
+            let msg = format!("You know the way to: {}", landmarks.join(", "));
+            self.send_to(addr, Response::game_msg(&msg));
+            return Ok(());
+        }
+        let name = args.join(" ");
+        let to = self
+            .world
+            .find_landmark(&name)
+            .ok_or(format!("You don't know of anywhere called {name}."))?
+            .id
+            .clone();
+        self.start_walk(addr, to)
+    }
+
+    /// `walk <location id>`
+    pub fn walk(&mut self, addr: &SocketAddr, args: &[&str]) -> Result<(), String> {
+        let id = args.first().ok_or("Walk where?")?;
+        if !self.world.locations.contains_key(*id) {
+            return Err(format!("There's no location {id}."));
+        }
+        self.start_walk(addr, id.to_string())
+    }
+
+    /// Takes everyone who is walking somewhere one step closer.
+    pub(crate) fn travel_tick(&mut self) {
+        let mut walkers = self
+            .peers
+            .iter()
+            .filter_map(|(addr, session)| Some((*addr, session.walk.clone()?)))
+            .collect::<Vec<_>>();
+        walkers.sort();
+
+        for (addr, to) in walkers {
+            if self.is_fighting(Combatant::Player(addr)) {
+                self.stop_walking(&addr, "You stop travelling to fight.");
+                continue;
+            }
+            let Some(dir) = self
+                .path_for(&addr, &to)
+                .and_then(|path| path.first().copied())
+            else {
+                self.stop_walking(&addr, "You can't find a way there any more.");
+                continue;
+            };
+
+            let here = self.peers[&addr].location.clone();
+            let closed = self.world.locations[&here]
+                .doors
+                .get(&dir)
+                .is_some_and(|door| door.closed);
+            if closed {
+                if let Err(e) = self.open_door(&addr, &[dir.name()]) {
+                    self.stop_walking(&addr, &e);
+                    continue;
+                }
+            }
+            if let Err(e) = self.move_user(&addr, dir) {
+                self.stop_walking(&addr, &e);
+                continue;
+            }
+            if self.peers[&addr].location == to {
+                let name = self.world.locations[&to].name.clone();
+                self.stop_walking(&addr, &format!("You have arrived at {name}."));
+            }
+        }
+    }
+}
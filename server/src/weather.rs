@@ -0,0 +1,156 @@
+use std::collections::{BTreeMap, BTreeSet};
+
+use rand::seq::SliceRandom;
+use rand::Rng;
+use serde::Deserialize;
+
+use crate::world::World;
+use crate::Shared;
+
+/// A kind of weather from `weather.ron`.
+#[derive(Deserialize, Clone, Debug)]
+pub struct WeatherDef {
+    pub id: String,
+    /// Shown outdoors after the location description, e.g. "Rain patters down."
+    pub description: String,
+    /// Shown outdoors when this weather sets in.
+    pub start: String,
+    /// How much it dims the daylight.
+    #[serde(default)]
+    pub darkness: i32,
+    /// Ticks it lasts before it may change.
+    pub ticks: u32,
+    /// Weights of the weather it can turn into.
+    pub next: BTreeMap<String, u32>,
+    /// Flavour messages shown outdoors now and then.
+    #[serde(default)]
+    pub ambient: Vec<String>,
+}
+
+impl World {
+    /// Checks that weather only refers to weather that exists, and that ambient
+    /// messages have a chance between 0 and 1.
+    pub(crate) fn validate_weather(&self) -> Result<(), String> {
+        if !(0.0..=1.0).contains(&self.config.ambient_chance) {
+            return Err("The ambient chance must be between 0 and 1".to_string());
+        }
+        for weather in self.weather.values() {
+            if let Some(next) = weather
+                .next
+                .keys()
+                .find(|next| !self.weather.contains_key(*next))
+            {
+                return Err(format!(
+                    "Weather {} turns into unknown weather {next}",
+                    weather.id
+                ));
+            }
+        }
+        for area in self.areas.values() {
+            if let Some(weather) = area
+                .weather
+                .as_ref()
+                .filter(|weather| !self.weather.contains_key(*weather))
+            {
+                return Err(format!("Area {} has unknown weather {weather}", area.id));
+            }
+        }
+        Ok(())
+    }
+
+    pub fn area_weather(&self, area: &str) -> Option<&WeatherDef> {
+        self.areas[area]
+            .weather
+            .as_ref()
+            .map(|weather| &self.weather[weather])
+    }
+}
+
+impl Shared {
+    /// Sends a message to everyone outdoors, either everywhere or in one area.
+    pub(crate) fn broadcast_outdoors(&self, area: Option<&str>, msg: &str) {
+        let locations =
+            self.world.locations.values().filter(|location| {
+                location.outdoors && area.is_none_or(|area| location.area == area)
+            });
+        for location in locations {
+            self.broadcast_location(&location.id, msg, &[]);
+        }
+    }
+
+    /// The time of day and the weather, if `location` is outdoors.
+    pub(crate) fn describe_sky(&self, location: &str) -> Option<String> {
+        let location = &self.world.locations[location];
+        if !location.outdoors {
+            return None;
+        }
+        let mut sky = format!("It is {}.", self.clock.period().name());
+        if let Some(weather) = self.world.area_weather(&location.area) {
+            sky.push_str(&format!(" {}", weather.description));
+        }
+        Some(sky)
+    }
+
+    /// Moves each area's weather along and plays ambient messages outdoors.
+    pub(crate) fn weather_tick(&mut self) {
+        let mut areas = self.world.areas.keys().cloned().collect::<Vec<_>>();
+        areas.sort();
+
+        for id in areas {
+            let Some(current) = self.world.area_weather(&id).cloned() else {
+                continue;
+            };
+            let area = self.world.areas.get_mut(&id).unwrap();
+            area.weather_timer += 1;
+            if area.weather_timer < current.ticks {
+                continue;
+            }
+            area.weather_timer = 0;
+
+            let total = current.next.values().sum::<u32>();
+            if total == 0 {
+                continue;
+            }
+            let mut pick = self.rng.gen_range(0..total);
+            let Some(next) = current.next.iter().find_map(|(next, weight)| {
+                if pick < *weight {
+                    return Some(next.clone());
+                }
+                pick -= weight;
+                None
+            }) else {
+                continue;
+            };
+            if next != current.id {
+                let start = self.world.weather[&next].start.clone();
+                self.world.areas.get_mut(&id).unwrap().weather = Some(next);
+                self.broadcast_outdoors(Some(&id), &start);
+            }
+        }
+
+        let chance = self.world.config.ambient_chance;
+        if chance <= 0.0 {
+            return;
+        }
+        let occupied = self
+            .peers
+            .values()
+            .map(|session| session.location.clone())
+            .collect::<BTreeSet<_>>();
+        for location in occupied {
+            let location = &self.world.locations[&location];
+            if !location.outdoors || !self.rng.gen_bool(chance) {
+                continue;
+            }
+            let weather = self.world.area_weather(&location.area);
+            let lines = self.world.areas[&location.area]
+                .ambient
+                .iter()
+                .chain(weather.into_iter().flat_map(|weather| &weather.ambient))
+                .collect::<Vec<_>>();
+            if let Some(line) = lines.choose(&mut self.rng) {
+                self.broadcast_location(&location.id, line, &[]);
+            }
+        }
+    }
+}
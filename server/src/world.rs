@@ -0,0 +1,630 @@
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+use std::error::Error;
+use std::fs;
+use std::net::SocketAddr;
+use std::path::{Path, PathBuf};
+
+use model::Response;
+use serde::{Deserialize, Serialize};
+
+use crate::character::CharacterRules;
+use crate::clock::{ClockConfig, Light};
+use crate::combat::{CombatRules, Combatant};
+use crate::crafting::CraftingRules;
+use crate::dialogue::DialogueTree;
+use crate::door::{DoorDef, DoorState};
+use crate::faction::FactionRules;
+use crate::item::{Item, ItemId, ItemTemplate};
+use crate::loot::LootTable;
+use crate::npc::{Npc, NpcId, NpcTemplate};
+use crate::pvp::Zone;
+use crate::quest::QuestDef;
+use crate::reset::{self, SpawnRule};
+use crate::script::{ScriptLimits, Scripts};
+use crate::shop::EconomyRules;
+use crate::skill::SkillDef;
+use crate::social::SocialDef;
+use crate::weather::WeatherDef;
+use crate::Shared;
+
+pub type LocationId = String;
+
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub enum Direction {
+    North,
+    East,
+    South,
+    West,
+    Up,
+    Down,
+}
+
+impl Direction {
+    pub fn parse(s: &str) -> Option<Direction> {
+        match s.to_lowercase().as_str() {
+            "n" | "north" => Some(Direction::North),
+            "e" | "east" => Some(Direction::East),
+            "s" | "south" => Some(Direction::South),
+            "w" | "west" => Some(Direction::West),
+            "u" | "up" => Some(Direction::Up),
+            "d" | "down" => Some(Direction::Down),
+            _ => None,
+        }
+    }
+
+    pub fn opposite(self) -> Direction {
+        match self {
+            Direction::North => Direction::South,
+            Direction::East => Direction::West,
+            Direction::South => Direction::North,
+            Direction::West => Direction::East,
+            Direction::Up => Direction::Down,
+            Direction::Down => Direction::Up,
+        }
+    }
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Direction::North => "north",
+            Direction::East => "east",
+            Direction::South => "south",
+            Direction::West => "west",
+            Direction::Up => "up",
+            Direction::Down => "down",
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct Exit {
+    pub to: LocationId,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub door: Option<DoorDef>,
+    /// Difficulty of finding this exit with `search`. It can't be seen or used until
+    /// someone does.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub hidden: Option<i32>,
+}
+
+/// A location as written in an area file.
+#[derive(Serialize, Deserialize, Clone, Debug, Default)]
+pub struct LocationDef {
+    pub id: LocationId,
+    pub name: String,
+    pub description: String,
+    /// Open to the sky, so lit by daylight and touched by the weather.
+    #[serde(default, skip_serializing_if = "is_default")]
+    pub outdoors: bool,
+    /// Can't be seen in without a light source.
+    #[serde(default, skip_serializing_if = "is_default")]
+    pub dark: bool,
+    /// Can be found with `travel`.
+    #[serde(default, skip_serializing_if = "is_default")]
+    pub landmark: bool,
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub exits: BTreeMap<Direction, Exit>,
+    /// Item templates kept on the floor, respawning whenever the area resets.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub items: Vec<String>,
+    /// NPC templates kept here, respawning whenever the area resets.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub npcs: Vec<String>,
+    /// Name of a script in `data/scripts` whose hooks run for this location.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub script: Option<String>,
+}
+
+/// The contents of one file in `data/areas`.
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct AreaDef {
+    pub id: String,
+    pub name: String,
+    /// Game ticks between putting the area back the way this file has it.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub reset_ticks: Option<u32>,
+    /// Holds off resetting while any players are in the area.
+    #[serde(default, skip_serializing_if = "is_default")]
+    pub reset_when_empty: bool,
+    /// The weather the area starts with. Areas without any never change.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub weather: Option<String>,
+    /// Flavour messages shown outdoors now and then, whatever the weather.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub ambient: Vec<String>,
+    /// Where players who die here wake up, instead of the combat rules' recall location.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub recall: Option<LocationId>,
+    /// Who may fight whom here.
+    #[serde(default, skip_serializing_if = "is_default")]
+    pub zone: Zone,
+    /// Keeps `travel` and `walk` from leading players in here from elsewhere.
+    #[serde(default, skip_serializing_if = "is_default")]
+    pub no_travel: bool,
+    /// Gives each party that enters a private copy of the area.
+    #[serde(default, skip_serializing_if = "is_default")]
+    pub instanced: bool,
+    /// Game ticks before a copy of an instanced area closes, even with players in it.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub instance_ticks: Option<u32>,
+    /// NPCs and items to respawn beyond those listed in each location.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub spawns: Vec<SpawnRule>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub locations: Vec<LocationDef>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub npcs: Vec<NpcTemplate>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub items: Vec<ItemTemplate>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub quests: Vec<QuestDef>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub dialogues: Vec<DialogueTree>,
+}
+
+#[derive(Deserialize, Debug)]
+pub struct WorldConfig {
+    pub start_location: LocationId,
+    /// Milliseconds between game ticks.
+    pub tick_ms: u64,
+    /// Where player save files are kept, relative to the data directory.
+    pub save_dir: String,
+    pub script_limits: ScriptLimits,
+    /// Seeds the game's random numbers so runs can be repeated. Random if unset.
+    #[serde(default)]
+    pub rng_seed: Option<u64>,
+    #[serde(default)]
+    pub clock: ClockConfig,
+    /// Chance each tick of an ambient message in each occupied outdoor location.
+    #[serde(default)]
+    pub ambient_chance: f64,
+}
+
+#[derive(Clone)]
+pub struct Area {
+    pub id: String,
+    pub name: String,
+    pub reset_ticks: Option<u32>,
+    /// Ticks since the area last reset.
+    pub reset_timer: u32,
+    pub reset_when_empty: bool,
+    /// Every spawn rule, including those from location `items` and `npcs` lists.
+    pub spawns: Vec<SpawnRule>,
+    /// The current weather.
+    pub weather: Option<String>,
+    /// Ticks since the weather last changed.
+    pub weather_timer: u32,
+    pub ambient: Vec<String>,
+    /// The file the area was loaded from.
+    pub path: PathBuf,
+    /// The area as its file has it, kept up to date by builders' edits.
+    pub source: AreaDef,
+}
+
+#[derive(Clone)]
+pub struct Location {
+    pub id: LocationId,
+    pub area: String,
+    pub name: String,
+    pub description: String,
+    pub exits: BTreeMap<Direction, Exit>,
+    /// Items lying on the floor.
+    pub items: Vec<ItemId>,
+    pub script: Option<String>,
+    /// The current state of each exit's door.
+    pub doors: BTreeMap<Direction, DoorState>,
+    /// Hidden exits nobody has found since the area last reset.
+    pub hidden: BTreeSet<Direction>,
+    pub outdoors: bool,
+    pub dark: bool,
+    pub landmark: bool,
+}
+
+pub struct World {
+    pub data_dir: PathBuf,
+    pub config: WorldConfig,
+    pub combat: CombatRules,
+    pub character_rules: CharacterRules,
+    pub economy: EconomyRules,
+    pub crafting: CraftingRules,
+    pub factions: FactionRules,
+    pub skills: Vec<SkillDef>,
+    pub loot_tables: HashMap<String, LootTable>,
+    pub weather: HashMap<String, WeatherDef>,
+    pub socials: HashMap<String, SocialDef>,
+    pub areas: HashMap<String, Area>,
+    pub locations: HashMap<LocationId, Location>,
+    pub item_templates: HashMap<String, ItemTemplate>,
+    pub items: HashMap<ItemId, Item>,
+    pub(crate) next_item_id: u64,
+    pub npc_templates: HashMap<String, NpcTemplate>,
+    pub npcs: HashMap<NpcId, Npc>,
+    pub(crate) next_npc_id: u64,
+    pub quests: HashMap<String, QuestDef>,
+    pub dialogues: HashMap<String, DialogueTree>,
+    pub scripts: Scripts,
+}
+
+impl World {
+    /// Loads `config.ron`, every area file in `areas/` and every script in `scripts/`
+    /// from the data directory.
+    pub fn load(dir: &Path) -> Result<World, Box<dyn Error>> {
+        let config: WorldConfig = read_ron(&dir.join("config.ron"))?;
+        let combat: CombatRules = read_ron(&dir.join("combat.ron"))?;
+        let character_rules: CharacterRules = read_ron(&dir.join("characters.ron"))?;
+        let economy: EconomyRules = read_ron(&dir.join("economy.ron"))?;
+        let crafting: CraftingRules = read_ron(&dir.join("crafting.ron"))?;
+        let factions: FactionRules = read_ron(&dir.join("factions.ron"))?;
+        let skills: Vec<SkillDef> = read_ron(&dir.join("skills.ron"))?;
+        let loot_tables: Vec<LootTable> = read_ron(&dir.join("loot.ron"))?;
+        let weather: Vec<WeatherDef> = read_ron(&dir.join("weather.ron"))?;
+        let socials: Vec<SocialDef> = read_ron(&dir.join("socials.ron"))?;
+        let scripts = Scripts::load(&dir.join("scripts"), config.script_limits.clone())?;
+
+        let mut area_defs = vec![];
+        let mut paths = fs::read_dir(dir.join("areas"))?
+            .filter_map(|entry| entry.ok().map(|e| e.path()))
+            .filter(|path| path.extension().is_some_and(|ext| ext == "ron"))
+            .collect::<Vec<_>>();
+        paths.sort();
+        for path in paths {
+            let area = read_ron::<AreaDef>(&path)?;
+            area_defs.push((path, area));
+        }
+
+        let mut world = World {
+            data_dir: dir.to_path_buf(),
+            config,
+            combat,
+            character_rules,
+            economy,
+            crafting,
+            factions,
+            skills,
+            loot_tables: loot_tables
+                .into_iter()
+                .map(|table| (table.id.clone(), table))
+                .collect(),
+            weather: weather
+                .into_iter()
+                .map(|weather| (weather.id.clone(), weather))
+                .collect(),
+            socials: socials
+                .into_iter()
+                .map(|social| (social.id.clone(), social))
+                .collect(),
+            areas: HashMap::new(),
+            locations: HashMap::new(),
+            item_templates: HashMap::new(),
+            items: HashMap::new(),
+            next_item_id: 0,
+            npc_templates: HashMap::new(),
+            npcs: HashMap::new(),
+            next_npc_id: 0,
+            quests: HashMap::new(),
+            dialogues: HashMap::new(),
+            scripts,
+        };
+
+        for (_, area) in &area_defs {
+            for template in &area.items {
+                world
+                    .item_templates
+                    .insert(template.id.clone(), template.clone());
+            }
+            for template in &area.npcs {
+                world
+                    .npc_templates
+                    .insert(template.id.clone(), template.clone());
+            }
+            for quest in &area.quests {
+                world.quests.insert(quest.id.clone(), quest.clone());
+            }
+            for tree in &area.dialogues {
+                world.dialogues.insert(tree.id.clone(), tree.clone());
+            }
+        }
+
+        for (path, area) in area_defs {
+            let source = area.clone();
+            let mut spawns = vec![];
+            for def in area.locations {
+                if world.locations.contains_key(&def.id) {
+                    return Err(format!("Duplicate location {}", def.id).into());
+                }
+                spawns.extend(reset::location_spawns(&def));
+
+                world.locations.insert(
+                    def.id.clone(),
+                    Location {
+                        id: def.id,
+                        area: area.id.clone(),
+                        name: def.name,
+                        description: def.description,
+                        exits: def.exits,
+                        items: vec![],
+                        script: def.script,
+                        doors: BTreeMap::new(),
+                        hidden: BTreeSet::new(),
+                        outdoors: def.outdoors,
+                        dark: def.dark,
+                        landmark: def.landmark,
+                    },
+                );
+            }
+            world.areas.insert(
+                area.id.clone(),
+                Area {
+                    id: area.id,
+                    name: area.name,
+                    reset_ticks: area.reset_ticks,
+                    reset_timer: 0,
+                    reset_when_empty: area.reset_when_empty,
+                    spawns: spawns.into_iter().chain(area.spawns).collect(),
+                    weather: area.weather,
+                    weather_timer: 0,
+                    ambient: area.ambient,
+                    path,
+                    source,
+                },
+            );
+        }
+
+        for location in world.locations.values() {
+            for (dir, exit) in &location.exits {
+                if !world.locations.contains_key(&exit.to) {
+                    return Err(format!(
+                        "Exit {} from {} leads to unknown location {}",
+                        dir.name(),
+                        location.id,
+                        exit.to
+                    )
+                    .into());
+                }
+            }
+        }
+
+        world.link_doors()?;
+        world.combat.validate()?;
+        world.validate_npcs()?;
+        world.validate_spawns()?;
+        world.validate_quests()?;
+        world.validate_dialogues()?;
+        world.validate_shops()?;
+        world.validate_skills()?;
+        world.validate_loot()?;
+        world.validate_weather()?;
+        world.validate_socials()?;
+        world.validate_death()?;
+        world.validate_crafting()?;
+        world.validate_factions()?;
+
+        let scripts = world
+            .locations
+            .values()
+            .filter_map(|location| location.script.as_ref())
+            .chain(
+                world
+                    .item_templates
+                    .values()
+                    .filter_map(|t| t.script.as_ref()),
+            )
+            .chain(
+                world
+                    .npc_templates
+                    .values()
+                    .filter_map(|t| t.script.as_ref()),
+            );
+        for script in scripts {
+            if !world.scripts.contains(script) {
+                return Err(format!("Unknown script {script}").into());
+            }
+        }
+
+        if !world.locations.contains_key(&world.config.start_location) {
+            return Err(format!(
+                "Start location {} does not exist",
+                world.config.start_location
+            )
+            .into());
+        }
+
+        if !world.locations.contains_key(&world.combat.recall_location) {
+            return Err(format!(
+                "Recall location {} does not exist",
+                world.combat.recall_location
+            )
+            .into());
+        }
+
+        let mut areas = world.areas.keys().cloned().collect::<Vec<_>>();
+        areas.sort();
+        for area in &areas {
+            world.reset_area(area)?;
+        }
+
+        Ok(world)
+    }
+}
+
+pub(crate) fn read_ron<T: serde::de::DeserializeOwned>(path: &Path) -> Result<T, Box<dyn Error>> {
+    let contents = fs::read_to_string(path).map_err(|e| format!("{}: {e}", path.display()))?;
+    ron::from_str(&contents).map_err(|e| format!("{}: {e}", path.display()).into())
+}
+
+/// Lets area files leave out fields that are at their defaults.
+pub(crate) fn is_default<T: Default + PartialEq>(value: &T) -> bool {
+    *value == T::default()
+}
+
+pub(crate) fn write_ron<T: Serialize>(path: &Path, value: &T) -> Result<(), String> {
+    let config = ron::ser::PrettyConfig::default().depth_limit(4);
+    let contents = ron::ser::to_string_pretty(value, config).map_err(|e| e.to_string())?;
+    fs::write(path, contents).map_err(|e| format!("{}: {e}", path.display()))
+}
+
+impl Shared {
+    pub(crate) fn users_in_location<'a>(
+        &'a self,
+        location: &'a str,
+    ) -> impl Iterator<Item = (&'a SocketAddr, &'a crate::UserSession)> + 'a {
+        self.peers
+            .iter()
+            .filter(move |(_, session)| session.location == location)
+    }
+
+    /// Finds a user in `location` whose name starts with `name`, ignoring case.
+    pub(crate) fn find_user_in_location(&self, location: &str, name: &str) -> Option<SocketAddr> {
+        let name = name.to_lowercase();
+        self.users_in_location(location)
+            .find(|(_, session)| session.name.to_lowercase().starts_with(&name))
+            .map(|(addr, _)| *addr)
+    }
+
+    /// Sends a narrative message to everyone in `location` except the users in `except`.
+    pub(crate) fn broadcast_location(&self, location: &str, msg: &str, except: &[SocketAddr]) {
+        let res = Response::game_msg(msg);
+        for (addr, session) in self.users_in_location(location) {
+            if !except.contains(addr) {
+                let _ = session.send.send(res.clone());
+            }
+        }
+    }
+
+    pub(crate) fn describe_location(&self, location: &Location, viewer: &SocketAddr) -> String {
+        let light = self.light_level(&location.id);
+        if light == Light::Dark {
+            return "It is pitch black. You can't see a thing.".to_string();
+        }
+
+        let mut desc = format!("{}\n{}", location.name, location.description);
+        if let Some(sky) = self.describe_sky(&location.id) {
+            desc.push_str(&format!("\n{sky}"));
+        }
+
+        let exits = location
+            .exits
+            .iter()
+            .filter(|(dir, _)| !location.hidden.contains(dir))
+            .map(|(dir, exit)| match (&exit.door, location.doors.get(dir)) {
+                (Some(door), Some(state)) if state.closed => {
+                    format!("{} (closed {})", dir.name(), door.name)
+                }
+                _ => dir.name().to_string(),
+            })
+            .collect::<Vec<_>>();
+        if exits.is_empty() {
+            desc.push_str("\nThere are no obvious exits.");
+        } else {
+            desc.push_str(&format!("\nExits: {}", exits.join(", ")));
+        }
+
+        if light == Light::Dim {
+            desc.push_str("\nIt's too dim to make out what's on the ground.");
+        } else if !location.items.is_empty() {
+            let items = location
+                .items
+                .iter()
+                .map(|id| self.world.item_name(*id))
+                .collect::<Vec<_>>();
+            desc.push_str(&format!("\nYou see here: {}.", items.join(", ")));
+        }
+
+        let mut npcs = self
+            .world
+            .npcs_in_location(&location.id)
+            .collect::<Vec<_>>();
+        npcs.sort_by_key(|npc| npc.id);
+        for npc in npcs {
+            desc.push_str(&format!("\n{}", self.world.npc_template(npc.id).long));
+        }
+
+        let others = self
+            .users_in_location(&location.id)
+            .filter(|(addr, _)| *addr != viewer)
+            .map(|(_, session)| session.name.as_str())
+            .collect::<Vec<_>>();
+        if !others.is_empty() {
+            desc.push_str(&format!("\nAlso here: {}.", others.join(", ")));
+        }
+
+        desc
+    }
+
+    pub fn look(&mut self, addr: &SocketAddr, args: &[&str]) -> Result<(), String> {
+        let session = self.session(addr)?;
+        let location = &self.world.locations[&session.location];
+
+        let Some(target) = args.last() else {
+            self.send_to(
+                addr,
+                Response::game_msg(&self.describe_location(location, addr)),
+            );
+            self.send_map(addr);
+            return Ok(());
+        };
+        if self.light_level(&location.id) == Light::Dark {
+            return Err("It's too dark to see.".to_string());
+        }
+
+        if let Some(npc) = self.world.find_npc(&location.id, target) {
+            let template = self.world.npc_template(npc);
+            let mut desc = if template.description.is_empty() {
+                format!("You see nothing special about {}.", template.name)
+            } else {
+                template.description.clone()
+            };
+            let equipment = &self.world.npcs[&npc].equipment;
+            if !equipment.is_empty() {
+                let items = equipment
+                    .iter()
+                    .map(|id| self.world.item_name(*id))
+                    .collect::<Vec<_>>();
+                desc.push_str(&format!("\nEquipped with: {}.", items.join(", ")));
+            }
+            self.send_to(addr, Response::game_msg(&desc));
+            return Ok(());
+        }
+
+        let id = self
+            .world
+            .find_item(&session.inventory, target)
+            .or_else(|| self.world.find_item(&session.equipment_ids(), target))
+            .or_else(|| self.world.find_item(&location.items, target))
+            .ok_or(format!("You don't see a {target} here."))?;
+
+        self.open_container(addr, id);
+        self.send_to(addr, Response::game_msg(&self.world.describe_item(id)));
+        Ok(())
+    }
+
+    pub fn move_user(&mut self, addr: &SocketAddr, dir: Direction) -> Result<(), String> {
+        if self.is_fighting(Combatant::Player(*addr)) {
+            return Err("You're fighting for your life! Try to flee.".to_string());
+        }
+
+        let session = self.session(addr)?;
+        let from = session.location.clone();
+        let name = session.name.clone();
+
+        self.world.check_exit(&from, dir)?;
+        let to = self.world.locations[&from].exits[&dir].to.clone();
+        if let Some(guard) = self.blocking_guard(&from, dir) {
+            return Err(format!("{guard} blocks your way."));
+        }
+        let to = self.instance_destination(addr, &from, &to)?;
+
+        self.broadcast_location(&from, &format!("{name} leaves {}.", dir.name()), &[*addr]);
+        self.peers
+            .get_mut(addr)
+            .ok_or("No session for address")?
+            .location = to.clone();
+        self.broadcast_location(&to, &format!("{name} arrives."), &[*addr]);
+
+        self.look(addr, &[])?;
+        self.npcs_greet(addr);
+        self.quest_reach(addr);
+        self.fire_enter(addr);
+        self.move_followers(addr, &from, dir);
+        Ok(())
+    }
+}